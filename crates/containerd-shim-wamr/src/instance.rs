@@ -59,8 +59,31 @@ impl Sandbox for WamrSandbox {
 
         log::info!("Create a WASI context");
 
+        // WAMR has no notion of read-only preopens, read-only mounts are only
+        // enforced by the mount itself.
+        let mut dirs = vec![];
+        let mut map_dirs = vec![];
+        for preopen in ctx.preopens() {
+            if preopen.readonly {
+                log::warn!(
+                    "read-only preopen {:?} is not enforced by WAMR",
+                    preopen.guest_path
+                );
+            }
+            let host = preopen.host_path.to_string_lossy().into_owned();
+            let guest = preopen.guest_path.to_string_lossy().into_owned();
+            if host == guest {
+                dirs.push(host);
+            } else {
+                map_dirs.push(format!("{guest}::{host}"));
+            }
+        }
+
         let wasi_ctx = WasiCtxBuilder::new()
-            .set_pre_open_path(vec!["/"], vec![])
+            .set_pre_open_path(
+                dirs.iter().map(String::as_str).collect(),
+                map_dirs.iter().map(String::as_str).collect(),
+            )
             .set_env_vars(envs.iter().map(String::as_str).collect())
             .set_arguments(args.iter().map(String::as_str).collect())
             .build();
@@ -69,6 +92,12 @@ impl Sandbox for WamrSandbox {
 
         // TODO: no way to register a named module with bytes?
 
+        // The WAMR SDK doesn't allow limiting the size of the linear memory, so the
+        // memory limit is only enforced by the container cgroup.
+        if let Some(limit) = ctx.resources().memory_limit {
+            log::warn!("memory limit of {limit} bytes is not enforced by WAMR");
+        }
+
         log::info!("Create a WAMR instance");
 
         let instance = WamrInst::new(&self.runtime, &module, 1024 * 64)
@@ -0,0 +1,38 @@
+(module
+    ;; A module that can be checkpointed while it runs, and resumed from the checkpoint.
+    ;; `_start` prepares its state at runtime, so that it only exists in the snapshot, and then spins forever.
+    ;; `runwasi_resume` prints the message from the restored memory through the restored table.
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+    (memory (export "memory") 1)
+    (global $ready (export "ready") (mut i32) (i32.const 0))
+    (table $table (export "table") 2 funcref)
+    (elem (table $table) (i32.const 1) func $print)
+    (type $print (func))
+
+    (func $print
+        ;; iov.iov_base and iov.iov_len of the 'restored\n' string
+        (i32.store (i32.const 0) (i32.const 8))
+        (i32.store (i32.const 4) (i32.const 9))
+        (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20)))
+    )
+
+    (func (export "_start")
+        ;; Write 'restored\n' to memory at an offset of 8 bytes
+        (i64.store (i32.const 8) (i64.const 0x6465726f74736572))
+        (i32.store8 (i32.const 16) (i32.const 10))
+        (table.set $table (i32.const 0) (table.get $table (i32.const 1)))
+        (global.set $ready (i32.const 1))
+        (loop $loop
+            (br $loop)
+        )
+    )
+
+    (func (export "runwasi_resume")
+        (if (i32.eqz (global.get $ready))
+            (then (call $proc_exit (i32.const 1)))
+        )
+        (call_indirect (type $print) (i32.const 0))
+    )
+)
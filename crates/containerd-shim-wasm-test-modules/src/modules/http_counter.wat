@@ -0,0 +1,343 @@
+;; A `wasi:http/proxy` component for testing the HTTP proxy of the shims.
+;;
+;; It responds to:
+;; * `/slow` with `slow\n`, after sleeping for a second
+;; * `/trap` by trapping
+;; * any other path with the number of requests handled by the instance, e.g., `3\n`
+(component $C
+  (import "wasi:io/poll@0.2.0" (instance $poll
+    (export $pollable "pollable" (type (sub resource)))
+    (export "[method]pollable.block" (func (param "self" (borrow $pollable))))
+  ))
+  (alias export $poll "pollable" (type $pollable))
+
+  (import "wasi:clocks/monotonic-clock@0.2.0" (instance $clock
+    (alias outer $C $pollable (type $pollable))
+    (export $pollable' "pollable" (type (eq $pollable)))
+    (export "subscribe-duration" (func (param "when" u64) (result (own $pollable'))))
+  ))
+
+  (import "wasi:io/error@0.2.0" (instance $error
+    (export "error" (type (sub resource)))
+  ))
+  (alias export $error "error" (type $error))
+
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export $output-stream "output-stream" (type (sub resource)))
+    (alias outer $C $error (type $error))
+    (export $error' "error" (type (eq $error)))
+    (type $stream-error-t
+      (variant (case "last-operation-failed" (own $error')) (case "closed")))
+    (export $stream-error "stream-error" (type (eq $stream-error-t)))
+    (export "[method]output-stream.blocking-write-and-flush"
+      (func (param "self" (borrow $output-stream)) (param "contents" (list u8))
+        (result (result (error $stream-error)))))
+  ))
+  (alias export $streams "output-stream" (type $output-stream))
+
+  (import "wasi:http/types@0.2.0" (instance $types
+    (export $fields "fields" (type (sub resource)))
+    (export $incoming-request "incoming-request" (type (sub resource)))
+    (export $response-outparam "response-outparam" (type (sub resource)))
+    (export $outgoing-response "outgoing-response" (type (sub resource)))
+    (export $outgoing-body "outgoing-body" (type (sub resource)))
+    (type $dns-error-payload-t
+      (record (field "rcode" (option string)) (field "info-code" (option u16))))
+    (export $dns-error-payload "DNS-error-payload" (type (eq $dns-error-payload-t)))
+    (type $tls-alert-received-payload-t
+      (record (field "alert-id" (option u8)) (field "alert-message" (option string))))
+    (export $tls-alert-received-payload "TLS-alert-received-payload" (type (eq $tls-alert-received-payload-t)))
+    (type $field-size-payload-t
+      (record (field "field-name" (option string)) (field "field-size" (option u32))))
+    (export $field-size-payload "field-size-payload" (type (eq $field-size-payload-t)))
+    (type $error-code-t
+      (variant
+        (case "DNS-timeout")
+        (case "DNS-error" $dns-error-payload)
+        (case "destination-not-found")
+        (case "destination-unavailable")
+        (case "destination-IP-prohibited")
+        (case "destination-IP-unroutable")
+        (case "connection-refused")
+        (case "connection-terminated")
+        (case "connection-timeout")
+        (case "connection-read-timeout")
+        (case "connection-write-timeout")
+        (case "connection-limit-reached")
+        (case "TLS-protocol-error")
+        (case "TLS-certificate-error")
+        (case "TLS-alert-received" $tls-alert-received-payload)
+        (case "HTTP-request-denied")
+        (case "HTTP-request-length-required")
+        (case "HTTP-request-body-size" (option u64))
+        (case "HTTP-request-method-invalid")
+        (case "HTTP-request-URI-invalid")
+        (case "HTTP-request-URI-too-long")
+        (case "HTTP-request-header-section-size" (option u32))
+        (case "HTTP-request-header-size" (option $field-size-payload))
+        (case "HTTP-request-trailer-section-size" (option u32))
+        (case "HTTP-request-trailer-size" $field-size-payload)
+        (case "HTTP-response-incomplete")
+        (case "HTTP-response-header-section-size" (option u32))
+        (case "HTTP-response-header-size" $field-size-payload)
+        (case "HTTP-response-body-size" (option u64))
+        (case "HTTP-response-trailer-section-size" (option u32))
+        (case "HTTP-response-trailer-size" $field-size-payload)
+        (case "HTTP-response-transfer-coding" (option string))
+        (case "HTTP-response-content-coding" (option string))
+        (case "HTTP-response-timeout")
+        (case "HTTP-upgrade-failed")
+        (case "HTTP-protocol-error")
+        (case "loop-detected")
+        (case "configuration-error")
+        (case "internal-error" (option string))))
+    (export $error-code "error-code" (type (eq $error-code-t)))
+    (export $headers "headers" (type (eq $fields)))
+    (export $trailers "trailers" (type (eq $fields)))
+    (alias outer $C $output-stream (type $output-stream))
+    (export $output-stream' "output-stream" (type (eq $output-stream)))
+    (export "[constructor]fields" (func (result (own $fields))))
+    (export "[method]incoming-request.path-with-query"
+      (func (param "self" (borrow $incoming-request)) (result (option string))))
+    (export "[static]response-outparam.set"
+      (func (param "param" (own $response-outparam))
+        (param "response" (result (own $outgoing-response) (error $error-code)))))
+    (export "[constructor]outgoing-response"
+      (func (param "headers" (own $headers)) (result (own $outgoing-response))))
+    (export "[method]outgoing-response.body"
+      (func (param "self" (borrow $outgoing-response)) (result (result (own $outgoing-body)))))
+    (export "[method]outgoing-body.write"
+      (func (param "self" (borrow $outgoing-body)) (result (result (own $output-stream')))))
+    (export "[static]outgoing-body.finish"
+      (func (param "this" (own $outgoing-body)) (param "trailers" (option (own $trailers)))
+        (result (result (error $error-code)))))
+  ))
+  (alias export $types "incoming-request" (type $incoming-request))
+  (alias export $types "response-outparam" (type $response-outparam))
+
+  (core module $Main
+    (import "host" "pollable.block" (func $pollable.block (param i32)))
+    (import "host" "pollable.drop" (func $pollable.drop (param i32)))
+    (import "host" "subscribe-duration" (func $subscribe-duration (param i64) (result i32)))
+    (import "host" "output-stream.blocking-write-and-flush"
+      (func $blocking-write-and-flush (param i32 i32 i32 i32)))
+    (import "host" "output-stream.drop" (func $output-stream.drop (param i32)))
+    (import "host" "fields" (func $fields (result i32)))
+    (import "host" "incoming-request.path-with-query" (func $path-with-query (param i32 i32)))
+    (import "host" "incoming-request.drop" (func $incoming-request.drop (param i32)))
+    (import "host" "response-outparam.set"
+      (func $response-outparam.set (param i32 i32 i32 i32 i64 i32 i32 i32 i32)))
+    (import "host" "outgoing-response" (func $outgoing-response (param i32) (result i32)))
+    (import "host" "outgoing-response.body" (func $outgoing-response.body (param i32 i32)))
+    (import "host" "outgoing-body.write" (func $outgoing-body.write (param i32 i32)))
+    (import "host" "outgoing-body.finish" (func $outgoing-body.finish (param i32 i32 i32 i32)))
+
+    (memory (export "memory") 1)
+
+    ;; 0..16: return area
+    ;; 16..32: response body
+    ;; 32..: heap for the strings returned by the host, reset on every request
+    (global $heap (mut i32) (i32.const 32))
+    ;; the number of requests handled by this instance
+    (global $count (mut i32) (i32.const 0))
+
+    (data (i32.const 64) "/slow")
+    (data (i32.const 72) "/trap")
+    (data (i32.const 80) "slow\n")
+
+    (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      ;; align the allocation
+      (local.set $ptr
+        (i32.and
+          (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+          (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $ptr) (local.get 3)))
+      (if (i32.gt_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536)))
+        (then (drop (memory.grow (i32.const 1)))))
+      (local.get $ptr))
+
+    ;; whether the `len` bytes at `a` and `b` are equal
+    (func $eq (param $a i32) (param $b i32) (param $len i32) (result i32)
+      (block $done
+        (loop $next
+          (br_if $done (i32.eqz (local.get $len)))
+          (if (i32.ne (i32.load8_u (local.get $a)) (i32.load8_u (local.get $b)))
+            (then (return (i32.const 0))))
+          (local.set $a (i32.add (local.get $a) (i32.const 1)))
+          (local.set $b (i32.add (local.get $b) (i32.const 1)))
+          (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+          (br $next)))
+      (i32.const 1))
+
+    ;; writes `n` and a newline at the end of the body buffer, returns the offset of the first digit
+    (func $itoa (param $n i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (i32.const 31))
+      (i32.store8 (local.get $ptr) (i32.const 10))
+      (loop $digit
+        (local.set $ptr (i32.sub (local.get $ptr) (i32.const 1)))
+        (i32.store8 (local.get $ptr)
+          (i32.add (i32.const 48) (i32.rem_u (local.get $n) (i32.const 10))))
+        (local.set $n (i32.div_u (local.get $n) (i32.const 10)))
+        (br_if $digit (local.get $n)))
+      (local.get $ptr))
+
+    (func (export "handle") (param $request i32) (param $response-out i32)
+      (local $path i32)
+      (local $path-len i32)
+      (local $body-ptr i32)
+      (local $body-len i32)
+      (local $response i32)
+      (local $body i32)
+      (local $stream i32)
+      (local $pollable i32)
+      (global.set $heap (i32.const 32))
+      (global.set $count (i32.add (global.get $count) (i32.const 1)))
+
+      (call $path-with-query (local.get $request) (i32.const 0))
+      (if (i32.load8_u (i32.const 0))
+        (then
+          (local.set $path (i32.load (i32.const 4)))
+          (local.set $path-len (i32.load (i32.const 8)))))
+      (call $incoming-request.drop (local.get $request))
+
+      (if (i32.and
+            (i32.eq (local.get $path-len) (i32.const 5))
+            (call $eq (local.get $path) (i32.const 72) (i32.const 5)))
+        (then unreachable))
+
+      (if (i32.and
+            (i32.eq (local.get $path-len) (i32.const 5))
+            (call $eq (local.get $path) (i32.const 64) (i32.const 5)))
+        (then
+          (local.set $pollable (call $subscribe-duration (i64.const 1000000000)))
+          (call $pollable.block (local.get $pollable))
+          (call $pollable.drop (local.get $pollable))
+          (local.set $body-ptr (i32.const 80))
+          (local.set $body-len (i32.const 5)))
+        (else
+          (local.set $body-ptr (call $itoa (global.get $count)))
+          (local.set $body-len (i32.sub (i32.const 32) (local.get $body-ptr)))))
+
+      (local.set $response (call $outgoing-response (call $fields)))
+      (call $outgoing-response.body (local.get $response) (i32.const 0))
+      (local.set $body (i32.load (i32.const 4)))
+      (call $response-outparam.set
+        (local.get $response-out)
+        (i32.const 0) (local.get $response)
+        (i32.const 0) (i64.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))
+
+      (call $outgoing-body.write (local.get $body) (i32.const 0))
+      (local.set $stream (i32.load (i32.const 4)))
+      (call $blocking-write-and-flush
+        (local.get $stream) (local.get $body-ptr) (local.get $body-len) (i32.const 0))
+      (call $output-stream.drop (local.get $stream))
+      (call $outgoing-body.finish (local.get $body) (i32.const 0) (i32.const 0) (i32.const 0)))
+  )
+
+  (core func $pollable.block (canon lower (func $poll "[method]pollable.block")))
+  (core func $pollable.drop (canon resource.drop $pollable))
+  (core func $subscribe-duration (canon lower (func $clock "subscribe-duration")))
+  (core func $output-stream.drop (canon resource.drop $output-stream))
+  (core func $fields (canon lower (func $types "[constructor]fields")))
+  (core func $incoming-request.drop (canon resource.drop $incoming-request))
+  (core func $outgoing-response (canon lower (func $types "[constructor]outgoing-response")))
+
+  ;; the functions that return values in memory need the memory of the main module,
+  ;; so they're instantiated with placeholders that are patched through a table
+  (core module $Indirect
+    (type $i32x2 (func (param i32 i32)))
+    (type $i32x4 (func (param i32 i32 i32 i32)))
+    (type $set (func (param i32 i32 i32 i32 i64 i32 i32 i32 i32)))
+    (table (export "$imports") 6 6 funcref)
+    (func (export "output-stream.blocking-write-and-flush") (type $i32x4)
+      (call_indirect (type $i32x4)
+        (local.get 0) (local.get 1) (local.get 2) (local.get 3) (i32.const 0)))
+    (func (export "incoming-request.path-with-query") (type $i32x2)
+      (call_indirect (type $i32x2) (local.get 0) (local.get 1) (i32.const 1)))
+    (func (export "response-outparam.set") (type $set)
+      (call_indirect (type $set)
+        (local.get 0) (local.get 1) (local.get 2) (local.get 3) (local.get 4)
+        (local.get 5) (local.get 6) (local.get 7) (local.get 8) (i32.const 2)))
+    (func (export "outgoing-response.body") (type $i32x2)
+      (call_indirect (type $i32x2) (local.get 0) (local.get 1) (i32.const 3)))
+    (func (export "outgoing-body.write") (type $i32x2)
+      (call_indirect (type $i32x2) (local.get 0) (local.get 1) (i32.const 4)))
+    (func (export "outgoing-body.finish") (type $i32x4)
+      (call_indirect (type $i32x4)
+        (local.get 0) (local.get 1) (local.get 2) (local.get 3) (i32.const 5)))
+  )
+  (core instance $indirect (instantiate $Indirect))
+
+  (core instance $main (instantiate $Main
+    (with "host" (instance
+      (export "pollable.block" (func $pollable.block))
+      (export "pollable.drop" (func $pollable.drop))
+      (export "subscribe-duration" (func $subscribe-duration))
+      (export "output-stream.blocking-write-and-flush"
+        (func $indirect "output-stream.blocking-write-and-flush"))
+      (export "output-stream.drop" (func $output-stream.drop))
+      (export "fields" (func $fields))
+      (export "incoming-request.path-with-query" (func $indirect "incoming-request.path-with-query"))
+      (export "incoming-request.drop" (func $incoming-request.drop))
+      (export "response-outparam.set" (func $indirect "response-outparam.set"))
+      (export "outgoing-response" (func $outgoing-response))
+      (export "outgoing-response.body" (func $indirect "outgoing-response.body"))
+      (export "outgoing-body.write" (func $indirect "outgoing-body.write"))
+      (export "outgoing-body.finish" (func $indirect "outgoing-body.finish"))
+    ))
+  ))
+
+  (alias core export $main "memory" (core memory $memory))
+  (alias core export $main "cabi_realloc" (core func $realloc))
+
+  (core func $blocking-write-and-flush'
+    (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $memory)))
+  (core func $path-with-query'
+    (canon lower (func $types "[method]incoming-request.path-with-query")
+      (memory $memory) (realloc $realloc) string-encoding=utf8))
+  (core func $response-outparam.set'
+    (canon lower (func $types "[static]response-outparam.set") (memory $memory) string-encoding=utf8))
+  (core func $outgoing-response.body'
+    (canon lower (func $types "[method]outgoing-response.body") (memory $memory)))
+  (core func $outgoing-body.write'
+    (canon lower (func $types "[method]outgoing-body.write") (memory $memory)))
+  (core func $outgoing-body.finish'
+    (canon lower (func $types "[static]outgoing-body.finish")
+      (memory $memory) (realloc $realloc) string-encoding=utf8))
+
+  (core module $Fixup
+    (type $i32x2 (func (param i32 i32)))
+    (type $i32x4 (func (param i32 i32 i32 i32)))
+    (type $set (func (param i32 i32 i32 i32 i64 i32 i32 i32 i32)))
+    (import "" "$imports" (table 6 6 funcref))
+    (import "" "0" (func $f0 (type $i32x4)))
+    (import "" "1" (func $f1 (type $i32x2)))
+    (import "" "2" (func $f2 (type $set)))
+    (import "" "3" (func $f3 (type $i32x2)))
+    (import "" "4" (func $f4 (type $i32x2)))
+    (import "" "5" (func $f5 (type $i32x4)))
+    (elem (i32.const 0) func $f0 $f1 $f2 $f3 $f4 $f5)
+  )
+  (core instance (instantiate $Fixup
+    (with "" (instance
+      (export "$imports" (table $indirect "$imports"))
+      (export "0" (func $blocking-write-and-flush'))
+      (export "1" (func $path-with-query'))
+      (export "2" (func $response-outparam.set'))
+      (export "3" (func $outgoing-response.body'))
+      (export "4" (func $outgoing-body.write'))
+      (export "5" (func $outgoing-body.finish'))
+    ))
+  ))
+
+  (func $handle
+    (param "request" (own $incoming-request))
+    (param "response-out" (own $response-outparam))
+    (canon lift (core func $main "handle")))
+
+  (instance $incoming-handler
+    (export "handle" (func $handle)))
+  (export "wasi:http/incoming-handler@0.2.0" (instance $incoming-handler))
+)
@@ -0,0 +1,5 @@
+(func $main (export "_start")
+    (loop $loop
+        (br $loop)
+    )
+)
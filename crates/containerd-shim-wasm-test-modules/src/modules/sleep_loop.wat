@@ -0,0 +1,30 @@
+(module
+    ;; Import the poll_oneoff WASI function, used to sleep
+    ;; The function signature for poll_oneoff is:
+    ;; (*subscriptions, *events, nsubscriptions, *nevents) -> Returns an errno
+    (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+    (memory 1)
+    (export "memory" (memory 0))
+
+    (func $main (export "_start")
+        ;; A subscription to the monotonic clock, at memory location 0
+        (i32.store8 (i32.const 8) (i32.const 0))  ;; tag - 0 for a clock subscription
+        (i32.store (i32.const 16) (i32.const 1))  ;; clock id - 1 for the monotonic clock
+        (i64.store (i32.const 24) (i64.const 10000000))  ;; timeout - 10ms, relative to now
+        (i64.store (i32.const 32) (i64.const 0))  ;; precision
+        (i32.store16 (i32.const 40) (i32.const 0))  ;; flags - 0 for a relative timeout
+
+        ;; Sleep forever, 10ms at a time
+        (loop $loop
+            (call $poll_oneoff
+                (i32.const 0) ;; *subscriptions - The subscription at memory location 0
+                (i32.const 64) ;; *events - A place in memory to store the event
+                (i32.const 1) ;; nsubscriptions - Just the one subscription
+                (i32.const 96) ;; *nevents - A place in memory to store the number of events
+            )
+            drop ;; Discard the errno
+            (br $loop)
+        )
+    )
+)
@@ -0,0 +1,317 @@
+;; A `wasi:cli/command` component for testing the network policy of the shims.
+;;
+;; It connects to `127.0.0.1:8081` and `127.0.0.1:8082`, and prints the outcome of each, e.g.,
+;; `8081 connected\n` and `8082 denied\n`, where `denied` means the host refused the address.
+(component $C
+  (import "wasi:io/poll@0.2.0" (instance $poll
+    (export $pollable "pollable" (type (sub resource)))
+    (export "[method]pollable.block" (func (param "self" (borrow $pollable))))
+  ))
+  (alias export $poll "pollable" (type $pollable))
+
+  (import "wasi:io/error@0.2.0" (instance $error
+    (export "error" (type (sub resource)))
+  ))
+  (alias export $error "error" (type $error))
+
+  (import "wasi:io/streams@0.2.0" (instance $streams
+    (export $input-stream "input-stream" (type (sub resource)))
+    (export $output-stream "output-stream" (type (sub resource)))
+    (alias outer $C $error (type $error))
+    (export $error' "error" (type (eq $error)))
+    (type $stream-error-t
+      (variant (case "last-operation-failed" (own $error')) (case "closed")))
+    (export $stream-error "stream-error" (type (eq $stream-error-t)))
+    (export "[method]output-stream.blocking-write-and-flush"
+      (func (param "self" (borrow $output-stream)) (param "contents" (list u8))
+        (result (result (error $stream-error)))))
+  ))
+  (alias export $streams "input-stream" (type $input-stream))
+  (alias export $streams "output-stream" (type $output-stream))
+
+  (import "wasi:cli/stdout@0.2.0" (instance $stdout
+    (alias outer $C $output-stream (type $output-stream))
+    (export $output-stream' "output-stream" (type (eq $output-stream)))
+    (export "get-stdout" (func (result (own $output-stream'))))
+  ))
+
+  (import "wasi:sockets/network@0.2.0" (instance $network
+    (export "network" (type (sub resource)))
+    (type $error-code-t
+      (enum
+        "unknown"
+        "access-denied"
+        "not-supported"
+        "invalid-argument"
+        "out-of-memory"
+        "timeout"
+        "concurrency-conflict"
+        "not-in-progress"
+        "would-block"
+        "invalid-state"
+        "new-socket-limit"
+        "address-not-bindable"
+        "address-in-use"
+        "remote-unreachable"
+        "connection-refused"
+        "connection-reset"
+        "connection-aborted"
+        "datagram-too-large"
+        "name-unresolvable"
+        "temporary-resolver-failure"
+        "permanent-resolver-failure"))
+    (export "error-code" (type (eq $error-code-t)))
+    (type $ip-address-family-t (enum "ipv4" "ipv6"))
+    (export "ip-address-family" (type (eq $ip-address-family-t)))
+    (type $ipv4-address-t (tuple u8 u8 u8 u8))
+    (export $ipv4-address "ipv4-address" (type (eq $ipv4-address-t)))
+    (type $ipv6-address-t (tuple u16 u16 u16 u16 u16 u16 u16 u16))
+    (export $ipv6-address "ipv6-address" (type (eq $ipv6-address-t)))
+    (type $ipv4-socket-address-t
+      (record (field "port" u16) (field "address" $ipv4-address)))
+    (export $ipv4-socket-address "ipv4-socket-address" (type (eq $ipv4-socket-address-t)))
+    (type $ipv6-socket-address-t
+      (record
+        (field "port" u16)
+        (field "flow-info" u32)
+        (field "address" $ipv6-address)
+        (field "scope-id" u32)))
+    (export $ipv6-socket-address "ipv6-socket-address" (type (eq $ipv6-socket-address-t)))
+    (type $ip-socket-address-t
+      (variant (case "ipv4" $ipv4-socket-address) (case "ipv6" $ipv6-socket-address)))
+    (export "ip-socket-address" (type (eq $ip-socket-address-t)))
+  ))
+  (alias export $network "network" (type $network))
+  (alias export $network "error-code" (type $error-code))
+  (alias export $network "ip-address-family" (type $ip-address-family))
+  (alias export $network "ip-socket-address" (type $ip-socket-address))
+
+  (import "wasi:sockets/instance-network@0.2.0" (instance $instance-network
+    (alias outer $C $network (type $network))
+    (export $network' "network" (type (eq $network)))
+    (export "instance-network" (func (result (own $network'))))
+  ))
+
+  (import "wasi:sockets/tcp@0.2.0" (instance $tcp
+    (alias outer $C $pollable (type $pollable))
+    (export $pollable' "pollable" (type (eq $pollable)))
+    (alias outer $C $input-stream (type $input-stream))
+    (export $input-stream' "input-stream" (type (eq $input-stream)))
+    (alias outer $C $output-stream (type $output-stream))
+    (export $output-stream' "output-stream" (type (eq $output-stream)))
+    (alias outer $C $network (type $network))
+    (export $network' "network" (type (eq $network)))
+    (alias outer $C $error-code (type $error-code))
+    (export $error-code' "error-code" (type (eq $error-code)))
+    (alias outer $C $ip-socket-address (type $ip-socket-address))
+    (export $ip-socket-address' "ip-socket-address" (type (eq $ip-socket-address)))
+    (export $tcp-socket "tcp-socket" (type (sub resource)))
+    (export "[method]tcp-socket.start-connect"
+      (func (param "self" (borrow $tcp-socket)) (param "network" (borrow $network'))
+        (param "remote-address" $ip-socket-address')
+        (result (result (error $error-code')))))
+    (type $streams-t (tuple (own $input-stream') (own $output-stream')))
+    (export "[method]tcp-socket.finish-connect"
+      (func (param "self" (borrow $tcp-socket))
+        (result (result $streams-t (error $error-code')))))
+    (export "[method]tcp-socket.subscribe"
+      (func (param "self" (borrow $tcp-socket)) (result (own $pollable'))))
+  ))
+  (alias export $tcp "tcp-socket" (type $tcp-socket))
+
+  (import "wasi:sockets/tcp-create-socket@0.2.0" (instance $tcp-create-socket
+    (alias outer $C $error-code (type $error-code))
+    (export $error-code' "error-code" (type (eq $error-code)))
+    (alias outer $C $ip-address-family (type $ip-address-family))
+    (export $ip-address-family' "ip-address-family" (type (eq $ip-address-family)))
+    (alias outer $C $tcp-socket (type $tcp-socket))
+    (export $tcp-socket' "tcp-socket" (type (eq $tcp-socket)))
+    (export "create-tcp-socket"
+      (func (param "address-family" $ip-address-family')
+        (result (result (own $tcp-socket') (error $error-code')))))
+  ))
+
+  (core module $Main
+    (type $start-connect
+      (func (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)))
+    (import "host" "pollable.block" (func $pollable.block (param i32)))
+    (import "host" "pollable.drop" (func $pollable.drop (param i32)))
+    (import "host" "input-stream.drop" (func $input-stream.drop (param i32)))
+    (import "host" "output-stream.blocking-write-and-flush"
+      (func $blocking-write-and-flush (param i32 i32 i32 i32)))
+    (import "host" "output-stream.drop" (func $output-stream.drop (param i32)))
+    (import "host" "get-stdout" (func $get-stdout (result i32)))
+    (import "host" "instance-network" (func $instance-network (result i32)))
+    (import "host" "network.drop" (func $network.drop (param i32)))
+    (import "host" "create-tcp-socket" (func $create-tcp-socket (param i32 i32)))
+    (import "host" "tcp-socket.start-connect" (func $start-connect (type $start-connect)))
+    (import "host" "tcp-socket.finish-connect" (func $finish-connect (param i32 i32)))
+    (import "host" "tcp-socket.subscribe" (func $subscribe (param i32) (result i32)))
+    (import "host" "tcp-socket.drop" (func $tcp-socket.drop (param i32)))
+
+    (memory (export "memory") 1)
+
+    ;; 0..16: return area
+    (data (i32.const 64) "8081 ")
+    (data (i32.const 72) "8082 ")
+    (data (i32.const 80) "connected\n")
+    (data (i32.const 96) "denied\n")
+    (data (i32.const 112) "failed\n")
+
+    ;; connects to `127.0.0.1:port`, returns 0 if connected, 1 if denied, and 2 if failed
+    (func $connect (param $network i32) (param $port i32) (result i32)
+      (local $socket i32)
+      (local $pollable i32)
+      (local $status i32)
+      ;; ipv4
+      (call $create-tcp-socket (i32.const 0) (i32.const 0))
+      (if (i32.load8_u (i32.const 0))
+        (then (return (i32.const 2))))
+      (local.set $socket (i32.load (i32.const 4)))
+
+      (call $start-connect
+        (local.get $socket) (local.get $network)
+        (i32.const 0) (local.get $port) (i32.const 127) (i32.const 0) (i32.const 0) (i32.const 1)
+        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+        (i32.const 0))
+      (if (i32.load8_u (i32.const 0))
+        (then
+          ;; access-denied
+          (local.set $status
+            (select (i32.const 1) (i32.const 2) (i32.eq (i32.load8_u (i32.const 1)) (i32.const 1))))
+          (call $tcp-socket.drop (local.get $socket))
+          (return (local.get $status))))
+
+      (local.set $status (i32.const 2))
+      (block $done
+        (loop $wait
+          (local.set $pollable (call $subscribe (local.get $socket)))
+          (call $pollable.block (local.get $pollable))
+          (call $pollable.drop (local.get $pollable))
+          (call $finish-connect (local.get $socket) (i32.const 0))
+          (if (i32.eqz (i32.load8_u (i32.const 0)))
+            (then
+              ;; the streams are children of the socket, so they're dropped first
+              (call $input-stream.drop (i32.load (i32.const 4)))
+              (call $output-stream.drop (i32.load (i32.const 8)))
+              (local.set $status (i32.const 0))
+              (br $done)))
+          ;; would-block
+          (br_if $wait (i32.eq (i32.load8_u (i32.const 4)) (i32.const 8)))))
+      (call $tcp-socket.drop (local.get $socket))
+      (local.get $status))
+
+    (func $print (param $stdout i32) (param $prefix i32) (param $status i32)
+      (call $blocking-write-and-flush (local.get $stdout) (local.get $prefix) (i32.const 5) (i32.const 0))
+      (call $blocking-write-and-flush
+        (local.get $stdout)
+        (i32.add (i32.const 80) (i32.mul (local.get $status) (i32.const 16)))
+        (select (i32.const 10) (i32.const 7) (i32.eqz (local.get $status)))
+        (i32.const 0)))
+
+    (func (export "run") (result i32)
+      (local $stdout i32)
+      (local $network i32)
+      (local.set $stdout (call $get-stdout))
+      (local.set $network (call $instance-network))
+      (call $print (local.get $stdout) (i32.const 64)
+        (call $connect (local.get $network) (i32.const 8081)))
+      (call $print (local.get $stdout) (i32.const 72)
+        (call $connect (local.get $network) (i32.const 8082)))
+      (call $network.drop (local.get $network))
+      (call $output-stream.drop (local.get $stdout))
+      (i32.const 0))
+  )
+
+  (core func $pollable.block (canon lower (func $poll "[method]pollable.block")))
+  (core func $pollable.drop (canon resource.drop $pollable))
+  (core func $input-stream.drop (canon resource.drop $input-stream))
+  (core func $output-stream.drop (canon resource.drop $output-stream))
+  (core func $get-stdout (canon lower (func $stdout "get-stdout")))
+  (core func $instance-network (canon lower (func $instance-network "instance-network")))
+  (core func $network.drop (canon resource.drop $network))
+  (core func $subscribe (canon lower (func $tcp "[method]tcp-socket.subscribe")))
+  (core func $tcp-socket.drop (canon resource.drop $tcp-socket))
+
+  ;; the functions that return values in memory need the memory of the main module,
+  ;; so they're instantiated with placeholders that are patched through a table
+  (core module $Indirect
+    (type $i32x2 (func (param i32 i32)))
+    (type $i32x4 (func (param i32 i32 i32 i32)))
+    (type $start-connect
+      (func (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)))
+    (table (export "$imports") 4 4 funcref)
+    (func (export "output-stream.blocking-write-and-flush") (type $i32x4)
+      (call_indirect (type $i32x4)
+        (local.get 0) (local.get 1) (local.get 2) (local.get 3) (i32.const 0)))
+    (func (export "create-tcp-socket") (type $i32x2)
+      (call_indirect (type $i32x2) (local.get 0) (local.get 1) (i32.const 1)))
+    (func (export "tcp-socket.start-connect") (type $start-connect)
+      (call_indirect (type $start-connect)
+        (local.get 0) (local.get 1) (local.get 2) (local.get 3) (local.get 4)
+        (local.get 5) (local.get 6) (local.get 7) (local.get 8) (local.get 9)
+        (local.get 10) (local.get 11) (local.get 12) (local.get 13) (local.get 14)
+        (i32.const 2)))
+    (func (export "tcp-socket.finish-connect") (type $i32x2)
+      (call_indirect (type $i32x2) (local.get 0) (local.get 1) (i32.const 3)))
+  )
+  (core instance $indirect (instantiate $Indirect))
+
+  (core instance $main (instantiate $Main
+    (with "host" (instance
+      (export "pollable.block" (func $pollable.block))
+      (export "pollable.drop" (func $pollable.drop))
+      (export "input-stream.drop" (func $input-stream.drop))
+      (export "output-stream.blocking-write-and-flush"
+        (func $indirect "output-stream.blocking-write-and-flush"))
+      (export "output-stream.drop" (func $output-stream.drop))
+      (export "get-stdout" (func $get-stdout))
+      (export "instance-network" (func $instance-network))
+      (export "network.drop" (func $network.drop))
+      (export "create-tcp-socket" (func $indirect "create-tcp-socket"))
+      (export "tcp-socket.start-connect" (func $indirect "tcp-socket.start-connect"))
+      (export "tcp-socket.finish-connect" (func $indirect "tcp-socket.finish-connect"))
+      (export "tcp-socket.subscribe" (func $subscribe))
+      (export "tcp-socket.drop" (func $tcp-socket.drop))
+    ))
+  ))
+
+  (alias core export $main "memory" (core memory $memory))
+
+  (core func $blocking-write-and-flush'
+    (canon lower (func $streams "[method]output-stream.blocking-write-and-flush") (memory $memory)))
+  (core func $create-tcp-socket'
+    (canon lower (func $tcp-create-socket "create-tcp-socket") (memory $memory)))
+  (core func $start-connect'
+    (canon lower (func $tcp "[method]tcp-socket.start-connect") (memory $memory)))
+  (core func $finish-connect'
+    (canon lower (func $tcp "[method]tcp-socket.finish-connect") (memory $memory)))
+
+  (core module $Fixup
+    (type $i32x2 (func (param i32 i32)))
+    (type $i32x4 (func (param i32 i32 i32 i32)))
+    (type $start-connect
+      (func (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32 i32)))
+    (import "" "$imports" (table 4 4 funcref))
+    (import "" "0" (func $f0 (type $i32x4)))
+    (import "" "1" (func $f1 (type $i32x2)))
+    (import "" "2" (func $f2 (type $start-connect)))
+    (import "" "3" (func $f3 (type $i32x2)))
+    (elem (i32.const 0) func $f0 $f1 $f2 $f3)
+  )
+  (core instance (instantiate $Fixup
+    (with "" (instance
+      (export "$imports" (table $indirect "$imports"))
+      (export "0" (func $blocking-write-and-flush'))
+      (export "1" (func $create-tcp-socket'))
+      (export "2" (func $start-connect'))
+      (export "3" (func $finish-connect'))
+    ))
+  ))
+
+  (func $run (result (result)) (canon lift (core func $main "run")))
+
+  (instance $run-instance
+    (export "run" (func $run)))
+  (export "wasi:cli/run@0.2.0" (instance $run-instance))
+)
@@ -20,6 +20,7 @@ use containerd_client::{tonic, with_namespace};
 use containerd_shimkit::sandbox::error::{Error as ShimError, Result};
 use futures::TryStreamExt;
 use oci_spec::image::{Arch, Digest, ImageManifest, MediaType, Platform};
+use serde::Deserialize;
 use sha256::digest;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
@@ -68,6 +69,14 @@ impl WriteContent {
     }
 }
 
+// The subset of the image configuration needed to extract the image labels.
+// Parsing the full `ImageConfiguration` would fail for images that omit
+// fields that are irrelevant to wasm images, like `rootfs`.
+#[derive(Deserialize)]
+struct ImageConfigLabels {
+    config: Option<oci_spec::image::Config>,
+}
+
 // sync wrapper implementation from https://tokio.rs/tokio/topics/bridging
 impl Client {
     // wrapper around connection that will establish a connection and create a client
@@ -394,7 +403,7 @@ impl Client {
         engine_name: impl AsRef<str> + Debug,
         supported_layer_types: &[&str],
         compiler: Option<&impl Compiler>,
-    ) -> Result<(Vec<WasmLayer>, Platform)> {
+    ) -> Result<(Vec<WasmLayer>, Platform, HashMap<String, String>)> {
         let container = self.get_container(containerd_id).await?;
         let (manifest, image_digest) = self.get_image_manifest_and_digest(&container.image).await?;
 
@@ -402,11 +411,15 @@ impl Client {
         let image_config = self.read_content(image_config_descriptor.digest()).await?;
         let image_config = image_config.as_slice();
 
-        // the only part we care about here is the platform values
+        // the only parts we care about here are the platform values and the labels
         let platform: Platform = serde_json::from_slice(image_config)?;
+        let labels = serde_json::from_slice::<ImageConfigLabels>(image_config)?
+            .config
+            .and_then(|config| config.labels().clone())
+            .unwrap_or_default();
         let Arch::Wasm = platform.architecture() else {
             log::info!("manifest is not in WASM OCI image format");
-            return Ok((vec![], platform));
+            return Ok((vec![], platform, labels));
         };
 
         log::info!("found manifest with WASM OCI image format");
@@ -421,7 +434,7 @@ impl Client {
 
         if configs.is_empty() {
             log::info!("no WASM layers found in OCI image");
-            return Ok((vec![], platform));
+            return Ok((vec![], platform, labels));
         }
 
         log::info!("using OCI layers");
@@ -432,7 +445,7 @@ impl Client {
                 let layer = self.read_original_layer(config).await?;
                 layers.push(layer);
             }
-            return Ok((layers, platform));
+            return Ok((layers, platform, labels));
         };
 
         let precompile_id = precompile_label(engine_name.as_ref(), compiler.cache_key());
@@ -470,7 +483,7 @@ impl Client {
                 }
                 Err(e) => {
                     log::error!("precompilation failed: {}", e);
-                    return Ok((layers, platform));
+                    return Ok((layers, platform, labels));
                 }
             };
 
@@ -534,11 +547,11 @@ impl Client {
 
                 let _ = precompiled_content.lease.release().await;
             }
-            return Ok((layers_for_runtime, platform));
+            return Ok((layers_for_runtime, platform, labels));
         };
 
         log::info!("using OCI layers");
-        Ok((layers, platform))
+        Ok((layers, platform, labels))
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
@@ -689,7 +702,7 @@ mod tests {
         let fake_bytes = generate_content("original", WASM_LAYER_MEDIA_TYPE);
         let (_, container_name, _cleanup) = generate_test_container(None, &[&fake_bytes]);
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
@@ -717,7 +730,7 @@ mod tests {
         let mut engine = FakePrecomipler::new();
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
 
-        let (_, _) = client
+        let (_, _, _) = client
             .load_modules(
                 &container_name,
                 "fake",
@@ -729,7 +742,7 @@ mod tests {
         assert_eq!(engine.precompile_called.load(Ordering::SeqCst), 1);
 
         // Even on second calls should only pre-compile once
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 &container_name,
                 "fake",
@@ -758,7 +771,7 @@ mod tests {
         let mut engine = FakePrecomipler::new();
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
 
-        let (_, _) = client
+        let (_, _, _) = client
             .load_modules(
                 &container_name,
                 "fake",
@@ -770,7 +783,7 @@ mod tests {
         assert_eq!(engine.precompile_called.load(Ordering::SeqCst), 1);
 
         engine.precompile_id = "new_version".to_string();
-        let (_, _) = client
+        let (_, _, _) = client
             .load_modules(
                 &container_name,
                 "fake",
@@ -798,7 +811,7 @@ mod tests {
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
         let expected_id = precompile_label("fake", &engine.cache_key());
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
@@ -842,7 +855,7 @@ mod tests {
         let mut engine = FakePrecomipler::new();
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
@@ -874,7 +887,7 @@ mod tests {
         let mut engine = FakePrecomipler::new();
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
@@ -908,7 +921,7 @@ mod tests {
         // and then check that the layers don't need to be recompiled
         oci_helpers::wait_for_content_removal(&image_sha).unwrap();
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name2,
                 "fake",
@@ -937,7 +950,7 @@ mod tests {
         let mut engine = FakePrecomipler::new();
         engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
@@ -956,7 +969,7 @@ mod tests {
         let fake_precompiled_bytes2 = generate_content("precompiled2", WASM_LAYER_MEDIA_TYPE);
         engine.add_precompiled_bits(fake_bytes2.bytes.clone(), &fake_precompiled_bytes2);
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name2,
                 "fake",
@@ -993,7 +1006,7 @@ mod tests {
 
         let expected_id = precompile_label("fake", &engine.cache_key());
 
-        let (layers, _) = client
+        let (layers, _, _) = client
             .load_modules(
                 container_name,
                 "fake",
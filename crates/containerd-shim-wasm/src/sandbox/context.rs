@@ -1,13 +1,17 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
 use anyhow::{Context, bail};
+use containerd_shimkit::sandbox::EngineOptions;
 use oci_spec::image::{Descriptor, Platform};
 use oci_spec::runtime::Spec;
 use serde::{Deserialize, Serialize};
 use wasmparser::Parser;
 
-use crate::sandbox::path::PathResolve;
+use crate::sandbox::path::{PathResolve, resolve_in_root};
 
 /// The `RuntimeContext` trait provides access to the runtime context that includes
 /// the arguments, environment variables, and entrypoint for the container.
@@ -44,10 +48,231 @@ pub trait RuntimeContext: Send + Sync {
     /// In Kubernetes environments, containers run within pods, and the pod ID is usually
     /// stored in the OCI spec annotations under "io.kubernetes.cri.sandbox-id"
     fn pod_id(&self) -> Option<&str> {
+        self.annotations()
+            .get(POD_ID_ANNOTATION)
+            .map(String::as_str)
+    }
+
+    /// Returns the annotations from the runtime spec.
+    /// Annotations are set per container, e.g., by Kubernetes from the pod annotations,
+    /// and can be used to tune the shim for a given workload.
+    fn annotations(&self) -> &HashMap<String, String>;
+
+    /// Returns the labels from the config of the image the container was created from.
+    /// This is empty if the image config could not be read, e.g., when the container
+    /// was not created from an image.
+    fn labels(&self) -> &HashMap<String, String> {
+        &NO_LABELS
+    }
+
+    /// Returns the directories that should be made visible to the guest, derived
+    /// from the container rootfs and the `mounts` in the OCI spec.
+    ///
+    /// The rootfs is the first entry, followed by the mounts in the order
+    /// they appear in the spec. Mounts that are not directories (e.g., a bind
+    /// mount of `/etc/hosts`) are skipped, as WASI can only preopen directories.
+    ///
+    /// The rootfs is read-only if it's read-only in the spec, and can be made read-only
+    /// or left out with the `runwasi.io/root-preopen` annotation (see [`RootPreopen`]).
+    ///
+    /// Defaults to preopening the whole root with full rights.
+    fn preopens(&self) -> Vec<Preopen> {
+        vec![Preopen {
+            host_path: PathBuf::from("/"),
+            guest_path: PathBuf::from("/"),
+            readonly: false,
+        }]
+    }
+
+    /// Returns the resource limits for the container from the `linux.resources`
+    /// field of the OCI spec.
+    ///
+    /// These limits are also enforced by the container cgroup, but shims should
+    /// translate the memory limit into engine limits, e.g., a maximum number of pages,
+    /// so that a guest exceeding it results in a trap rather than the whole process
+    /// being killed. The CPU quota only throttles the container, it never kills it,
+    /// so shims only use it to size their own work, e.g., the compilation threads.
+    ///
+    /// Defaults to no limits.
+    fn resources(&self) -> ResourceLimits {
+        ResourceLimits::default()
+    }
+
+    /// Returns the checkpoint to restore the container from, if any.
+    ///
+    /// This is the snapshot returned by [`Sandbox::checkpoint`](crate::sandbox::Sandbox::checkpoint)
+    /// when the container was checkpointed. It is only set for shims that
+    /// support checkpointing (see [`Shim::supports_checkpoint`](crate::shim::Shim::supports_checkpoint)).
+    fn checkpoint(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Returns the stdio of the container, if it's not the stdio of the current process.
+    ///
+    /// This is only set when the container runs inside the shim process (see
+    /// [`Shim::supports_in_process`](crate::shim::Shim::supports_in_process)),
+    /// in which case the shim must use these files instead of inheriting its own stdio.
+    fn stdio(&self) -> Option<&Stdio> {
+        None
+    }
+
+    /// Returns the engine specific options, from the `Engine` table of the runtime options
+    /// of the shim, e.g., in containerd's `config.toml`.
+    /// These are opaque to runwasi, shims can deserialize them into their own type
+    /// with [`EngineOptions::get`].
+    fn engine_options(&self) -> &EngineOptions {
+        &NO_ENGINE_OPTIONS
+    }
+
+    /// Returns the directory to write a core dump to when the guest traps.
+    ///
+    /// This is only set when the `CoreDumpOnTrap` runtime option is set, for shims
+    /// that can produce core dumps in the `wasm-coredump` format.
+    fn core_dump_dir(&self) -> Option<&CoreDumpDir> {
         None
     }
 }
 
+/// The stdio of a container.
+/// A stream is `None` if it was not provided by containerd.
+#[derive(Debug, Default)]
+pub struct Stdio {
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+}
+
+/// A directory to write the core dumps of a container to.
+///
+/// The directory is opened by the shim before the container starts, so that it
+/// can be written to from inside the container.
+#[derive(Debug)]
+pub struct CoreDumpDir {
+    dir: File,
+    prefix: String,
+}
+
+impl CoreDumpDir {
+    pub(crate) fn new(dir: File, prefix: impl Into<String>) -> Self {
+        Self {
+            dir,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Write a core dump to a new file in the directory, returning the name of the file.
+    #[cfg(unix)]
+    pub fn write(&self, core_dump: &[u8]) -> std::io::Result<String> {
+        use std::io::Write;
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        use nix::fcntl::{OFlag, openat};
+        use nix::sys::stat::Mode;
+
+        let name = format!(
+            "{}.{}.coredump",
+            self.prefix,
+            chrono::Utc::now().timestamp_millis()
+        );
+        let fd = openat(
+            Some(self.dir.as_raw_fd()),
+            name.as_str(),
+            OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+            Mode::from_bits_truncate(0o600),
+        )?;
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(core_dump)?;
+        Ok(name)
+    }
+}
+
+/// The resource limits of a container.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// The memory limit in bytes.
+    pub memory_limit: Option<u64>,
+    /// The CPU time in microseconds the container can use in each `cpu_period`.
+    pub cpu_quota: Option<u64>,
+    /// The length in microseconds of a CPU period.
+    pub cpu_period: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// The size of a wasm page.
+    pub const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+    /// Returns the memory limit as a number of wasm pages, rounded down.
+    pub fn max_memory_pages(&self) -> Option<u32> {
+        self.memory_limit.map(|limit| {
+            (limit / Self::WASM_PAGE_SIZE)
+                .try_into()
+                .unwrap_or(u32::MAX)
+        })
+    }
+
+    /// Returns the number of CPUs the container is allowed to use, if limited.
+    pub fn cpus(&self) -> Option<f64> {
+        let quota = self.cpu_quota?;
+        let period = self.cpu_period.unwrap_or(DEFAULT_CPU_PERIOD);
+        Some(quota as f64 / period as f64)
+    }
+}
+
+// The default CPU period used by the kernel when `cpu.cfs_period_us` is not set
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// A directory that should be preopened in the guest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Preopen {
+    /// The path of the directory as seen by the shim.
+    pub host_path: PathBuf,
+    /// The path the directory is mounted at in the guest.
+    pub guest_path: PathBuf,
+    /// Whether the guest should only be given read access to the directory.
+    pub readonly: bool,
+}
+
+impl Preopen {
+    /// Returns the path as seen by the shim of a path of the guest, or `None` if the path
+    /// isn't in this directory.
+    ///
+    /// Symlinks are resolved inside of the directory, as the guest would see them, so that
+    /// they can't point to the files of the host.
+    pub fn resolve(&self, path: &Path) -> Option<std::io::Result<PathBuf>> {
+        let relative = path.strip_prefix(&self.guest_path).ok()?;
+        Some(resolve_in_root(&self.host_path, relative))
+    }
+}
+
+/// The annotation that sets how the rootfs is preopened in the guest.
+pub const ROOT_PREOPEN_ANNOTATION: &str = "runwasi.io/root-preopen";
+
+/// How the rootfs is preopened in the guest, as set with the
+/// `runwasi.io/root-preopen` annotation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RootPreopen {
+    /// The rootfs is preopened with read and write access, unless it's read-only in the spec.
+    #[default]
+    ReadWrite,
+    /// The rootfs is preopened with read access only.
+    ReadOnly,
+    /// The rootfs is not preopened, only the mounts are.
+    None,
+}
+
+impl std::str::FromStr for RootPreopen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "read-write" => Ok(Self::ReadWrite),
+            "read-only" => Ok(Self::ReadOnly),
+            "none" => Ok(Self::None),
+            _ => bail!("invalid root preopen {s:?}, expected read-write, read-only or none"),
+        }
+    }
+}
+
 /// The source for a WASI module / components.
 #[derive(Debug)]
 pub enum Source<'a> {
@@ -72,6 +297,9 @@ pub struct WasmLayer {
 
 impl<'a> Source<'a> {
     /// Returns the bytes of the WASI module / component.
+    ///
+    /// This fails for `Source::Oci` with more than one layer. Shims that support
+    /// images with multiple layers need to handle those layers themselves.
     pub fn as_bytes(&self) -> anyhow::Result<Cow<'a, [u8]>> {
         match self {
             Source::File(path) => {
@@ -101,13 +329,59 @@ pub struct Entrypoint<'a> {
     pub source: Source<'a>,
 }
 
+const POD_ID_ANNOTATION: &str = "io.kubernetes.cri.sandbox-id";
+
+static NO_ANNOTATIONS: LazyLock<HashMap<String, String>> = LazyLock::new(HashMap::new);
+static NO_LABELS: LazyLock<HashMap<String, String>> = LazyLock::new(HashMap::new);
+static NO_ENGINE_OPTIONS: LazyLock<EngineOptions> = LazyLock::new(EngineOptions::default);
+
 pub(crate) struct WasiContext<'a> {
     pub spec: &'a Spec,
     pub wasm_layers: &'a [WasmLayer],
     pub platform: &'a Platform,
+    pub labels: &'a HashMap<String, String>,
+    pub checkpoint: Option<&'a [u8]>,
+    pub in_process: Option<&'a InProcessContext>,
+    pub engine_options: Option<&'a EngineOptions>,
+    pub core_dump_dir: Option<&'a CoreDumpDir>,
     pub id: String,
 }
 
+/// The view of the host of a container running inside the shim process.
+pub(crate) struct InProcessContext {
+    /// The container rootfs, as seen by the shim.
+    pub rootfs: PathBuf,
+    pub stdio: Stdio,
+}
+
+impl WasiContext<'_> {
+    /// Returns the path as seen by the shim of a path in the container.
+    ///
+    /// When running in-process, the symlinks of the container rootfs are resolved
+    /// inside of it, so that reading the path doesn't read the files of the host.
+    /// A path that can't be resolved is returned as empty.
+    fn host_path(&self, path: &Path) -> PathBuf {
+        let Some(in_process) = self.in_process else {
+            return path.to_path_buf();
+        };
+        if path.as_os_str().is_empty() {
+            return PathBuf::new();
+        }
+        let cwd = self
+            .spec
+            .process()
+            .as_ref()
+            .map(|p| p.cwd().as_path())
+            .unwrap_or(Path::new("/"));
+        // joining an absolute path replaces the cwd
+        let path = cwd.join(path);
+        resolve_in_root(&in_process.rootfs, &path).unwrap_or_else(|err| {
+            log::warn!("failed to resolve {path:?} in the container rootfs: {err}");
+            PathBuf::new()
+        })
+    }
+}
+
 impl RuntimeContext for WasiContext<'_> {
     fn args(&self) -> &[String] {
         self.spec
@@ -136,7 +410,7 @@ impl RuntimeContext for WasiContext<'_> {
             .unwrap_or((entry_point, "_start"));
 
         let source = if self.wasm_layers.is_empty() {
-            Source::File(PathBuf::from(path))
+            Source::File(self.host_path(Path::new(path)))
         } else {
             Source::Oci(self.wasm_layers)
         };
@@ -161,16 +435,111 @@ impl RuntimeContext for WasiContext<'_> {
         &self.id
     }
 
-    fn pod_id(&self) -> Option<&str> {
-        pod_id(self.spec)
+    fn annotations(&self) -> &HashMap<String, String> {
+        self.spec.annotations().as_ref().unwrap_or(&NO_ANNOTATIONS)
+    }
+
+    fn labels(&self) -> &HashMap<String, String> {
+        self.labels
+    }
+
+    fn preopens(&self) -> Vec<Preopen> {
+        // An invalid value falls back to the most restrictive access that still runs the guest
+        let root_preopen = match self.annotations().get(ROOT_PREOPEN_ANNOTATION) {
+            None => RootPreopen::default(),
+            Some(value) => value.parse().unwrap_or_else(|err| {
+                log::warn!("{err}, preopening the rootfs read-only");
+                RootPreopen::ReadOnly
+            }),
+        };
+        let root = self
+            .spec
+            .root()
+            .as_ref()
+            .filter(|_| root_preopen != RootPreopen::None)
+            .map(|root| Preopen {
+                host_path: self.host_path(Path::new("/")),
+                guest_path: PathBuf::from("/"),
+                readonly: root.readonly().unwrap_or(false) || root_preopen == RootPreopen::ReadOnly,
+            });
+
+        let mounts = self.spec.mounts().iter().flatten().filter_map(|mount| {
+            let destination = mount.destination();
+            let options = mount.options().as_deref().unwrap_or_default();
+            // Inside the shim process, only bind mounts are visible, at their source
+            let host_path = match self.in_process {
+                None => destination.clone(),
+                Some(_) => {
+                    let is_bind = mount.typ().as_deref() == Some("bind")
+                        || options.iter().any(|o| o == "bind" || o == "rbind");
+                    match mount.source() {
+                        Some(source) if is_bind => source.clone(),
+                        _ => {
+                            log::debug!("skipping preopen of non-bind mount {destination:?}");
+                            return None;
+                        }
+                    }
+                }
+            };
+            if !host_path.is_dir() {
+                log::debug!("skipping preopen of non-directory mount {destination:?}");
+                return None;
+            }
+            let readonly = options.iter().any(|option| option == "ro");
+            Some(Preopen {
+                host_path,
+                guest_path: destination.clone(),
+                readonly,
+            })
+        });
+
+        root.into_iter().chain(mounts).collect()
+    }
+
+    fn resources(&self) -> ResourceLimits {
+        let Some(resources) = self
+            .spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+        else {
+            return ResourceLimits::default();
+        };
+
+        // A limit of -1 (or any negative value) means unlimited
+        let memory_limit = resources
+            .memory()
+            .as_ref()
+            .and_then(|memory| memory.limit())
+            .and_then(|limit| u64::try_from(limit).ok());
+        let cpu = resources.cpu().as_ref();
+        let cpu_quota = cpu
+            .and_then(|cpu| cpu.quota())
+            .and_then(|quota| u64::try_from(quota).ok());
+        let cpu_period = cpu.and_then(|cpu| cpu.period());
+
+        ResourceLimits {
+            memory_limit,
+            cpu_quota,
+            cpu_period,
+        }
     }
-}
 
-pub(crate) fn pod_id(spec: &Spec) -> Option<&str> {
-    spec.annotations()
-        .as_ref()
-        .and_then(|a| a.get("io.kubernetes.cri.sandbox-id"))
-        .map(|s| s.as_str())
+    fn checkpoint(&self) -> Option<&[u8]> {
+        self.checkpoint
+    }
+
+    fn stdio(&self) -> Option<&Stdio> {
+        self.in_process.map(|in_process| &in_process.stdio)
+    }
+
+    fn engine_options(&self) -> &EngineOptions {
+        self.engine_options.unwrap_or(&NO_ENGINE_OPTIONS)
+    }
+
+    fn core_dump_dir(&self) -> Option<&CoreDumpDir> {
+        self.core_dump_dir
+    }
 }
 
 /// The type of a wasm binary.
@@ -198,7 +567,10 @@ impl WasmBinaryType {
 mod tests {
     use anyhow::Result;
     use oci_spec::image::{Descriptor, Digest};
-    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxCpuBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder, MountBuilder,
+        ProcessBuilder, RootBuilder, SpecBuilder,
+    };
 
     use super::*;
 
@@ -218,6 +590,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -239,6 +616,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -268,6 +650,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -291,6 +678,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -323,6 +715,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -364,6 +761,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -405,6 +807,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -444,6 +851,11 @@ mod tests {
                 ),
             }],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -468,6 +880,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -490,6 +907,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -510,6 +932,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test".to_string(),
         };
 
@@ -521,8 +948,6 @@ mod tests {
 
     #[test]
     fn test_get_pod_id() -> Result<()> {
-        use std::collections::HashMap;
-
         let mut annotations = HashMap::new();
         annotations.insert(
             "io.kubernetes.cri.sandbox-id".to_string(),
@@ -539,6 +964,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test-container".to_string(),
         };
 
@@ -558,6 +988,11 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
             id: "test-container".to_string(),
         };
 
@@ -565,4 +1000,409 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_get_annotations_and_labels() -> Result<()> {
+        let annotations = HashMap::from([(
+            "wasmtime.runwasi.io/http-proxy-socket-addr".to_string(),
+            "0.0.0.0:9090".to_string(),
+        )]);
+        let labels = HashMap::from([(
+            "org.opencontainers.image.title".to_string(),
+            "hello".to_string(),
+        )]);
+
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .annotations(annotations.clone())
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &labels,
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        assert_eq!(ctx.annotations(), &annotations);
+        assert_eq!(ctx.labels(), &labels);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_resources() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .linux(
+                LinuxBuilder::default()
+                    .resources(
+                        LinuxResourcesBuilder::default()
+                            .memory(
+                                LinuxMemoryBuilder::default()
+                                    .limit(10 * 1024 * 1024)
+                                    .build()?,
+                            )
+                            .cpu(
+                                LinuxCpuBuilder::default()
+                                    .quota(50_000)
+                                    .period(100_000u64)
+                                    .build()?,
+                            )
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        let resources = ctx.resources();
+        assert_eq!(
+            resources,
+            ResourceLimits {
+                memory_limit: Some(10 * 1024 * 1024),
+                cpu_quota: Some(50_000),
+                cpu_period: Some(100_000),
+            }
+        );
+        assert_eq!(resources.max_memory_pages(), Some(160));
+        assert_eq!(resources.cpus(), Some(0.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_resources_unlimited() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .linux(
+                LinuxBuilder::default()
+                    .resources(
+                        LinuxResourcesBuilder::default()
+                            .memory(LinuxMemoryBuilder::default().limit(-1).build()?)
+                            .cpu(LinuxCpuBuilder::default().quota(-1).build()?)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        let resources = ctx.resources();
+        assert_eq!(resources, ResourceLimits::default());
+        assert_eq!(resources.max_memory_pages(), None);
+        assert_eq!(resources.cpus(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_annotations_empty() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        assert!(ctx.annotations().is_empty());
+        assert!(ctx.labels().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preopens_from_root_and_mounts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = dir.path().join("data");
+        let config = dir.path().join("config");
+        let hosts = dir.path().join("hosts");
+        std::fs::create_dir(&data)?;
+        std::fs::create_dir(&config)?;
+        std::fs::write(&hosts, "")?;
+
+        let spec = SpecBuilder::default()
+            .root(
+                RootBuilder::default()
+                    .path("rootfs")
+                    .readonly(false)
+                    .build()?,
+            )
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination(&data)
+                    .options(vec!["rbind".to_string(), "rw".to_string()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination(&config)
+                    .options(vec!["rbind".to_string(), "ro".to_string()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination(&hosts)
+                    .options(vec!["rbind".to_string(), "ro".to_string()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination(dir.path().join("missing"))
+                    .build()?,
+            ])
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        assert_eq!(
+            ctx.preopens(),
+            vec![
+                Preopen {
+                    host_path: "/".into(),
+                    guest_path: "/".into(),
+                    readonly: false,
+                },
+                Preopen {
+                    host_path: data.clone(),
+                    guest_path: data,
+                    readonly: false,
+                },
+                Preopen {
+                    host_path: config.clone(),
+                    guest_path: config,
+                    readonly: true,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preopens_readonly_root() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(
+                RootBuilder::default()
+                    .path("rootfs")
+                    .readonly(true)
+                    .build()?,
+            )
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .mounts(vec![])
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: None,
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        assert_eq!(
+            ctx.preopens(),
+            vec![Preopen {
+                host_path: "/".into(),
+                guest_path: "/".into(),
+                readonly: true,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_preopens_root_preopen_annotation() -> Result<()> {
+        let preopens = |value: &str| -> Result<Vec<Preopen>> {
+            let spec = SpecBuilder::default()
+                .root(
+                    RootBuilder::default()
+                        .path("rootfs")
+                        .readonly(false)
+                        .build()?,
+                )
+                .process(ProcessBuilder::default().cwd("/").build()?)
+                .annotations(HashMap::from([(
+                    ROOT_PREOPEN_ANNOTATION.to_string(),
+                    value.to_string(),
+                )]))
+                .mounts(vec![])
+                .build()?;
+
+            let ctx = WasiContext {
+                spec: &spec,
+                wasm_layers: &[],
+                platform: &Platform::default(),
+                labels: &HashMap::new(),
+                checkpoint: None,
+                in_process: None,
+                engine_options: None,
+                core_dump_dir: None,
+                id: "test".to_string(),
+            };
+            Ok(ctx.preopens())
+        };
+        let root = |readonly| Preopen {
+            host_path: "/".into(),
+            guest_path: "/".into(),
+            readonly,
+        };
+
+        assert_eq!(preopens("read-write")?, vec![root(false)]);
+        assert_eq!(preopens("read-only")?, vec![root(true)]);
+        assert_eq!(preopens("none")?, vec![]);
+        assert_eq!(preopens("invalid")?, vec![root(true)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_process_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rootfs = dir.path().join("rootfs");
+        let data = dir.path().join("data");
+        std::fs::create_dir(&rootfs)?;
+        std::fs::create_dir(&data)?;
+
+        let spec = SpecBuilder::default()
+            .root(
+                RootBuilder::default()
+                    .path(&rootfs)
+                    .readonly(false)
+                    .build()?,
+            )
+            .process(
+                ProcessBuilder::default()
+                    .cwd("/app")
+                    .args(vec!["module.wasm#init".to_string()])
+                    .build()?,
+            )
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/data")
+                    .source(&data)
+                    .options(vec!["rbind".to_string(), "ro".to_string()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination("/proc")
+                    .typ("proc")
+                    .source("proc")
+                    .build()?,
+            ])
+            .build()?;
+
+        let in_process = InProcessContext {
+            rootfs: rootfs.clone(),
+            stdio: Stdio::default(),
+        };
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            labels: &HashMap::new(),
+            checkpoint: None,
+            in_process: Some(&in_process),
+            engine_options: None,
+            core_dump_dir: None,
+            id: "test".to_string(),
+        };
+
+        let path = match ctx.entrypoint().source {
+            Source::File(path) => path,
+            _ => panic!("unexpected source"),
+        };
+        assert_eq!(path, rootfs.join("app/module.wasm"));
+        assert_eq!(
+            ctx.preopens(),
+            vec![
+                Preopen {
+                    host_path: rootfs.clone(),
+                    guest_path: "/".into(),
+                    readonly: false,
+                },
+                Preopen {
+                    host_path: data,
+                    guest_path: "/data".into(),
+                    readonly: true,
+                },
+            ]
+        );
+        assert!(ctx.stdio().is_some());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_preopen_resolve() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = dir.path().join("data");
+        std::fs::create_dir_all(data.join("..2025"))?;
+        std::fs::write(data.join("..2025/tls.crt"), "")?;
+        std::os::unix::fs::symlink("..2025", data.join("..data"))?;
+        std::os::unix::fs::symlink("..data/tls.crt", data.join("tls.crt"))?;
+        std::os::unix::fs::symlink("../../etc/passwd", data.join("escape"))?;
+
+        let preopen = Preopen {
+            host_path: data.clone(),
+            guest_path: "/data".into(),
+            readonly: true,
+        };
+        let resolve = |path: &str| preopen.resolve(Path::new(path)).map(|p| p.unwrap());
+        assert_eq!(resolve("/data/tls.crt"), Some(data.join("..2025/tls.crt")));
+        assert_eq!(resolve("/data/escape"), Some(data.join("etc/passwd")));
+        assert_eq!(resolve("/etc/passwd"), None);
+
+        Ok(())
+    }
 }
@@ -1,16 +1,26 @@
 use std::fs::File;
 use std::io::Read;
+use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+pub use containerd_shimkit::sandbox::Termination;
 use context::{RuntimeContext, Source};
 use path::PathResolve as _;
 
 pub mod context;
+pub mod network;
 pub(crate) mod path;
 
+/// Delivers the signals a container running inside the shim process is killed with,
+/// see [`Sandbox::signal_handle`].
+pub type SignalHandle = Arc<dyn Fn(i32) + Send + Sync>;
+
 #[trait_variant::make(Send)]
 pub trait Sandbox: Default + 'static {
     /// Run a WebAssembly container
+    ///
+    /// To report why the guest terminated, e.g., because it trapped, return a
+    /// [`Termination`] as the error. Any other error is reported as an engine error.
     async fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32>;
 
     /// Check that the runtime can run the container.
@@ -44,4 +54,49 @@ pub trait Sandbox: Default + 'static {
             Ok(())
         }
     }
+
+    /// Take a snapshot of the running container, e.g., of the linear memory of its
+    /// module instance.
+    /// The snapshot is written to the checkpoint image, and passed back to the shim
+    /// in [`RuntimeContext::checkpoint`] when the container is restored.
+    /// This is only called for shims that support checkpointing
+    /// (see [`Shim::supports_checkpoint`](crate::shim::Shim::supports_checkpoint)).
+    async fn checkpoint(&self) -> Result<Vec<u8>> {
+        async move { bail!("checkpoint is not supported") }
+    }
+
+    /// Returns a handle delivering the signals the container is killed with to the guest,
+    /// when it runs inside the shim process
+    /// (see [`Shim::supports_in_process`](crate::shim::Shim::supports_in_process)).
+    ///
+    /// The handle is called from another thread than [`Sandbox::run_wasi`]. Unless the guest
+    /// handles the signal, e.g., by draining an HTTP server, the handle must stop the guest
+    /// even if it never yields, e.g., by interrupting the engine, so that [`Sandbox::run_wasi`]
+    /// returns. Containers can only run in-process with sandboxes that return a handle.
+    fn signal_handle(&self) -> Option<SignalHandle> {
+        None
+    }
+}
+
+/// Returns why a container terminated, from the result of [`Sandbox::run_wasi`].
+pub(crate) fn termination(result: Result<i32>) -> Termination {
+    match result {
+        Ok(code) => Termination::Exited { code },
+        Err(err) => err
+            .downcast::<Termination>()
+            .unwrap_or_else(|err| Termination::EngineError {
+                message: format!("{err:#}"),
+            }),
+    }
+}
+
+/// Whether the termination should be reported on the stderr of the container.
+pub(crate) fn is_abnormal(termination: &Termination) -> bool {
+    matches!(
+        termination,
+        Termination::Trap { .. }
+            | Termination::LimitExceeded { .. }
+            | Termination::DeadlineExceeded
+            | Termination::EngineError { .. }
+    )
 }
@@ -0,0 +1,364 @@
+//! Socket level network policy of the guests.
+//!
+//! By default guests can bind and connect sockets to any address, and resolve host
+//! names, like before the policy existed. The annotations of the container can deny
+//! the network by default, allow the addresses and ports sockets can be bound and
+//! connected to, and toggle the name lookups.
+//! The policy is only read from annotations, and not from environment variables,
+//! since those can be set by the image of the guest.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, bail, ensure};
+
+use super::context::RuntimeContext;
+
+/// Annotation to deny the network unless it's allowed, with `deny`, or to allow it unless it's restricted, with `allow`.
+pub const NETWORK_DEFAULT_ANNOTATION: &str = "runwasi.io/network-default";
+/// Annotation with the addresses sockets can be bound to.
+pub const NETWORK_ALLOWED_BIND_ANNOTATION: &str = "runwasi.io/network-allowed-bind";
+/// Annotation with the addresses sockets can connect, or send datagrams, to.
+pub const NETWORK_ALLOWED_CONNECT_ANNOTATION: &str = "runwasi.io/network-allowed-connect";
+/// Annotation to allow, or not, the guest to resolve host names.
+pub const NETWORK_IP_NAME_LOOKUP_ANNOTATION: &str = "runwasi.io/network-ip-name-lookup";
+
+/// The network access of a container.
+#[derive(Clone, Debug)]
+pub struct NetworkPolicy {
+    /// The addresses sockets can be bound to, or `None` to allow any address.
+    bind: Option<Vec<AddrPattern>>,
+    /// The addresses sockets can connect to, or `None` to allow any address.
+    connect: Option<Vec<AddrPattern>>,
+    ip_name_lookup: bool,
+}
+
+/// What a socket address is used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketUse {
+    /// Binding a socket to a local address.
+    Bind,
+    /// Connecting a socket, or sending a datagram, to a remote address.
+    Connect,
+}
+
+impl Display for SocketUse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bind => f.write_str("bind"),
+            Self::Connect => f.write_str("connect"),
+        }
+    }
+}
+
+impl Default for NetworkPolicy {
+    /// Allows any network access.
+    fn default() -> Self {
+        Self {
+            bind: None,
+            connect: None,
+            ip_name_lookup: true,
+        }
+    }
+}
+
+impl NetworkPolicy {
+    /// An invalid policy is an error rather than being ignored, so that a typo can't open up the network.
+    pub fn new(ctx: &impl RuntimeContext) -> Result<Self> {
+        Self::from_annotations(ctx.annotations())
+    }
+
+    /// Reads the policy from the annotations of a container, see [`NetworkPolicy::new`].
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let annotation = |key: &str| annotations.get(key).map(String::as_str);
+
+        let deny = match annotation(NETWORK_DEFAULT_ANNOTATION) {
+            None | Some("allow") => false,
+            Some("deny") => true,
+            Some(value) => bail!(
+                "invalid {NETWORK_DEFAULT_ANNOTATION} annotation: expected `allow` or `deny`, got {value:?}"
+            ),
+        };
+        let patterns = |key: &str| -> Result<Option<Vec<AddrPattern>>> {
+            match annotation(key) {
+                Some(patterns) => list(patterns)
+                    .map(str::parse)
+                    .collect::<Result<_>>()
+                    .map(Some)
+                    .with_context(|| format!("invalid {key} annotation")),
+                None if deny => Ok(Some(vec![])),
+                None => Ok(None),
+            }
+        };
+        let bind = patterns(NETWORK_ALLOWED_BIND_ANNOTATION)?;
+        let connect = patterns(NETWORK_ALLOWED_CONNECT_ANNOTATION)?;
+        let ip_name_lookup = annotation(NETWORK_IP_NAME_LOOKUP_ANNOTATION)
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("invalid {NETWORK_IP_NAME_LOOKUP_ANNOTATION} annotation"))?
+            .unwrap_or(!deny);
+
+        Ok(Self {
+            bind,
+            connect,
+            ip_name_lookup,
+        })
+    }
+
+    /// Whether a socket can be bound or connected to `addr`.
+    pub fn is_allowed(&self, addr: SocketAddr, usage: SocketUse) -> bool {
+        let patterns = match usage {
+            SocketUse::Bind => &self.bind,
+            SocketUse::Connect => &self.connect,
+        };
+        match patterns {
+            Some(patterns) => patterns.iter().any(|pattern| pattern.matches(addr)),
+            None => true,
+        }
+    }
+
+    /// Whether the guest can resolve host names.
+    pub fn allows_ip_name_lookup(&self) -> bool {
+        self.ip_name_lookup
+    }
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Socket addresses, `<ip>[/<prefix>][:<port>[-<port>]]`.
+///
+/// The IP is either an address, a CIDR block, or `*` for any address, and IPv6
+/// addresses are in brackets when followed by ports, e.g., `[fd00::/8]:443`.
+/// Any port matches when the ports are omitted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AddrPattern {
+    ip: IpPattern,
+    ports: Option<RangeInclusive<u16>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum IpPattern {
+    Any,
+    Cidr(IpAddr, u8),
+}
+
+impl AddrPattern {
+    fn matches(&self, addr: SocketAddr) -> bool {
+        self.ip.matches(addr.ip())
+            && self
+                .ports
+                .as_ref()
+                .is_none_or(|ports| ports.contains(&addr.port()))
+    }
+}
+
+impl IpPattern {
+    fn matches(&self, ip: IpAddr) -> bool {
+        let Self::Cidr(net, prefix) = self else {
+            return true;
+        };
+        // IPv4 addresses of dual stack sockets are mapped to IPv6
+        match (ip.to_canonical(), net) {
+            (IpAddr::V4(ip), IpAddr::V4(net)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                u32::from(ip) & mask == u32::from(*net) & mask
+            }
+            (IpAddr::V6(ip), IpAddr::V6(net)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                u128::from(ip) & mask == u128::from(*net) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for AddrPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(pattern: &str) -> Result<Self> {
+        let (ip, ports) = if let Some(rest) = pattern.strip_prefix('[') {
+            let (ip, rest) = rest
+                .split_once(']')
+                .with_context(|| format!("missing `]` in {pattern:?}"))?;
+            let ports = match rest {
+                "" => None,
+                _ => Some(
+                    rest.strip_prefix(':')
+                        .with_context(|| format!("expected a port after `]` in {pattern:?}"))?,
+                ),
+            };
+            (ip, ports)
+        } else if pattern.matches(':').count() > 1 {
+            // an IPv6 address without ports
+            (pattern, None)
+        } else {
+            match pattern.split_once(':') {
+                Some((ip, ports)) => (ip, Some(ports)),
+                None => (pattern, None),
+            }
+        };
+
+        let ip = match ip {
+            "*" => IpPattern::Any,
+            _ => ip
+                .parse()
+                .with_context(|| format!("invalid IP address or CIDR block in {pattern:?}"))?,
+        };
+        let ports = ports
+            .map(|ports| parse_ports(ports).with_context(|| format!("invalid port in {pattern:?}")))
+            .transpose()?;
+        Ok(Self { ip, ports })
+    }
+}
+
+impl FromStr for IpPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(cidr: &str) -> Result<Self> {
+        let (ip, prefix) = match cidr.split_once('/') {
+            Some((ip, prefix)) => (ip.parse::<IpAddr>()?, Some(prefix.parse::<u8>()?)),
+            None => (cidr.parse::<IpAddr>()?, None),
+        };
+        let max = match ip {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(max);
+        ensure!(prefix <= max, "prefix /{prefix} is longer than {max} bits");
+        Ok(Self::Cidr(ip, prefix))
+    }
+}
+
+fn parse_ports(ports: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = match ports.split_once('-') {
+        Some((start, end)) => (start.parse()?, end.parse()?),
+        None => {
+            let port = ports.parse()?;
+            (port, port)
+        }
+    };
+    ensure!(start <= end, "empty port range {ports:?}");
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_annotations(annotations: &[(&str, &str)]) -> Result<NetworkPolicy> {
+        let annotations = annotations
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        NetworkPolicy::from_annotations(&annotations)
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allow_by_default() -> Result<()> {
+        let policy = from_annotations(&[])?;
+        assert!(policy.is_allowed(addr("0.0.0.0:8080"), SocketUse::Bind));
+        assert!(policy.is_allowed(addr("[2001:db8::1]:443"), SocketUse::Connect));
+        assert!(policy.allows_ip_name_lookup());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deny_by_default() -> Result<()> {
+        let policy = from_annotations(&[
+            (NETWORK_DEFAULT_ANNOTATION, "deny"),
+            (
+                NETWORK_ALLOWED_CONNECT_ANNOTATION,
+                "10.0.0.0/8:5432, [fd00::/8]:443",
+            ),
+        ])?;
+        assert!(!policy.is_allowed(addr("0.0.0.0:8080"), SocketUse::Bind));
+        assert!(policy.is_allowed(addr("10.1.2.3:5432"), SocketUse::Connect));
+        assert!(!policy.is_allowed(addr("10.1.2.3:5433"), SocketUse::Connect));
+        assert!(!policy.is_allowed(addr("11.1.2.3:5432"), SocketUse::Connect));
+        assert!(policy.is_allowed(addr("[fd12::1]:443"), SocketUse::Connect));
+        assert!(!policy.allows_ip_name_lookup());
+
+        let policy = from_annotations(&[
+            (NETWORK_DEFAULT_ANNOTATION, "deny"),
+            (NETWORK_IP_NAME_LOOKUP_ANNOTATION, "true"),
+        ])?;
+        assert!(policy.allows_ip_name_lookup());
+        Ok(())
+    }
+
+    #[test]
+    fn test_allowed_bind() -> Result<()> {
+        let policy =
+            from_annotations(&[(NETWORK_ALLOWED_BIND_ANNOTATION, "*:8000-8999, 127.0.0.1")])?;
+        assert!(policy.is_allowed(addr("0.0.0.0:8080"), SocketUse::Bind));
+        assert!(policy.is_allowed(addr("[::]:8999"), SocketUse::Bind));
+        assert!(!policy.is_allowed(addr("0.0.0.0:9000"), SocketUse::Bind));
+        assert!(policy.is_allowed(addr("127.0.0.1:9000"), SocketUse::Bind));
+        // IPv4 addresses mapped to IPv6 match IPv4 blocks
+        assert!(policy.is_allowed(addr("[::ffff:127.0.0.1]:9000"), SocketUse::Bind));
+        // the connections are still allowed
+        assert!(policy.is_allowed(addr("192.0.2.1:9000"), SocketUse::Connect));
+        Ok(())
+    }
+
+    #[test]
+    fn test_addr_pattern() -> Result<()> {
+        let parse = |pattern: &str| pattern.parse::<AddrPattern>();
+        assert_eq!(
+            parse("192.0.2.0/24:80")?,
+            AddrPattern {
+                ip: IpPattern::Cidr("192.0.2.0".parse()?, 24),
+                ports: Some(80..=80),
+            }
+        );
+        assert_eq!(
+            parse("::1")?,
+            AddrPattern {
+                ip: IpPattern::Cidr("::1".parse()?, 128),
+                ports: None,
+            }
+        );
+        assert_eq!(
+            parse("[::/0]:1-1024")?,
+            AddrPattern {
+                ip: IpPattern::Cidr("::".parse()?, 0),
+                ports: Some(1..=1024),
+            }
+        );
+        assert_eq!(
+            parse("*")?,
+            AddrPattern {
+                ip: IpPattern::Any,
+                ports: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        for annotations in [
+            [(NETWORK_DEFAULT_ANNOTATION, "none")],
+            [(NETWORK_IP_NAME_LOOKUP_ANNOTATION, "yes")],
+            [(NETWORK_ALLOWED_CONNECT_ANNOTATION, "example.com:443")],
+            [(NETWORK_ALLOWED_CONNECT_ANNOTATION, "10.0.0.0/33")],
+            [(NETWORK_ALLOWED_CONNECT_ANNOTATION, "10.0.0.1:443-80")],
+            [(NETWORK_ALLOWED_BIND_ANNOTATION, "[::1:80")],
+            [(NETWORK_ALLOWED_BIND_ANNOTATION, "[::1]80")],
+        ] {
+            assert!(from_annotations(&annotations).is_err(), "{annotations:?}");
+        }
+    }
+}
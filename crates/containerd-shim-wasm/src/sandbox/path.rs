@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 /// PathResolve allows to resolve a file path in a set of directories.
 pub(crate) trait PathResolve {
@@ -73,3 +73,90 @@ impl<T: AsRef<Path>> PathResolve for T {
         self.resolve_in_dirs(paths().chain(std::env::current_dir().ok()))
     }
 }
+
+/// Maximum number of symlinks followed when resolving a path, as in Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolve `path` as if `root` was the root of the filesystem, like `openat2` with
+/// `RESOLVE_IN_ROOT` does: symlinks are followed, but absolute targets and `..`
+/// components never leave `root`.
+/// The components of `path` that don't exist are kept as they are.
+pub(crate) fn resolve_in_root(root: &Path, path: &Path) -> std::io::Result<PathBuf> {
+    // the components left to resolve, in reverse order
+    let mut pending: Vec<PathBuf> = path
+        .components()
+        .rev()
+        .map(|c| PathBuf::from(c.as_os_str()))
+        .collect();
+    // the resolved path relative to `root`, which has no symlinks
+    let mut resolved = PathBuf::new();
+    let mut symlinks = 0;
+
+    while let Some(component) = pending.pop() {
+        match component.components().next() {
+            Some(Component::RootDir) => resolved = PathBuf::new(),
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Normal(name)) => {
+                resolved.push(name);
+                let Ok(target) = std::fs::read_link(root.join(&resolved)) else {
+                    continue;
+                };
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(std::io::Error::other(format!(
+                        "too many levels of symbolic links resolving {path:?}"
+                    )));
+                }
+                // the target is relative to the directory of the symlink
+                resolved.pop();
+                pending.extend(
+                    target
+                        .components()
+                        .rev()
+                        .map(|c| PathBuf::from(c.as_os_str())),
+                );
+            }
+            Some(Component::CurDir | Component::Prefix(_)) | None => {}
+        }
+    }
+
+    Ok(root.join(resolved))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_in_root() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("rootfs");
+        std::fs::create_dir_all(root.join("app"))?;
+        std::fs::write(root.join("app/module.wasm"), "")?;
+        symlink("/app/module.wasm", root.join("absolute.wasm"))?;
+        symlink("../../app", root.join("app/up"))?;
+        symlink("/etc/passwd", root.join("escape"))?;
+        symlink("loop", root.join("loop"))?;
+
+        let resolve = |path: &str| resolve_in_root(&root, Path::new(path));
+        assert_eq!(resolve("/app/module.wasm")?, root.join("app/module.wasm"));
+        assert_eq!(resolve("/absolute.wasm")?, root.join("app/module.wasm"));
+        assert_eq!(
+            resolve("/app/up/module.wasm")?,
+            root.join("app/module.wasm")
+        );
+        assert_eq!(
+            resolve("/../../app/./module.wasm")?,
+            root.join("app/module.wasm")
+        );
+        assert_eq!(resolve("/escape")?, root.join("etc/passwd"));
+        assert_eq!(resolve("/missing/file")?, root.join("missing/file"));
+        assert_eq!(resolve("/")?, root);
+        assert!(resolve("/loop").is_err());
+        Ok(())
+    }
+}
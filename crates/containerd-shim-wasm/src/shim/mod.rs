@@ -10,6 +10,18 @@
 //! * Less customizable
 //! * Currently only works on Linux
 //!
+//! Shims that support it (see [`Shim::supports_in_process`]) can avoid the overhead of
+//! setting up a container by running tasks inside the shim process instead.
+//! This is opt-in, with the `InProcess` runtime option, and relies only on the Wasm sandbox
+//! for isolation. Tasks running in-process don't support `exec`, pausing, or updating their
+//! resources, and are accounted to the cgroup of the shim, which the shim is moved to on
+//! start with the `ShimCgroup` runtime option.
+//!
+//! Containers whose entrypoint is a native executable instead of a Wasm module run
+//! as regular Linux containers. This can be forbidden with the `DisableLinuxFallback`
+//! runtime option or the `runwasi.io/disable-linux-fallback: "true"` annotation, in which
+//! case such tasks are rejected and a `TaskRejected` event is published.
+//!
 //! ## Key Components
 //!
 //! - [`Shim`]: The trait for implementing the shim entrypoint
@@ -23,6 +23,23 @@ pub trait Shim: Sync + 'static {
 
     type Sandbox: Sandbox;
 
+    /// Whether the shim supports checkpointing and restoring containers.
+    /// Shims returning `true` must implement [`Sandbox::checkpoint`], and restore
+    /// the snapshot from [`RuntimeContext::checkpoint`](crate::sandbox::context::RuntimeContext::checkpoint).
+    fn supports_checkpoint() -> bool {
+        false
+    }
+
+    /// Whether the shim can run containers inside the shim process, without
+    /// setting up a container, when requested by the `InProcess` runtime option.
+    ///
+    /// Shims returning `true` must use the stdio from [`RuntimeContext::stdio`](crate::sandbox::context::RuntimeContext::stdio)
+    /// when set, and their sandbox must return a [`Sandbox::signal_handle`] to stop the guest
+    /// when the container is killed.
+    fn supports_in_process() -> bool {
+        false
+    }
+
     /// When `compiler` returns `Some`, the returned `Compiler` will be used to precompile
     /// the layers before they are run.
     /// Returns the compiler to be used by this engine
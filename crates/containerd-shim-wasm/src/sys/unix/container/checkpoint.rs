@@ -0,0 +1,107 @@
+//! Checkpointing of running wasm containers.
+//!
+//! The wasm instance lives in the container process, so the shim can't snapshot it
+//! directly. Instead, the shim binds a unix socket in the bundle of the container,
+//! outside of the container's filesystem, and the container process inherits it
+//! and serves checkpoint requests on it.
+//!
+//! The response to a request is a status byte followed by either the snapshot
+//! (status `0`) or an error message (any other status).
+
+use std::fs::{File, Permissions};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::sandbox::Sandbox;
+
+/// Name of the file in the checkpoint image containing the snapshot of the container.
+pub(crate) const SNAPSHOT_FILE: &str = "wasm-snapshot.bin";
+
+/// Name of the socket in the bundle of the container.
+const SOCKET_FILE: &str = "checkpoint.sock";
+
+/// How long the shim waits for the container to take a snapshot.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The path of the socket in the bundle, through the file descriptor of the bundle.
+/// The path to the bundle can easily exceed the maximum length of a unix socket path.
+fn socket_path(bundle: &File) -> String {
+    format!("/proc/self/fd/{}/{SOCKET_FILE}", bundle.as_raw_fd())
+}
+
+/// Bind the socket the container serves checkpoint requests on.
+/// This must be called before creating the container, for the container process to inherit it.
+pub(crate) fn bind(bundle: impl AsRef<Path>) -> Result<StdUnixListener> {
+    let bundle = File::open(bundle).context("failed to open container bundle")?;
+    let path = socket_path(&bundle);
+    let _ = std::fs::remove_file(&path);
+    let listener = StdUnixListener::bind(&path).context("failed to bind checkpoint socket")?;
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serve checkpoint requests for the given sandbox on the socket from [`bind`].
+/// This must be called from within the container process, and never returns.
+pub(crate) async fn serve(sandbox: &impl Sandbox, listener: &StdUnixListener) {
+    let listener = listener
+        .try_clone()
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            UnixListener::from_std(listener)
+        })
+        .inspect_err(|err| log::warn!("checkpointing is disabled: {err}"));
+    let Ok(listener) = listener else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("failed to accept checkpoint request: {err}");
+                continue;
+            }
+        };
+
+        log::info!("taking checkpoint");
+        let response = match sandbox.checkpoint().await {
+            Ok(snapshot) => [&[0u8][..], &snapshot].concat(),
+            Err(err) => {
+                log::error!("failed to take checkpoint: {err:#}");
+                [&[1u8][..], format!("{err:#}").as_bytes()].concat()
+            }
+        };
+
+        if let Err(err) = stream.write_all(&response).await {
+            log::warn!("failed to send checkpoint: {err}");
+        }
+    }
+}
+
+/// Request a snapshot from the container with the given bundle.
+pub(crate) async fn request(bundle: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let bundle = File::open(bundle).context("failed to open container bundle")?;
+    let path = socket_path(&bundle);
+
+    let mut stream = UnixStream::connect(path)
+        .await
+        .context("failed to connect to container, is it running?")?;
+
+    let mut response = vec![];
+    tokio::time::timeout(REQUEST_TIMEOUT, stream.read_to_end(&mut response))
+        .await
+        .context("timeout waiting for checkpoint")??;
+
+    match response.split_first() {
+        Some((0, snapshot)) => Ok(snapshot.to_vec()),
+        Some((_, msg)) => bail!("{}", String::from_utf8_lossy(msg)),
+        None => bail!("container exited before taking a checkpoint"),
+    }
+}
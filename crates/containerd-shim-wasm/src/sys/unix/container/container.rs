@@ -4,8 +4,10 @@ use std::mem::transmute;
 
 use anyhow::{Context, anyhow};
 use containerd_shimkit::zygote::{WireError, Zygote};
+use libcgroups::common::{CgroupConfig, CgroupManager as _, ControllerOpt, create_cgroup_manager};
 use libcontainer::container::Container as YoukiContainer;
 use libcontainer::signal::Signal;
+use oci_spec::runtime::LinuxResources;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
@@ -51,11 +53,55 @@ impl Container {
             signal,
         )
     }
+    pub fn pause(&self) -> anyhow::Result<()> {
+        self.run(|c, _| Ok(c.pause()?), ())
+    }
+    pub fn resume(&self) -> anyhow::Result<()> {
+        self.run(|c, _| Ok(c.resume()?), ())
+    }
+    pub fn update(&self, resources: LinuxResources) -> anyhow::Result<()> {
+        self.run(
+            |c, resources| {
+                let cmanager = create_cgroup_manager(CgroupConfig {
+                    cgroup_path: c.spec()?.cgroup_path,
+                    systemd_cgroup: c.systemd(),
+                    container_name: c.id().to_string(),
+                })?;
+                cmanager.apply(&ControllerOpt {
+                    resources: &resources,
+                    disable_oom_killer: false,
+                    oom_score_adj: None,
+                    freezer_state: None,
+                })?;
+                Ok(())
+            },
+            resources,
+        )
+    }
     pub fn delete(&self) -> anyhow::Result<()> {
         self.run(|c, _| Ok(c.delete(true)?), ())
     }
 }
 
+// Additional processes in the container
+impl Container {
+    /// Runs `f` in the zygote process to spawn an additional process in the container.
+    /// `f` should return the PID of the new process.
+    pub fn exec<Arg: Serialize + DeserializeOwned + 'static>(
+        &self,
+        f: fn(Arg) -> anyhow::Result<i32>,
+        arg: Arg,
+    ) -> anyhow::Result<i32> {
+        self.run(
+            |_, (f, arg): (usize, Arg)| {
+                let f: fn(Arg) -> anyhow::Result<i32> = unsafe { transmute(f) };
+                f(arg)
+            },
+            (f as usize, arg),
+        )
+    }
+}
+
 impl Container {
     fn run_impl<
         Arg: Serialize + DeserializeOwned + 'static,
@@ -1,13 +1,16 @@
+use std::borrow::Cow;
 use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::os::unix::prelude::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result, bail};
 use containerd_shimkit::AmbientRuntime;
+use containerd_shimkit::sandbox::{EngineOptions, InstanceConfig};
 use libcontainer::workload::default::DefaultExecutor;
 use libcontainer::workload::{
     Executor as LibcontainerExecutor, ExecutorError as LibcontainerExecutorError,
@@ -16,11 +19,51 @@ use libcontainer::workload::{
 use oci_spec::image::Platform;
 use oci_spec::runtime::Spec;
 
-use crate::sandbox::Sandbox;
-use crate::sandbox::context::{RuntimeContext, Source, WasiContext, WasmLayer};
+use super::checkpoint;
+use crate::sandbox::context::{CoreDumpDir, RuntimeContext, Source, WasiContext, WasmLayer};
 use crate::sandbox::path::PathResolve;
+use crate::sandbox::{Sandbox, is_abnormal, termination};
 use crate::shim::Shim;
 
+/// Annotation to forbid running native Linux containers, as an alternative to the
+/// `DisableLinuxFallback` runtime option.
+pub(crate) const DISABLE_LINUX_FALLBACK_ANNOTATION: &str = "runwasi.io/disable-linux-fallback";
+
+/// The `PATH` used to resolve the entrypoint when the process doesn't set one.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Name of the socket in the bundle where the container processes relay their metrics to the shim.
+#[cfg(feature = "opentelemetry")]
+pub(crate) const METRICS_SOCKET: &str = "metrics.sock";
+
+/// Whether running native Linux containers is forbidden for the container.
+pub(crate) fn is_linux_fallback_disabled(cfg: &InstanceConfig, spec: &Spec) -> bool {
+    cfg.config.disable_linux_fallback
+        || spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(DISABLE_LINUX_FALLBACK_ANNOTATION))
+            .is_some_and(|v| v == "true")
+}
+
+/// Opens the directory the core dumps of the container are written to,
+/// if the `CoreDumpOnTrap` runtime option is set.
+/// This defaults to the bundle of the container.
+pub(crate) fn open_core_dump_dir(cfg: &InstanceConfig, id: &str) -> Option<CoreDumpDir> {
+    if !cfg.config.core_dump_on_trap {
+        return None;
+    }
+    let dir = cfg.config.core_dump_dir.as_ref().unwrap_or(&cfg.bundle);
+    let result = std::fs::create_dir_all(dir).and_then(|_| File::open(dir));
+    match result {
+        Ok(file) => Some(CoreDumpDir::new(file, id)),
+        Err(err) => {
+            log::warn!("failed to open core dump directory {dir:?}: {err}");
+            None
+        }
+    }
+}
+
 #[derive(Clone)]
 enum ExecutorType<S: Shim> {
     Wasm(S::Sandbox),
@@ -28,6 +71,75 @@ enum ExecutorType<S: Shim> {
     CantHandle,
 }
 
+/// The kind of process the executor runs in the container.
+pub(crate) enum ProcessKind {
+    /// The container's init process, optionally restored from a checkpoint.
+    /// Why the process terminated is written to `termination`, for the shim to read it.
+    /// Checkpoint requests are served on `checkpoint_listener`, for shims that support it.
+    Init {
+        checkpoint: Option<Vec<u8>>,
+        termination: File,
+        checkpoint_listener: Option<UnixListener>,
+        telemetry: ProcessTelemetry,
+    },
+    /// An additional process started with `exec`.
+    /// Why the process terminated is written to `termination`, like for the init process.
+    Exec {
+        termination: File,
+        telemetry: ProcessTelemetry,
+    },
+}
+
+/// The telemetry of the shim a container process carries on, as it doesn't export any itself.
+#[derive(Default)]
+pub(crate) struct ProcessTelemetry {
+    /// The W3C `traceparent` of the shim's span that started the process,
+    /// set as the `TRACEPARENT` env of the guest.
+    traceparent: Option<String>,
+    /// The connection to the metrics relay of the shim, if it exports metrics.
+    #[cfg_attr(not(feature = "opentelemetry"), allow(dead_code))]
+    metrics: Option<UnixStream>,
+}
+
+impl ProcessTelemetry {
+    /// Connects to the metrics relay of the shim in `bundle`, if there's one.
+    pub fn connect(bundle: &Path, traceparent: Option<String>) -> Self {
+        #[cfg(feature = "opentelemetry")]
+        let metrics =
+            containerd_shimkit::sandbox::telemetry::MetricsRelay::connect(bundle, METRICS_SOCKET)
+                .ok();
+        #[cfg(not(feature = "opentelemetry"))]
+        let metrics = {
+            let _ = bundle;
+            None
+        };
+        Self {
+            traceparent,
+            metrics,
+        }
+    }
+
+    /// Adds the `TRACEPARENT` env to the process of `spec`, unless it's already set.
+    fn with_trace_context<'a>(&self, spec: &'a Spec) -> Cow<'a, Spec> {
+        let Some(traceparent) = &self.traceparent else {
+            return Cow::Borrowed(spec);
+        };
+        let Some(process) = spec.process() else {
+            return Cow::Borrowed(spec);
+        };
+        let mut env = process.env().clone().unwrap_or_default();
+        if env.iter().any(|var| var.starts_with("TRACEPARENT=")) {
+            return Cow::Borrowed(spec);
+        }
+        env.push(format!("TRACEPARENT={traceparent}"));
+        let mut spec = spec.clone();
+        if let Some(process) = spec.process_mut() {
+            process.set_env(Some(env));
+        }
+        Cow::Owned(spec)
+    }
+}
+
 pub(crate) struct Executor<S: Shim>(Arc<InnerExecutor<S>>);
 
 impl<S: Shim> Clone for Executor<S> {
@@ -40,7 +152,12 @@ pub(crate) struct InnerExecutor<S: Shim> {
     ty: OnceCell<ExecutorType<S>>,
     wasm_layers: Vec<WasmLayer>,
     platform: Platform,
+    labels: HashMap<String, String>,
     id: String,
+    kind: ProcessKind,
+    linux_fallback: bool,
+    engine_options: EngineOptions,
+    core_dump_dir: Option<CoreDumpDir>,
 }
 
 impl<S: Shim> LibcontainerExecutor for Executor<S> {
@@ -49,30 +166,79 @@ impl<S: Shim> LibcontainerExecutor for Executor<S> {
         // We can handle linux container. We delegate wasm container to the engine.
         match self.ty(spec) {
             ExecutorType::CantHandle => Err(ExecutorValidationError::CantHandle(S::name())),
+            ExecutorType::Linux if !self.0.linux_fallback => Err(
+                ExecutorValidationError::ArgValidationError(linux_fallback_disabled_message()),
+            ),
             _ => Ok(()),
         }
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     fn exec(&self, spec: &Spec) -> Result<(), LibcontainerExecutorError> {
+        let terminal = spec.process().as_ref().and_then(|p| p.terminal());
+        if terminal == Some(true) {
+            if let Err(err) = set_controlling_terminal() {
+                log::warn!("failed to set the controlling terminal: {err}");
+            }
+        }
+
         // If it looks like a linux container, run it as a linux container.
         // Otherwise, run it as a wasm container
         match self.ty(spec) {
             ExecutorType::CantHandle => Err(LibcontainerExecutorError::CantHandle(S::name())),
+            ExecutorType::Linux if !self.0.linux_fallback => Err(LibcontainerExecutorError::Other(
+                linux_fallback_disabled_message(),
+            )),
             ExecutorType::Linux => {
                 log::info!("executing linux container");
                 DefaultExecutor {}.exec(spec)
             }
             ExecutorType::Wasm(container) => {
-                let ctx = self.ctx(spec);
+                let (ProcessKind::Init { telemetry, .. } | ProcessKind::Exec { telemetry, .. }) =
+                    &self.0.kind;
+                let spec = telemetry.with_trace_context(spec);
+                let ctx = self.ctx(&spec);
+                #[cfg(feature = "opentelemetry")]
+                let metrics = telemetry.metrics.as_ref().and_then(|stream| {
+                    let stream = stream
+                        .try_clone()
+                        .inspect_err(|err| log::warn!("failed to relay metrics: {err}"));
+                    Some(containerd_shimkit::sandbox::telemetry::relay_metrics(
+                        stream.ok()?,
+                    ))
+                });
                 log::info!("calling start function");
-                match container.run_wasi(&ctx).block_on() {
-                    Ok(code) => std::process::exit(code),
-                    Err(err) => {
-                        log::info!("error running start function: {err}");
-                        std::process::exit(137)
+                let result = match &self.0.kind {
+                    ProcessKind::Init {
+                        checkpoint_listener: Some(listener),
+                        ..
+                    } => async {
+                        tokio::select! {
+                            result = container.run_wasi(&ctx) => result,
+                            _ = checkpoint::serve(container, listener) => unreachable!(),
+                        }
                     }
+                    .block_on(),
+                    _ => container.run_wasi(&ctx).block_on(),
                 };
+                let termination = termination(result);
+                if is_abnormal(&termination) {
+                    log::error!("error running start function: {termination}");
+                    let _ = writeln!(std::io::stderr(), "{termination}");
+                }
+                let (ProcessKind::Init {
+                    termination: file, ..
+                }
+                | ProcessKind::Exec {
+                    termination: file, ..
+                }) = &self.0.kind;
+                if let Err(err) = serde_json::to_writer(file, &termination) {
+                    log::warn!("failed to report termination: {err}");
+                }
+                // `exit` doesn't run destructors, relay the pending metrics first
+                #[cfg(feature = "opentelemetry")]
+                drop(metrics);
+                std::process::exit(termination.exit_code() as i32)
             }
         }
     }
@@ -94,22 +260,46 @@ impl<S: Shim> LibcontainerExecutor for Executor<S> {
 }
 
 impl<S: Shim> Executor<S> {
-    pub fn new(wasm_layers: Vec<WasmLayer>, platform: Platform, id: String) -> Self {
+    pub fn new(
+        wasm_layers: Vec<WasmLayer>,
+        platform: Platform,
+        labels: HashMap<String, String>,
+        id: String,
+        kind: ProcessKind,
+        linux_fallback: bool,
+        cfg: &InstanceConfig,
+    ) -> Self {
+        let core_dump_dir = open_core_dump_dir(cfg, &id);
         Self(Arc::new(InnerExecutor {
             ty: Default::default(),
             wasm_layers,
             platform,
+            labels,
             id,
+            kind,
+            linux_fallback,
+            engine_options: cfg.config.engine.clone(),
+            core_dump_dir,
         }))
     }
 
     fn ctx<'a>(&'a self, spec: &'a Spec) -> WasiContext<'a> {
         let wasm_layers = &self.0.wasm_layers;
         let platform = &self.0.platform;
+        let labels = &self.0.labels;
+        let checkpoint = match &self.0.kind {
+            ProcessKind::Init { checkpoint, .. } => checkpoint.as_deref(),
+            ProcessKind::Exec { .. } => None,
+        };
         WasiContext {
             spec,
             wasm_layers,
             platform,
+            labels,
+            checkpoint,
+            in_process: None,
+            engine_options: Some(&self.0.engine_options),
+            core_dump_dir: self.0.core_dump_dir.as_ref(),
             id: self.0.id.clone(),
         }
     }
@@ -136,6 +326,24 @@ impl<S: Shim> Executor<S> {
     }
 }
 
+/// Make the terminal the process uses as stdin its controlling terminal,
+/// in a new session, so that e.g. `Ctrl+C` interrupts it.
+fn set_controlling_terminal() -> std::io::Result<()> {
+    let is_session_leader = unsafe { libc::getsid(0) == libc::getpid() };
+    if !is_session_leader && unsafe { libc::setsid() } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn linux_fallback_disabled_message() -> String {
+    "the entrypoint is a native executable, and running native Linux containers is disabled"
+        .to_string()
+}
+
 fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
     if let Source::Oci(_) = ctx.entrypoint().source {
         bail!("the entry point contains wasm layers")
@@ -146,16 +354,60 @@ fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
         .arg0
         .context("no entrypoint provided")?
         .resolve_in_path()
-        .find_map(|p| -> Option<PathBuf> {
-            let mode = p.metadata().ok()?.permissions().mode();
-            (mode & 0o001 != 0).then_some(p)
-        })
+        .find(|p| is_executable(p))
         .context("entrypoint not found")?;
 
+    is_native_executable(&executable)
+}
+
+/// Resolve the entrypoint of the container from outside of it, and check if it is a
+/// native executable.
+///
+/// This lets the shim reject a container before creating it. The entrypoint is
+/// resolved in the container's rootfs using the `PATH` of the process, but symlinks
+/// are followed on the host, so this is only a best effort check; the executor
+/// enforces it from within the container.
+pub(crate) fn has_native_entrypoint(spec: &Spec) -> bool {
+    native_entrypoint(spec).is_some()
+}
+
+fn native_entrypoint(spec: &Spec) -> Option<()> {
+    let rootfs = spec.root().as_ref()?.path();
+    let process = spec.process().as_ref()?;
+    let arg0 = Path::new(process.args().as_ref()?.first()?);
+
+    let candidates: Vec<PathBuf> = if arg0.components().count() > 1 {
+        vec![process.cwd().join(arg0)]
+    } else {
+        let path = process
+            .env()
+            .iter()
+            .flatten()
+            .find_map(|env| env.strip_prefix("PATH="))
+            .unwrap_or(DEFAULT_PATH);
+        std::env::split_paths(path)
+            .map(|dir| dir.join(arg0))
+            .collect()
+    };
+
+    let executable = candidates
+        .into_iter()
+        .map(|p| rootfs.join(p.strip_prefix("/").unwrap_or(&p)))
+        .find(|p| p.is_file() && is_executable(p))?;
+
+    is_native_executable(&executable).ok()
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.permissions().mode() & 0o001 != 0)
+}
+
+fn is_native_executable(path: &Path) -> Result<()> {
     // check the shebang and ELF magic number
     // https://en.wikipedia.org/wiki/Executable_and_Linkable_Format#File_header
     let mut buffer = [0; 4];
-    File::open(executable)?.read_exact(&mut buffer)?;
+    File::open(path)?.read_exact(&mut buffer)?;
 
     match buffer {
         [0x7f, 0x45, 0x4c, 0x46] => Ok(()), // ELF magic number
@@ -163,3 +415,64 @@ fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
         _ => bail!("not a valid script or elf file"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::Permissions;
+
+    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(rootfs: &Path, args: &[&str], env: &[&str]) -> Result<Spec> {
+        Ok(SpecBuilder::default()
+            .root(RootBuilder::default().path(rootfs).build()?)
+            .process(
+                ProcessBuilder::default()
+                    .cwd("/app")
+                    .args(args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+                    .env(env.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    fn write_executable(path: &Path, content: &[u8]) -> Result<()> {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, content)?;
+        std::fs::set_permissions(path, Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_native_entrypoint() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rootfs = dir.path();
+        write_executable(&rootfs.join("bin/server"), b"\x7fELF\x02\x01\x01")?;
+        write_executable(&rootfs.join("app/run.sh"), b"#!/bin/sh\n")?;
+        write_executable(&rootfs.join("app/module.wasm"), b"\0asm\x01\0\0\0")?;
+        std::fs::write(rootfs.join("app/data.sh"), b"#!/bin/sh\n")?;
+
+        // resolved in the default PATH, or in the PATH of the process
+        assert!(has_native_entrypoint(&spec(rootfs, &["server"], &[])?));
+        assert!(!has_native_entrypoint(&spec(
+            rootfs,
+            &["server"],
+            &["PATH=/usr/bin"]
+        )?));
+        // resolved relative to the cwd of the process
+        assert!(has_native_entrypoint(&spec(rootfs, &["./run.sh"], &[])?));
+        assert!(has_native_entrypoint(&spec(rootfs, &["/app/run.sh"], &[])?));
+        // not executable
+        assert!(!has_native_entrypoint(&spec(rootfs, &["./data.sh"], &[])?));
+        // not native
+        assert!(!has_native_entrypoint(&spec(
+            rootfs,
+            &["module.wasm"],
+            &["PATH=/app"]
+        )?));
+        assert!(!has_native_entrypoint(&spec(rootfs, &["missing"], &[])?));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+//! Running wasm containers inside the shim process.
+//!
+//! Instead of setting up a container, the module runs on a thread of the shim,
+//! relying only on the wasm sandbox for isolation. This makes starting a task
+//! much cheaper, at the cost of the isolation and features a container provides,
+//! like `exec`, pausing, or per-task cgroups.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use containerd_shimkit::AmbientRuntime;
+use containerd_shimkit::sandbox::sync::WaitableCell;
+use containerd_shimkit::sandbox::{EngineOptions, InstanceConfig, Termination};
+use oci_spec::image::Platform;
+use oci_spec::runtime::Spec;
+use tokio::sync::{mpsc, oneshot};
+
+use super::executor::open_core_dump_dir;
+use crate::sandbox::context::{CoreDumpDir, InProcessContext, Stdio, WasiContext, WasmLayer};
+use crate::sandbox::{Sandbox, SignalHandle, is_abnormal, termination};
+use crate::shim::Shim;
+
+type ExitCode = WaitableCell<(u32, DateTime<Utc>)>;
+type TerminationCell = Arc<OnceLock<Termination>>;
+type CheckpointRequest = oneshot::Sender<Result<Vec<u8>>>;
+
+/// A wasm container running on a thread of the shim process.
+pub(crate) struct InProcess {
+    start: Mutex<Option<oneshot::Sender<(ExitCode, TerminationCell)>>>,
+    signals: SignalHandle,
+    checkpoints: mpsc::UnboundedSender<CheckpointRequest>,
+}
+
+/// Everything the thread running the container needs.
+struct Task {
+    id: String,
+    spec: Spec,
+    modules: Vec<WasmLayer>,
+    platform: Platform,
+    labels: HashMap<String, String>,
+    checkpoint: Option<Vec<u8>>,
+    in_process: InProcessContext,
+    engine_options: EngineOptions,
+    core_dump_dir: Option<CoreDumpDir>,
+}
+
+impl InProcess {
+    pub async fn new<S: Shim>(
+        id: String,
+        cfg: &InstanceConfig,
+        mut spec: Spec,
+        modules: Vec<WasmLayer>,
+        platform: Platform,
+        labels: HashMap<String, String>,
+        checkpoint: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        spec.canonicalize_rootfs(&cfg.bundle)?;
+        let rootfs = spec.root().as_ref().context("rootfs is not set")?.path();
+
+        let in_process = InProcessContext {
+            rootfs: rootfs.clone(),
+            stdio: Stdio {
+                stdin: cfg.open_stdin().ok(),
+                stdout: cfg.open_stdout().ok(),
+                stderr: cfg.open_stderr().ok(),
+            },
+        };
+        let core_dump_dir = open_core_dump_dir(cfg, &id);
+        let task = Task {
+            id,
+            spec,
+            modules,
+            platform,
+            labels,
+            checkpoint,
+            in_process,
+            engine_options: cfg.config.engine.clone(),
+            core_dump_dir,
+        };
+
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (start_tx, start_rx) = oneshot::channel();
+        let (checkpoints_tx, checkpoints_rx) = mpsc::unbounded_channel();
+
+        // The sandbox is not necessarily `Send`, so it lives on its own thread
+        // for the whole lifetime of the container.
+        std::thread::spawn(move || task.run::<S>(ready_tx, start_rx, checkpoints_rx));
+
+        let signals = ready_rx
+            .await
+            .context("container thread exited unexpectedly")??;
+
+        Ok(Self {
+            start: Mutex::new(Some(start_tx)),
+            signals,
+            checkpoints: checkpoints_tx,
+        })
+    }
+
+    /// Start running the container, setting `exit_code` and `termination` once it finishes.
+    pub fn start(&self, exit_code: ExitCode, termination: TerminationCell) -> Result<()> {
+        self.start
+            .lock()
+            .unwrap()
+            .take()
+            .context("container already started")?
+            .send((exit_code, termination))
+            .ok()
+            .context("container thread exited unexpectedly")
+    }
+
+    /// Deliver a signal to the container.
+    /// The sandbox interrupts the guest, unless it handles the signal, e.g., by draining an HTTP server.
+    pub fn kill(&self, signal: u32) {
+        if signal != 0 {
+            (self.signals)(signal as i32);
+        }
+    }
+
+    pub async fn checkpoint(&self) -> Result<Vec<u8>> {
+        let (tx, rx) = oneshot::channel();
+        self.checkpoints
+            .send(tx)
+            .ok()
+            .context("container is not running")?;
+        rx.await
+            .context("container exited before taking a checkpoint")?
+    }
+
+    pub fn delete(&self) {
+        // Dropping the start sender stops the thread of a container that never started
+        self.start.lock().unwrap().take();
+    }
+}
+
+impl Task {
+    fn ctx(&self) -> WasiContext<'_> {
+        WasiContext {
+            spec: &self.spec,
+            wasm_layers: &self.modules,
+            platform: &self.platform,
+            labels: &self.labels,
+            checkpoint: self.checkpoint.as_deref(),
+            in_process: Some(&self.in_process),
+            engine_options: Some(&self.engine_options),
+            core_dump_dir: self.core_dump_dir.as_ref(),
+            id: self.id.clone(),
+        }
+    }
+
+    fn run<S: Shim>(
+        self,
+        ready: oneshot::Sender<Result<SignalHandle>>,
+        start: oneshot::Receiver<(ExitCode, TerminationCell)>,
+        checkpoints: mpsc::UnboundedReceiver<CheckpointRequest>,
+    ) {
+        let ctx = self.ctx();
+        let sandbox = S::Sandbox::default();
+
+        let result = sandbox
+            .can_handle(&ctx)
+            .block_on()
+            .context("can't run container in-process. Note: arg0 must be a path to a Wasm file")
+            .and_then(|_| {
+                // a guest that can't be interrupted would outlive its container in the shim
+                sandbox
+                    .signal_handle()
+                    .context("can't run container in-process, as the engine can't interrupt it")
+            });
+        let failed = result.is_err();
+        if ready.send(result).is_err() || failed {
+            return;
+        }
+
+        let Ok((exit_code, termination_cell)) = start.blocking_recv() else {
+            log::debug!("container {} deleted before starting", self.id);
+            return;
+        };
+        // make sure we have an exit code by the time we finish (even if there's a panic)
+        let _guard = exit_code.clone().set_guard_with(|| (137, Utc::now()));
+
+        log::info!("calling start function");
+        let termination = async {
+            tokio::select! {
+                result = sandbox.run_wasi(&ctx) => termination(result),
+                _ = serve_checkpoints(&sandbox, checkpoints) => unreachable!(),
+            }
+        }
+        .block_on();
+
+        if is_abnormal(&termination) {
+            log::error!("error running start function: {termination}");
+            if let Some(mut stderr) = self.in_process.stdio.stderr.as_ref() {
+                let _ = writeln!(stderr, "{termination}");
+            }
+        }
+
+        let code = termination.exit_code();
+        let _ = termination_cell.set(termination);
+        let _ = exit_code.set((code, Utc::now()));
+    }
+}
+
+async fn serve_checkpoints(
+    sandbox: &impl Sandbox,
+    mut requests: mpsc::UnboundedReceiver<CheckpointRequest>,
+) {
+    while let Some(request) = requests.recv().await {
+        log::info!("taking checkpoint");
+        let _ = request.send(sandbox.checkpoint().await);
+    }
+    std::future::pending().await
+}
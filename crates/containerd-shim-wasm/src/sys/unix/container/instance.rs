@@ -1,36 +1,121 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use chrono::{DateTime, Utc};
 use containerd_client::tonic::async_trait;
+use containerd_shim::Error as ShimError;
+use containerd_shim::monitor::{Topic, monitor_subscribe};
 use containerd_shimkit::sandbox::sync::WaitableCell;
+#[cfg(feature = "opentelemetry")]
+use containerd_shimkit::sandbox::telemetry::{self, MetricsRelay};
 use containerd_shimkit::sandbox::{
-    Error as SandboxError, Instance as SandboxInstance, InstanceConfig,
+    Error as SandboxError, ExecConfig, Feature, Instance as SandboxInstance, InstanceConfig,
+    Termination,
 };
 use containerd_shimkit::set_logger_kv;
 use libcontainer::container::builder::ContainerBuilder;
 use libcontainer::syscall::syscall::SyscallType;
+use nix::sys::signal::{Signal, kill};
 use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
 use oci_spec::image::Platform;
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{LinuxResources, Spec};
 use tokio::sync::OnceCell;
 
+use super::checkpoint::{self, SNAPSHOT_FILE};
 use super::container::Container;
+use super::in_process::InProcess;
 use crate::containerd;
-use crate::sandbox::context::{WasmLayer, pod_id};
+use crate::sandbox::context::{RuntimeContext, WasiContext, WasmLayer};
 use crate::shim::{Compiler, Shim};
-use crate::sys::container::executor::Executor;
+#[cfg(feature = "opentelemetry")]
+use crate::sys::container::executor::METRICS_SOCKET;
+use crate::sys::container::executor::{
+    Executor, ProcessKind, ProcessTelemetry, has_native_entrypoint, is_linux_fallback_disabled,
+};
 use crate::sys::pid_fd::PidFd;
 
 pub struct Instance<S: Shim> {
     exit_code: WaitableCell<(u32, DateTime<Utc>)>,
-    container: Container,
+    termination: Arc<OnceLock<Termination>>,
+    runner: Runner,
     id: String,
+    cfg: InstanceConfig,
+    modules: Vec<WasmLayer>,
+    platform: Platform,
+    labels: Labels,
+    execs: Mutex<HashMap<String, ExecProcess>>,
+    _telemetry: InstanceTelemetry,
     _phantom: PhantomData<S>,
 }
 
+type Labels = HashMap<String, String>;
+
+/// Name of the file in the bundle where the container process writes why it terminated.
+const TERMINATION_FILE: &str = "termination.json";
+
+/// The telemetry the shim exports for the container processes, see [`ProcessTelemetry`].
+#[derive(Default)]
+struct InstanceTelemetry {
+    /// Where the container processes relay their metrics.
+    #[cfg(feature = "opentelemetry")]
+    _metrics_relay: Option<MetricsRelay>,
+}
+
+impl InstanceTelemetry {
+    /// Binds the metrics relay in `bundle`, if the shim exports telemetry.
+    fn bind(bundle: &Path) -> std::io::Result<Self> {
+        #[cfg(feature = "opentelemetry")]
+        let telemetry = Self {
+            _metrics_relay: match telemetry::enabled() {
+                true => Some(MetricsRelay::bind(bundle, METRICS_SOCKET)?),
+                false => None,
+            },
+        };
+        #[cfg(not(feature = "opentelemetry"))]
+        let telemetry = {
+            let _ = bundle;
+            Self {}
+        };
+        Ok(telemetry)
+    }
+}
+
+/// The trace context of the current span, for the container processes to carry on.
+fn traceparent() -> Option<String> {
+    #[cfg(feature = "opentelemetry")]
+    return telemetry::traceparent();
+    #[cfg(not(feature = "opentelemetry"))]
+    None
+}
+
+/// How the instance runs its wasm module.
+enum Runner {
+    /// In a container, the default.
+    Container(Container),
+    /// Inside the shim process, see [`super::in_process`].
+    InProcess(InProcess),
+}
+
+/// A process started in the container with `exec`.
+#[derive(Clone, Default)]
+struct ExecProcess {
+    pid: Option<i32>,
+    exit_code: WaitableCell<(u32, DateTime<Utc>)>,
+    termination: Arc<OnceLock<Termination>>,
+}
+
 #[async_trait]
 trait OciClient {
-    async fn load_modules(&self, id: &str) -> Result<(Vec<WasmLayer>, Platform), SandboxError>;
+    async fn load_modules(
+        &self,
+        id: &str,
+        precompile: bool,
+    ) -> Result<(Vec<WasmLayer>, Platform, Labels), SandboxError>;
 }
 
 struct EngineOciClient<P: Compiler> {
@@ -42,13 +127,17 @@ struct EngineOciClient<P: Compiler> {
 
 #[async_trait]
 impl<P: Compiler> OciClient for EngineOciClient<P> {
-    async fn load_modules(&self, id: &str) -> Result<(Vec<WasmLayer>, Platform), SandboxError> {
+    async fn load_modules(
+        &self,
+        id: &str,
+        precompile: bool,
+    ) -> Result<(Vec<WasmLayer>, Platform, Labels), SandboxError> {
         self.client
             .load_modules(
                 id,
                 self.name,
                 self.supported_layer_types,
-                self.precompiler.as_ref(),
+                self.precompiler.as_ref().filter(|_| precompile),
             )
             .await
     }
@@ -59,6 +148,16 @@ static OCI_CLIENT: OnceCell<Box<dyn OciClient + Send + Sync + 'static>> = OnceCe
 impl<S: Shim> SandboxInstance for Instance<S> {
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Info"))]
     async fn new(id: String, cfg: &InstanceConfig) -> Result<Self, SandboxError> {
+        let checkpoint = match &cfg.checkpoint {
+            Some(_) if !S::supports_checkpoint() => {
+                return Err(
+                    ShimError::Unimplemented("restore is not supported".to_string()).into(),
+                );
+            }
+            Some(path) => Some(std::fs::read(path.join(SNAPSHOT_FILE))?),
+            None => None,
+        };
+
         let oci_client = OCI_CLIENT
             .get_or_try_init(|| async {
                 let client =
@@ -76,29 +175,110 @@ impl<S: Shim> SandboxInstance for Instance<S> {
             .await?;
 
         // check if container is OCI image with wasm layers and attempt to read the module
-        let (modules, platform) = oci_client
-            .load_modules(&id)
+        let (modules, platform, labels) = oci_client
+            .load_modules(&id, !cfg.config.disable_precompilation)
             .await
             .unwrap_or_else(|e| {
                 log::warn!("Error obtaining wasm layers for container {id}.  Will attempt to use files inside container image. Error: {e}");
-                (vec![], Platform::default())
+                (vec![], Platform::default(), Labels::default())
             });
 
+        let mut spec = Spec::load(cfg.bundle.join("config.json"))?;
+        // Running in-process weakens the isolation, so only the operator can request it
+        if cfg.config.in_process {
+            cfg.config.ensure_allowed(Feature::InProcess)?;
+            if !S::supports_in_process() {
+                return Err(ShimError::Unimplemented(
+                    "running in-process is not supported".to_string(),
+                )
+                .into());
+            }
+            log::info!("running instance {id} in-process");
+            let in_process = InProcess::new::<S>(
+                id.clone(),
+                cfg,
+                spec,
+                modules.clone(),
+                platform.clone(),
+                labels.clone(),
+                checkpoint,
+            )
+            .await?;
+            return Ok(Self::with_runner(
+                id,
+                cfg,
+                modules,
+                platform,
+                labels,
+                Runner::InProcess(in_process),
+                InstanceTelemetry::default(),
+            ));
+        }
+
+        // The executor refuses to run native executables from within the container,
+        // but rejecting the container here lets us tell why.
+        if modules.is_empty() && is_linux_fallback_disabled(cfg, &spec) {
+            spec.canonicalize_rootfs(&cfg.bundle)?;
+            if has_native_entrypoint(&spec) {
+                return Err(SandboxError::PermissionDenied(format!(
+                    "entrypoint {:?} is a native executable, and running native Linux containers is disabled",
+                    spec.process()
+                        .as_ref()
+                        .and_then(|p| p.args().as_ref()?.first()),
+                )));
+            }
+        }
+
+        // The container processes connect to the relay when they're created
+        let telemetry = InstanceTelemetry::bind(&cfg.bundle)?;
+
         let container = Container::build(
-            |(id, cfg, modules, platform)| {
+            |(id, cfg, modules, platform, labels, checkpoint, traceparent)| {
                 let source_spec_path = cfg.bundle.join("config.json");
                 let spec = Spec::load(source_spec_path)?;
-                let pod_id = pod_id(&spec);
+                let ctx = WasiContext {
+                    spec: &spec,
+                    wasm_layers: &modules,
+                    platform: &platform,
+                    labels: &labels,
+                    checkpoint: None,
+                    in_process: None,
+                    engine_options: None,
+                    core_dump_dir: None,
+                    id: id.clone(),
+                };
+                cfg.config.set_log_level();
+                let linux_fallback = !is_linux_fallback_disabled(&cfg, &spec);
 
-                match pod_id {
+                match ctx.pod_id() {
                     Some(pod_id) => set_logger_kv([("instance", id.as_str()), ("pod", pod_id)]),
                     None => set_logger_kv([("instance", id.as_str())]),
                 };
 
                 let rootdir = cfg.determine_rootdir(S::name())?;
+                // The container process inherits these files, as it doesn't `exec`
+                let termination = File::create(cfg.bundle.join(TERMINATION_FILE))?;
+                let checkpoint_listener = match S::supports_checkpoint() {
+                    true => Some(checkpoint::bind(&cfg.bundle)?),
+                    false => None,
+                };
+                let telemetry = ProcessTelemetry::connect(&cfg.bundle, traceparent);
 
                 let mut builder = ContainerBuilder::new(id.clone(), SyscallType::Linux)
-                    .with_executor(Executor::<S>::new(modules, platform, id))
+                    .with_executor(Executor::<S>::new(
+                        modules,
+                        platform,
+                        labels,
+                        id,
+                        ProcessKind::Init {
+                            checkpoint,
+                            termination,
+                            checkpoint_listener,
+                            telemetry,
+                        },
+                        linux_fallback,
+                        &cfg,
+                    ))
                     .with_root_path(rootdir.clone())?;
 
                 if let Ok(f) = cfg.open_stdin() {
@@ -119,15 +299,40 @@ impl<S: Shim> SandboxInstance for Instance<S> {
 
                 Ok(container)
             },
-            (id.clone(), cfg.clone(), modules, platform),
+            (
+                id.clone(),
+                cfg.clone(),
+                modules.clone(),
+                platform.clone(),
+                labels.clone(),
+                checkpoint,
+                traceparent(),
+            ),
         )?;
 
-        Ok(Self {
+        Ok(Self::with_runner(
             id,
-            exit_code: WaitableCell::new(),
-            container,
-            _phantom: Default::default(),
-        })
+            cfg,
+            modules,
+            platform,
+            labels,
+            Runner::Container(container),
+            telemetry,
+        ))
+    }
+
+    /// The OCI hooks are run by libcontainer, in the right namespaces, for containers.
+    /// The shim runs them for containers running in-process.
+    fn runs_oci_hooks(&self) -> bool {
+        matches!(self.runner, Runner::Container(_))
+    }
+
+    /// The pid of the container's init process, which exists once the container is created.
+    fn pid(&self) -> Option<u32> {
+        match &self.runner {
+            Runner::Container(container) => container.pid().ok().map(|pid| pid as u32),
+            Runner::InProcess(_) => Some(std::process::id()),
+        }
     }
 
     /// Start the instance
@@ -137,35 +342,42 @@ impl<S: Shim> SandboxInstance for Instance<S> {
     async fn start(&self) -> Result<u32, SandboxError> {
         log::info!("starting instance: {}", self.id);
         // make sure we have an exit code by the time we finish (even if there's a panic)
+        let container = match &self.runner {
+            Runner::Container(container) => container,
+            Runner::InProcess(in_process) => {
+                in_process.start(self.exit_code.clone(), self.termination.clone())?;
+                return Ok(std::process::id());
+            }
+        };
+
         let guard = self.exit_code.clone().set_guard_with(|| (137, Utc::now()));
 
-        let pid = self.container.pid()?;
+        let pid = container.pid()?;
 
         // Use a pidfd FD so that we can wait for the process to exit asynchronously.
         // This should be created BEFORE calling container.start() to ensure we never
         // miss the SIGCHLD event.
         let pidfd = PidFd::new(pid)?;
 
-        self.container.start()?;
+        container.start()?;
 
         let exit_code = self.exit_code.clone();
+        let termination = self.termination.clone();
+        let termination_path = self.cfg.bundle.join(TERMINATION_FILE);
         tokio::spawn(async move {
             // move the exit code guard into this task
             let _guard = guard;
-
-            let status = match pidfd.wait().await {
-                Ok(WaitStatus::Exited(_, status)) => status,
-                Ok(WaitStatus::Signaled(_, sig, _)) => 128 + sig as i32,
-                Ok(res) => {
-                    log::error!("waitpid unexpected result: {res:?}");
-                    137
-                }
-                Err(e) => {
-                    log::error!("waitpid failed: {e}");
-                    137
-                }
-            } as u32;
-            let _ = exit_code.set((status, Utc::now()));
+            let status = wait_status(pidfd).await;
+            let code = exit_code_of(status.as_ref());
+            // The container process reports why it terminated, unless it didn't get the
+            // chance to, e.g., because it was killed.
+            let reported = std::fs::read(termination_path)
+                .ok()
+                .and_then(|report| serde_json::from_slice(&report).ok());
+            if let Some(reason) = reported.or(status) {
+                let _ = termination.set(reason);
+            }
+            let _ = exit_code.set((code, Utc::now()));
         });
 
         Ok(pid as _)
@@ -175,7 +387,58 @@ impl<S: Shim> SandboxInstance for Instance<S> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     async fn kill(&self, signal: u32) -> Result<(), SandboxError> {
         log::info!("sending signal {signal} to instance: {}", self.id);
-        self.container.kill(signal)?;
+        match &self.runner {
+            Runner::Container(container) => container.kill(signal)?,
+            Runner::InProcess(in_process) => in_process.kill(signal),
+        }
+        Ok(())
+    }
+
+    /// Update the resource limits of the instance by rewriting its cgroup
+    /// Limits that the engines enforce themselves, like the guest memory limit,
+    /// are still the ones the container was started with.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn update(
+        &self,
+        _pid: Option<u32>,
+        resources: &LinuxResources,
+    ) -> Result<(), SandboxError> {
+        log::info!("updating resources of instance: {}", self.id);
+        self.container()?.update(resources.clone())?;
+        Ok(())
+    }
+
+    /// Pause the instance by freezing its cgroup
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn pause(&self, _pid: u32) -> Result<(), SandboxError> {
+        log::info!("pausing instance: {}", self.id);
+        self.container()?.pause()?;
+        Ok(())
+    }
+
+    /// Resume the instance by thawing its cgroup
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn resume(&self, _pid: u32) -> Result<(), SandboxError> {
+        log::info!("resuming instance: {}", self.id);
+        self.container()?.resume()?;
+        Ok(())
+    }
+
+    /// Checkpoint the instance by requesting a snapshot from the running container,
+    /// and writing it to the checkpoint image at `path`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn checkpoint(&self, path: &Path) -> Result<(), SandboxError> {
+        if !S::supports_checkpoint() {
+            return Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into());
+        }
+        log::info!("checkpointing instance: {}", self.id);
+        if let Runner::InProcess(in_process) = &self.runner {
+            let snapshot = in_process.checkpoint().await?;
+            std::fs::write(path.join(SNAPSHOT_FILE), snapshot)?;
+            return Ok(());
+        }
+        let snapshot = checkpoint::request(&self.cfg.bundle).await?;
+        std::fs::write(path.join(SNAPSHOT_FILE), snapshot)?;
         Ok(())
     }
 
@@ -184,7 +447,10 @@ impl<S: Shim> SandboxInstance for Instance<S> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     async fn delete(&self) -> Result<(), SandboxError> {
         log::info!("deleting instance: {}", self.id);
-        self.container.delete()?;
+        match &self.runner {
+            Runner::Container(container) => container.delete()?,
+            Runner::InProcess(in_process) => in_process.delete(),
+        }
         Ok(())
     }
 
@@ -195,4 +461,242 @@ impl<S: Shim> SandboxInstance for Instance<S> {
     async fn wait(&self) -> (u32, DateTime<Utc>) {
         *self.exit_code.wait().await
     }
+
+    /// Returns why the instance terminated, as reported by the container process,
+    /// or from its wait status.
+    async fn termination(&self) -> Option<Termination> {
+        self.termination.get().cloned()
+    }
+
+    /// Start an additional process in the container.
+    /// The process joins the container's namespaces and cgroup, and runs the
+    /// entrypoint in its process spec, e.g., `#func` to call a different export
+    /// of the container's module.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn exec(&self, exec_id: &str, cfg: &ExecConfig) -> Result<u32, SandboxError> {
+        log::info!("exec process {exec_id} in instance: {}", self.id);
+        let container = self.container()?;
+        let ExecProcess {
+            exit_code,
+            termination,
+            ..
+        } = self.exec_process(exec_id);
+        // make sure we have an exit code by the time we finish (even if there's a panic)
+        let guard = exit_code.clone().set_guard_with(|| (137, Utc::now()));
+
+        let process_path = self.process_path(exec_id);
+        serde_json::to_writer(File::create(&process_path)?, &cfg.process)?;
+        let termination_path = self.exec_termination_path(exec_id);
+
+        // The process runs as soon as it's spawned, so we need to subscribe to
+        // the reaper BEFORE spawning it to ensure we never miss its exit.
+        let subs = monitor_subscribe(Topic::Pid)?;
+
+        let pid = container.exec(
+            |(
+                id,
+                cfg,
+                exec_cfg,
+                process_path,
+                termination_path,
+                modules,
+                platform,
+                labels,
+                traceparent,
+            )| {
+                let rootdir = cfg.determine_rootdir(S::name())?;
+                let spec = Spec::load(cfg.bundle.join("config.json"))?;
+                let linux_fallback = !is_linux_fallback_disabled(&cfg, &spec);
+                // The process inherits this file, as it doesn't `exec`
+                let termination = File::create(termination_path)?;
+                let telemetry = ProcessTelemetry::connect(&cfg.bundle, traceparent);
+
+                let mut builder = ContainerBuilder::new(id.clone(), SyscallType::Linux)
+                    .with_executor(Executor::<S>::new(
+                        modules,
+                        platform,
+                        labels,
+                        id,
+                        ProcessKind::Exec {
+                            termination,
+                            telemetry,
+                        },
+                        linux_fallback,
+                        &cfg,
+                    ))
+                    .with_root_path(rootdir)?;
+
+                if let Ok(f) = exec_cfg.open_stdin() {
+                    builder = builder.with_stdin(f);
+                }
+                if let Ok(f) = exec_cfg.open_stdout() {
+                    builder = builder.with_stdout(f);
+                }
+                if let Ok(f) = exec_cfg.open_stderr() {
+                    builder = builder.with_stderr(f);
+                }
+
+                let pid = builder
+                    .as_tenant()
+                    .with_process(Some(process_path))
+                    .as_sibling(true)
+                    .with_detach(true)
+                    .build()?;
+
+                Ok(pid.as_raw())
+            },
+            (
+                self.id.clone(),
+                self.cfg.clone(),
+                cfg.clone(),
+                process_path,
+                termination_path.clone(),
+                self.modules.clone(),
+                self.platform.clone(),
+                self.labels.clone(),
+                traceparent(),
+            ),
+        )?;
+
+        let pidfd = PidFd::with_subscription(pid, subs)?;
+
+        if let Some(process) = self.execs.lock().unwrap().get_mut(exec_id) {
+            process.pid = Some(pid);
+        }
+
+        tokio::spawn(async move {
+            // move the exit code guard into this task
+            let _guard = guard;
+            let status = wait_status(pidfd).await;
+            let code = exit_code_of(status.as_ref());
+            let reported = std::fs::read(termination_path)
+                .ok()
+                .and_then(|report| serde_json::from_slice(&report).ok());
+            if let Some(reason) = reported.or(status) {
+                let _ = termination.set(reason);
+            }
+            let _ = exit_code.set((code, Utc::now()));
+        });
+
+        Ok(pid as _)
+    }
+
+    /// Send a signal to a process started with `exec`
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn kill_exec(&self, exec_id: &str, signal: u32) -> Result<(), SandboxError> {
+        log::info!(
+            "sending signal {signal} to exec process {exec_id} in instance: {}",
+            self.id
+        );
+        let pid = self
+            .exec_process(exec_id)
+            .pid
+            .ok_or_else(|| SandboxError::NotFound(exec_id.to_string()))?;
+        let signal = Signal::try_from(signal as i32)?;
+        kill(Pid::from_raw(pid), signal)?;
+        Ok(())
+    }
+
+    /// Delete any reference to a process started with `exec`
+    /// This is called after the process has exited.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn delete_exec(&self, exec_id: &str) -> Result<(), SandboxError> {
+        log::info!("deleting exec process {exec_id} in instance: {}", self.id);
+        for path in [
+            self.process_path(exec_id),
+            self.exec_termination_path(exec_id),
+        ] {
+            match std::fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        self.execs.lock().unwrap().remove(exec_id);
+        Ok(())
+    }
+
+    /// Waits for a process started with `exec` to finish and returns its exit code
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    async fn wait_exec(&self, exec_id: &str) -> (u32, DateTime<Utc>) {
+        *self.exec_process(exec_id).exit_code.wait().await
+    }
+
+    /// Returns why a process started with `exec` terminated, as reported by the process,
+    /// or from its wait status.
+    async fn exec_termination(&self, exec_id: &str) -> Option<Termination> {
+        self.exec_process(exec_id).termination.get().cloned()
+    }
+}
+
+impl<S: Shim> Instance<S> {
+    fn with_runner(
+        id: String,
+        cfg: &InstanceConfig,
+        modules: Vec<WasmLayer>,
+        platform: Platform,
+        labels: Labels,
+        runner: Runner,
+        telemetry: InstanceTelemetry,
+    ) -> Self {
+        Self {
+            id,
+            exit_code: WaitableCell::new(),
+            termination: Arc::default(),
+            runner,
+            cfg: cfg.clone(),
+            modules,
+            platform,
+            labels,
+            execs: Mutex::default(),
+            _telemetry: telemetry,
+            _phantom: Default::default(),
+        }
+    }
+
+    /// Returns the container of the instance, failing for instances running in-process.
+    fn container(&self) -> Result<&Container, SandboxError> {
+        match &self.runner {
+            Runner::Container(container) => Ok(container),
+            Runner::InProcess(_) => Err(ShimError::Unimplemented(
+                "not supported for instances running in-process".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    /// Returns the process started with `exec` with the given id, creating an
+    /// entry for it if it doesn't exist yet.
+    fn exec_process(&self, exec_id: &str) -> ExecProcess {
+        let mut execs = self.execs.lock().unwrap();
+        execs.entry(exec_id.to_string()).or_default().clone()
+    }
+
+    /// Path to the file with the OCI process spec of a process started with `exec`.
+    fn process_path(&self, exec_id: &str) -> PathBuf {
+        self.cfg.bundle.join(format!("exec-{exec_id}.json"))
+    }
+
+    /// Path to the file where a process started with `exec` writes why it terminated.
+    fn exec_termination_path(&self, exec_id: &str) -> PathBuf {
+        self.cfg.bundle.join(format!("termination-{exec_id}.json"))
+    }
+}
+
+async fn wait_status(pidfd: PidFd) -> Option<Termination> {
+    match pidfd.wait().await {
+        Ok(WaitStatus::Exited(_, code)) => Some(Termination::Exited { code }),
+        Ok(WaitStatus::Signaled(_, sig, _)) => Some(Termination::Signaled { signal: sig as i32 }),
+        Ok(res) => {
+            log::error!("waitpid unexpected result: {res:?}");
+            None
+        }
+        Err(e) => {
+            log::error!("waitpid failed: {e}");
+            None
+        }
+    }
+}
+
+fn exit_code_of(status: Option<&Termination>) -> u32 {
+    status.map_or(137, Termination::exit_code)
 }
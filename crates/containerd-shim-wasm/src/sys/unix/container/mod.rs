@@ -1,5 +1,8 @@
 #[allow(clippy::module_inception)]
 mod container;
 
+mod checkpoint;
+
 mod executor;
+mod in_process;
 pub mod instance;
@@ -9,26 +9,43 @@ use nix::unistd::Pid;
 use tokio::io::unix::AsyncFd;
 
 pub(super) struct PidFd {
-    fd: OwnedFd,
+    fd: Option<OwnedFd>,
     pid: pid_t,
     subs: Subscription,
 }
 
 impl PidFd {
     pub(super) fn new(pid: impl Into<pid_t>) -> anyhow::Result<Self> {
+        let subs = monitor_subscribe(Topic::Pid)?;
+        Self::with_subscription(pid, subs)
+    }
+
+    /// Like `new`, but using a subscription to the containerd-shim reaper that was
+    /// created before the process was spawned.
+    /// This is needed for processes that are already running when spawned, as they
+    /// might exit and be reaped before we get a chance to open the pidfd.
+    pub(super) fn with_subscription(
+        pid: impl Into<pid_t>,
+        subs: Subscription,
+    ) -> anyhow::Result<Self> {
         use libc::{PIDFD_NONBLOCK, SYS_pidfd_open, syscall};
         let pid = pid.into();
-        let subs = monitor_subscribe(Topic::Pid)?;
         let pidfd = unsafe { syscall(SYS_pidfd_open, pid, PIDFD_NONBLOCK) };
-        if pidfd == -1 {
-            return Err(std::io::Error::last_os_error().into());
-        }
-        let fd = unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) };
+        let fd = match pidfd {
+            -1 if Errno::last() == Errno::ESRCH => None,
+            -1 => return Err(std::io::Error::last_os_error().into()),
+            fd => Some(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+        };
         Ok(Self { fd, pid, subs })
     }
 
     pub(super) async fn wait(self) -> std::io::Result<WaitStatus> {
-        let fd = AsyncFd::new(self.fd)?;
+        let Some(fd) = self.fd else {
+            // The process has already been reaped by the containerd-shim reaper.
+            let status = try_wait_pid(self.pid, self.subs).await?;
+            return Ok(WaitStatus::Exited(Pid::from_raw(self.pid), status));
+        };
+        let fd = AsyncFd::new(fd)?;
         loop {
             // Check with non-blocking waitid before awaiting on fd.
             // On some platforms, the readiness detecting mechanism relies on
@@ -8,13 +8,13 @@ use std::ops::Add;
 use std::os::unix::fs::symlink;
 #[cfg(windows)]
 use std::os::windows::fs::symlink_file as symlink;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{Result, bail};
 pub use containerd_shim_wasm_test_modules as modules;
 use containerd_shimkit::AmbientRuntime as _;
-use containerd_shimkit::sandbox::{Instance as _, InstanceConfig};
+use containerd_shimkit::sandbox::{Config, Instance as _, InstanceConfig, Termination};
 use libc::{SIGINT, SIGTERM};
 use oci_spec::runtime::{
     LinuxBuilder, LinuxNamespace, LinuxNamespaceType, ProcessBuilder, RootBuilder, SpecBuilder,
@@ -30,6 +30,10 @@ pub struct WasiTestBuilder<WasiEngine: Shim> {
     container_name: String,
     start_fn: String,
     namespaces: Vec<LinuxNamespace>,
+    in_process: bool,
+    core_dump_on_trap: bool,
+    annotations: HashMap<String, String>,
+    checkpoint: Option<PathBuf>,
     tempdir: tempfile::TempDir,
     _phantom: PhantomData<WasiEngine>,
 }
@@ -68,6 +72,10 @@ impl<WasiEngine: Shim> WasiTestBuilder<WasiEngine> {
             container_name: "test".to_string(),
             start_fn: "".to_string(),
             namespaces: get_default_namespaces(),
+            in_process: false,
+            core_dump_on_trap: false,
+            annotations: HashMap::new(),
+            checkpoint: None,
             _phantom: Default::default(),
         }
         .with_wasm([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00])?
@@ -90,6 +98,28 @@ impl<WasiEngine: Shim> WasiTestBuilder<WasiEngine> {
         self
     }
 
+    pub fn with_in_process(mut self) -> Self {
+        self.in_process = true;
+        self
+    }
+
+    /// Write a core dump to the bundle of the container if it traps.
+    pub fn with_core_dump_on_trap(mut self) -> Self {
+        self.core_dump_on_trap = true;
+        self
+    }
+
+    pub fn with_annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Restore the container from the checkpoint image at `path`.
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
     pub fn with_start_fn(mut self, start_fn: impl AsRef<str>) -> Self {
         start_fn.as_ref().clone_into(&mut self.start_fn);
         self
@@ -109,6 +139,24 @@ impl<WasiEngine: Shim> WasiTestBuilder<WasiEngine> {
         Ok(self)
     }
 
+    /// Write a file at `path` in the rootfs of the container.
+    pub fn with_file(self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<Self> {
+        let path = self
+            .tempdir
+            .path()
+            .join("rootfs")
+            .join(path.as_ref().strip_prefix("/").unwrap_or(path.as_ref()));
+
+        log::info!("writing file {path:?} to the rootfs");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write(path, contents)?;
+
+        Ok(self)
+    }
+
     pub fn with_stdin(self, stdin: impl AsRef<[u8]>) -> Result<Self> {
         let dir = self.tempdir.path();
 
@@ -202,6 +250,7 @@ impl<WasiEngine: Shim> WasiTestBuilder<WasiEngine> {
                     .args([entrypoint])
                     .build()?,
             )
+            .annotations(self.annotations)
             .build()?;
 
         spec.save(dir.join("config.json"))?;
@@ -215,6 +264,12 @@ impl<WasiEngine: Shim> WasiTestBuilder<WasiEngine> {
             stdout: dir.join("stdout"),
             stderr: dir.join("stderr"),
             stdin: dir.join("stdin"),
+            config: Config {
+                in_process: self.in_process,
+                core_dump_on_trap: self.core_dump_on_trap,
+                ..Default::default()
+            },
+            checkpoint: self.checkpoint,
             ..Default::default()
         };
 
@@ -246,6 +301,14 @@ impl<WasiEngine: Shim> WasiTest<WasiEngine> {
         Ok(self)
     }
 
+    /// Checkpoint the running container into the checkpoint image at `path`.
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<&Self> {
+        log::info!("checkpointing wasi test");
+        fs::create_dir_all(path.as_ref())?;
+        self.instance.checkpoint(path.as_ref()).block_on()?;
+        Ok(self)
+    }
+
     pub fn ctrl_c(&self) -> Result<&Self> {
         log::info!("sending SIGINT");
         self.instance.kill(SIGINT as u32).block_on()?;
@@ -284,6 +347,11 @@ impl<WasiEngine: Shim> WasiTest<WasiEngine> {
         Ok((status, stdout, stderr))
     }
 
+    /// Returns why the container terminated, once it has finished.
+    pub fn termination(&self) -> Option<Termination> {
+        self.instance.termination().block_on()
+    }
+
     pub fn root(&self) -> &Path {
         self.tempdir.path()
     }
@@ -6,11 +6,11 @@ use std::str::FromStr;
 use anyhow::{Context, Result};
 use cfg_if::cfg_if;
 use containerd_shim_wasm::sandbox::Sandbox;
-use containerd_shim_wasm::sandbox::context::{Entrypoint, RuntimeContext};
+use containerd_shim_wasm::sandbox::context::{Entrypoint, ResourceLimits, RuntimeContext};
 use containerd_shim_wasm::shim::{Shim, Version, version};
 #[cfg(all(feature = "plugin", not(target_env = "musl")))]
 use wasmedge_sdk::AsInstance;
-use wasmedge_sdk::config::{CommonConfigOptions, Config, ConfigBuilder};
+use wasmedge_sdk::config::{CommonConfigOptions, Config, ConfigBuilder, RuntimeConfigOptions};
 #[cfg(all(feature = "plugin", not(target_env = "musl")))]
 use wasmedge_sdk::plugin::NNPreload;
 #[cfg(all(feature = "plugin", not(target_env = "musl")))]
@@ -20,17 +20,17 @@ use wasmedge_sdk::{Module, Store, Vm};
 
 pub struct WasmEdgeShim;
 
-pub struct WasmEdgeSandbox {
-    config: Config,
-}
+#[derive(Default)]
+pub struct WasmEdgeSandbox;
 
-impl Default for WasmEdgeSandbox {
-    fn default() -> Self {
-        let config = ConfigBuilder::new(CommonConfigOptions::default())
-            .build()
-            .expect("failed to create config");
-        Self { config }
+fn config(resources: &ResourceLimits) -> Result<Config> {
+    let mut builder = ConfigBuilder::new(CommonConfigOptions::default());
+    if let Some(pages) = resources.max_memory_pages() {
+        log::debug!("limiting linear memory to {pages} pages");
+        builder =
+            builder.with_runtime_config(RuntimeConfigOptions::default().max_memory_pages(pages));
     }
+    Ok(builder.build()?)
 }
 
 impl Shim for WasmEdgeShim {
@@ -88,16 +88,30 @@ impl Sandbox for WasmEdgeSandbox {
             }
         }
 
+        let preopens = ctx
+            .preopens()
+            .into_iter()
+            .map(|preopen| {
+                let guest = preopen.guest_path.to_string_lossy();
+                let host = preopen.host_path.to_string_lossy();
+                match preopen.readonly {
+                    true => format!("{guest}:{host}:readonly"),
+                    false => format!("{guest}:{host}"),
+                }
+            })
+            .collect::<Vec<_>>();
+
         let mut wasi_module = WasiModule::create(
             Some(args.iter().map(String::as_str).collect()),
             Some(envs.iter().map(String::as_str).collect()),
-            Some(vec!["/:/"]),
+            Some(preopens.iter().map(String::as_str).collect()),
         )?;
         instances.insert(wasi_module.name().to_string(), wasi_module.as_mut());
 
+        let config = config(&ctx.resources()).context("failed to create config")?;
         let wasm_bytes = source.as_bytes()?;
-        let module = Module::from_bytes(Some(&self.config), &wasm_bytes)?;
-        let mut vm = Vm::new(Store::new(Some(&self.config), instances).unwrap());
+        let module = Module::from_bytes(Some(&config), &wasm_bytes)?;
+        let mut vm = Vm::new(Store::new(Some(&config), instances).unwrap());
         let mod_name = name.unwrap_or_else(|| "main".to_string());
 
         let vm = vm
@@ -1,11 +1,19 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use containerd_shim_wasm::sandbox::Sandbox;
 use containerd_shim_wasm::sandbox::context::{Entrypoint, RuntimeContext};
+use containerd_shim_wasm::sandbox::network::NetworkPolicy;
 use containerd_shim_wasm::shim::{Shim, Version, version};
 use tokio::runtime::Handle;
-use wasmer::{Module, Store};
+use wasmer::{BaseTunables, Engine, Module, NativeEngineExt, Pages, Store};
+use wasmer_wasix::runtime::task_manager::tokio::TokioTaskManager;
 use wasmer_wasix::virtual_fs::host_fs::FileSystem;
-use wasmer_wasix::{WasiEnv, WasiError};
+use wasmer_wasix::virtual_net::host::LocalNetworking;
+use wasmer_wasix::{PluggableRuntime, WasiEnv, WasiError};
+
+use crate::network::PolicyNetworking;
+use crate::tunables::LimitingTunables;
 
 pub struct WasmerShim;
 
@@ -46,20 +54,47 @@ impl Sandbox for WasmerSandbox {
 
         let mod_name = name.unwrap_or_else(|| "main".to_string());
 
+        let mut engine = Engine::from(self.engine.clone());
+        if let Some(pages) = ctx.resources().max_memory_pages() {
+            log::info!("Limiting linear memory to {pages} pages");
+            let base = BaseTunables::for_target(engine.target());
+            engine.set_tunables(LimitingTunables::new(base, Pages(pages)));
+        }
+
         log::info!("Create a Store");
-        let mut store = Store::new(self.engine.clone());
+        let mut store = Store::new(engine);
 
         let wasm_bytes = source.as_bytes()?;
         let module = Module::from_binary(&store, &wasm_bytes)?;
 
+        let network = NetworkPolicy::new(ctx)?;
+        let mut runtime = PluggableRuntime::new(Arc::new(TokioTaskManager::default()));
+        runtime
+            .set_networking_implementation(PolicyNetworking::new(LocalNetworking::new(), network));
+
         log::info!("Creating `WasiEnv`...: args {args:?}, envs: {envs:?}");
         let fs = FileSystem::new(Handle::current(), "/")?;
-        let (instance, wasi_env) = WasiEnv::builder(mod_name)
+        let mut builder = WasiEnv::builder(mod_name)
             .args(&args[1..])
             .envs(envs)
             .fs(Box::new(fs))
-            .preopen_dir("/")?
-            .instantiate(module, &mut store)?;
+            .runtime(Arc::new(runtime));
+
+        for preopen in ctx.preopens() {
+            builder.add_preopen_build(|p| {
+                let p = p
+                    .directory(&preopen.host_path)
+                    .read(true)
+                    .write(!preopen.readonly)
+                    .create(!preopen.readonly);
+                if preopen.guest_path != preopen.host_path {
+                    p.alias(&preopen.guest_path.to_string_lossy());
+                }
+                p
+            })?;
+        }
+
+        let (instance, wasi_env) = builder.instantiate(module, &mut store)?;
 
         log::info!("Running {func:?}");
         let start = instance.exports.get_function(&func)?;
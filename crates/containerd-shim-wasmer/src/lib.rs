@@ -1,4 +1,6 @@
 pub mod instance;
+mod network;
+mod tunables;
 
 pub use instance::WasmerShim;
 
@@ -0,0 +1,256 @@
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use containerd_shim_wasm::sandbox::network::{NetworkPolicy, SocketUse};
+use wasmer_wasix::virtual_net::{
+    InterestHandler, NetworkError, Result, SocketStatus, VirtualConnectionlessSocket,
+    VirtualIoSource, VirtualNetworking, VirtualSocket, VirtualTcpListener, VirtualTcpSocket,
+    VirtualUdpSocket,
+};
+
+/// Networking that checks the sockets of the guest against the network policy of the container.
+///
+/// Binds, connections and name lookups are checked before they're handed to the inner
+/// networking, and so are the datagrams sent by UDP sockets, which aren't connected.
+#[derive(Debug)]
+pub struct PolicyNetworking<N> {
+    inner: N,
+    policy: Arc<NetworkPolicy>,
+}
+
+impl<N> PolicyNetworking<N> {
+    pub fn new(inner: N, policy: NetworkPolicy) -> Self {
+        Self {
+            inner,
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+fn check(policy: &NetworkPolicy, addr: SocketAddr, usage: SocketUse) -> Result<()> {
+    if policy.is_allowed(addr, usage) {
+        return Ok(());
+    }
+    log::warn!("denied socket {usage} to {addr}");
+    Err(NetworkError::PermissionDenied)
+}
+
+#[async_trait::async_trait]
+impl<N: VirtualNetworking + Sync> VirtualNetworking for PolicyNetworking<N> {
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        check(&self.policy, addr, SocketUse::Bind)?;
+        self.inner
+            .listen_tcp(addr, only_v6, reuse_port, reuse_addr)
+            .await
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        check(&self.policy, addr, SocketUse::Bind)?;
+        let socket = self.inner.bind_udp(addr, reuse_port, reuse_addr).await?;
+        Ok(Box::new(PolicyUdpSocket {
+            inner: socket,
+            policy: self.policy.clone(),
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        check(&self.policy, peer, SocketUse::Connect)?;
+        self.inner.connect_tcp(addr, peer).await
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        if !self.policy.allows_ip_name_lookup() {
+            log::warn!("denied name lookup of {host}");
+            return Err(NetworkError::PermissionDenied);
+        }
+        self.inner.resolve(host, port, dns_server).await
+    }
+}
+
+/// A UDP socket whose outgoing datagrams are checked against the network policy.
+#[derive(Debug)]
+struct PolicyUdpSocket {
+    inner: Box<dyn VirtualUdpSocket + Sync>,
+    policy: Arc<NetworkPolicy>,
+}
+
+impl VirtualIoSource for PolicyUdpSocket {
+    fn remove_handler(&mut self) {
+        self.inner.remove_handler()
+    }
+
+    fn poll_read_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize>> {
+        self.inner.poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize>> {
+        self.inner.poll_write_ready(cx)
+    }
+}
+
+impl VirtualSocket for PolicyUdpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.inner.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.inner.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.inner.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.inner.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.inner.set_handler(handler)
+    }
+}
+
+impl VirtualConnectionlessSocket for PolicyUdpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        check(&self.policy, addr, SocketUse::Connect)?;
+        self.inner.try_send_to(data, addr)
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        self.inner.try_recv_from(buf)
+    }
+}
+
+impl VirtualUdpSocket for PolicyUdpSocket {
+    fn set_broadcast(&mut self, broadcast: bool) -> Result<()> {
+        self.inner.set_broadcast(broadcast)
+    }
+
+    fn broadcast(&self) -> Result<bool> {
+        self.inner.broadcast()
+    }
+
+    fn set_multicast_loop_v4(&mut self, val: bool) -> Result<()> {
+        self.inner.set_multicast_loop_v4(val)
+    }
+
+    fn multicast_loop_v4(&self) -> Result<bool> {
+        self.inner.multicast_loop_v4()
+    }
+
+    fn set_multicast_loop_v6(&mut self, val: bool) -> Result<()> {
+        self.inner.set_multicast_loop_v6(val)
+    }
+
+    fn multicast_loop_v6(&self) -> Result<bool> {
+        self.inner.multicast_loop_v6()
+    }
+
+    fn set_multicast_ttl_v4(&mut self, ttl: u32) -> Result<()> {
+        self.inner.set_multicast_ttl_v4(ttl)
+    }
+
+    fn multicast_ttl_v4(&self) -> Result<u32> {
+        self.inner.multicast_ttl_v4()
+    }
+
+    fn join_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.inner.join_multicast_v4(multiaddr, iface)
+    }
+
+    fn leave_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.inner.leave_multicast_v4(multiaddr, iface)
+    }
+
+    fn join_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.inner.join_multicast_v6(multiaddr, iface)
+    }
+
+    fn leave_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.inner.leave_multicast_v6(multiaddr, iface)
+    }
+
+    fn addr_peer(&self) -> Result<Option<SocketAddr>> {
+        self.inner.addr_peer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+
+    use containerd_shim_wasm::sandbox::network::{
+        NETWORK_ALLOWED_CONNECT_ANNOTATION, NETWORK_DEFAULT_ANNOTATION,
+    };
+    use wasmer_wasix::virtual_net::host::LocalNetworking;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_policy_networking_deny() -> anyhow::Result<()> {
+        // both listeners accept connections, only the policy tells them apart
+        let allowed_listener = TcpListener::bind("127.0.0.1:0")?;
+        let allowed = allowed_listener.local_addr()?;
+        let denied_listener = TcpListener::bind("127.0.0.1:0")?;
+        let denied = denied_listener.local_addr()?;
+
+        let annotations = HashMap::from([
+            (NETWORK_DEFAULT_ANNOTATION.to_string(), "deny".to_string()),
+            (
+                NETWORK_ALLOWED_CONNECT_ANNOTATION.to_string(),
+                format!("127.0.0.1:{}", allowed.port()),
+            ),
+        ]);
+        let policy = NetworkPolicy::from_annotations(&annotations)?;
+        let networking = PolicyNetworking::new(LocalNetworking::new(), policy);
+        let local: SocketAddr = "0.0.0.0:0".parse()?;
+
+        assert!(networking.connect_tcp(local, allowed).await.is_ok());
+        assert!(matches!(
+            networking.connect_tcp(local, denied).await,
+            Err(NetworkError::PermissionDenied)
+        ));
+        assert!(matches!(
+            networking
+                .listen_tcp("127.0.0.1:0".parse()?, false, false, false)
+                .await,
+            Err(NetworkError::PermissionDenied)
+        ));
+        assert!(matches!(
+            networking
+                .bind_udp("127.0.0.1:0".parse()?, false, false)
+                .await,
+            Err(NetworkError::PermissionDenied)
+        ));
+        assert!(matches!(
+            networking.resolve("localhost", None, None).await,
+            Err(NetworkError::PermissionDenied)
+        ));
+
+        drop((allowed_listener, denied_listener));
+        Ok(())
+    }
+}
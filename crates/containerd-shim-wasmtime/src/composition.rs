@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use containerd_shim_wasm::sandbox::context::WasmLayer;
+use wac_graph::types::Package;
+use wac_graph::{CompositionGraph, EncodeOptions};
+
+/// Composes the component layers of an OCI image into a single component.
+///
+/// The last layer is the main component. Its imports are satisfied by the exports of the
+/// other layers, and any import that no other layer exports is left for the host to provide.
+pub(crate) fn compose(layers: &[WasmLayer]) -> Result<Vec<u8>> {
+    log::info!("composing {} component layers", layers.len());
+
+    let mut graph = CompositionGraph::new();
+    let mut packages = Vec::with_capacity(layers.len());
+    for (i, layer) in layers.iter().enumerate() {
+        let digest = layer.config.digest();
+        let package = Package::from_bytes(
+            &format!("runwasi:layer{i}"),
+            None,
+            layer.layer.as_slice(),
+            graph.types_mut(),
+        )
+        .with_context(|| format!("failed to load layer {digest} as a component"))?;
+        let package = graph
+            .register_package(package)
+            .with_context(|| format!("failed to register layer {digest}"))?;
+        packages.push(package);
+    }
+
+    let (&main, dependencies) = packages.split_last().context("no layers to compose")?;
+    wac_graph::plug(&mut graph, dependencies.to_vec(), main)
+        .context("failed to link the component layers")?;
+
+    let component = graph
+        .encode(EncodeOptions::default())
+        .context("failed to encode the composed component")?;
+
+    Ok(component)
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::image::{Descriptor, Digest, MediaType};
+    use wasmtime::component::{Component, Linker};
+    use wasmtime::{Engine, Store};
+
+    use super::*;
+
+    fn layer(wat: &str) -> Result<WasmLayer> {
+        Ok(WasmLayer {
+            layer: wat::parse_str(wat)?,
+            config: Descriptor::new(
+                MediaType::Other("application/wasm".to_string()),
+                0,
+                Digest::try_from(format!("sha256:{:064}", 0))?,
+            ),
+        })
+    }
+
+    #[test]
+    fn test_compose_layers() -> Result<()> {
+        let library = layer(
+            r#"(component
+                (core module $m (func (export "answer") (result i32) i32.const 42))
+                (core instance $i (instantiate $m))
+                (func $answer (result u32) (canon lift (core func $i "answer")))
+                (instance $api (export "answer" (func $answer)))
+                (export "runwasi:test/api" (instance $api))
+            )"#,
+        )?;
+        let main = layer(
+            r#"(component
+                (import "runwasi:test/api" (instance $api (export "answer" (func (result u32)))))
+                (alias export $api "answer" (func $answer))
+                (export "run" (func $answer))
+            )"#,
+        )?;
+
+        let composed = compose(&[library, main])?;
+
+        let mut config = wasmtime::Config::new();
+        config.wasm_component_model(true);
+        let engine = Engine::new(&config)?;
+        let component = Component::from_binary(&engine, &composed)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine).instantiate(&mut store, &component)?;
+        let run = instance.get_typed_func::<(), (u32,)>(&mut store, "run")?;
+
+        assert_eq!(run.call(&mut store, ())?, (42,));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_layers_without_links() -> Result<()> {
+        let library = layer(r#"(component)"#)?;
+        let main = layer(r#"(component)"#)?;
+
+        assert!(compose(&[library, main]).is_err());
+
+        Ok(())
+    }
+}
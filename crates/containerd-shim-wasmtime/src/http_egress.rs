@@ -0,0 +1,350 @@
+//! Policy of the outgoing HTTP requests of `wasi:http` guests.
+//!
+//! By default guests can send requests to any host. The annotations of the container
+//! can restrict the hosts, ports and schemes they can reach, the headers they can
+//! set, the size of the bodies they send and how long they wait for a connection.
+//! The policy is only read from annotations, and not from environment variables,
+//! since those can be set by the image of the guest.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use containerd_shim_wasm::sandbox::context::RuntimeContext;
+use http_body_util::{BodyExt, Limited};
+use hyper::header::HeaderName;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::OutgoingRequestConfig;
+
+const ALLOWED_HOSTS_ANNOTATION: &str = "wasmtime.runwasi.io/http-egress-allowed-hosts";
+const FORBIDDEN_HEADERS_ANNOTATION: &str = "wasmtime.runwasi.io/http-egress-forbidden-headers";
+const MAX_BODY_SIZE_ANNOTATION: &str = "wasmtime.runwasi.io/http-egress-max-body-size";
+const CONNECT_TIMEOUT_ANNOTATION: &str = "wasmtime.runwasi.io/http-egress-connect-timeout";
+
+type OutgoingRequest = hyper::Request<HyperOutgoingBody>;
+
+/// The restrictions on the outgoing requests of a container.
+#[derive(Debug, Default)]
+pub(crate) struct EgressPolicy {
+    /// The destinations requests can be sent to, or `None` to allow any destination.
+    allowed_hosts: Option<Vec<HostPattern>>,
+    /// Headers the guest can't set, in lowercase.
+    forbidden_headers: HashSet<HeaderName>,
+    /// Maximum size in bytes of the body of a request.
+    max_body_size: Option<u64>,
+    /// Upper bound of the connection timeout requested by the guest.
+    connect_timeout: Option<Duration>,
+}
+
+impl EgressPolicy {
+    /// An invalid policy is an error rather than being ignored, so that a typo can't open up the network.
+    pub(crate) fn new(ctx: &impl RuntimeContext) -> Result<Self> {
+        let annotations = ctx.annotations();
+        let annotation = |key: &str| annotations.get(key).map(String::as_str);
+
+        let allowed_hosts = annotation(ALLOWED_HOSTS_ANNOTATION)
+            .map(|hosts| list(hosts).map(str::parse).collect())
+            .transpose()
+            .with_context(|| format!("invalid {ALLOWED_HOSTS_ANNOTATION} annotation"))?;
+        let forbidden_headers = annotation(FORBIDDEN_HEADERS_ANNOTATION)
+            .map(|headers| list(headers).map(HeaderName::try_from).collect())
+            .transpose()
+            .with_context(|| format!("invalid {FORBIDDEN_HEADERS_ANNOTATION} annotation"))?
+            .unwrap_or_default();
+        let max_body_size = annotation(MAX_BODY_SIZE_ANNOTATION)
+            .map(str::parse)
+            .transpose()
+            .with_context(|| format!("invalid {MAX_BODY_SIZE_ANNOTATION} annotation"))?;
+        let connect_timeout = annotation(CONNECT_TIMEOUT_ANNOTATION)
+            .map(humantime::parse_duration)
+            .transpose()
+            .with_context(|| format!("invalid {CONNECT_TIMEOUT_ANNOTATION} annotation"))?;
+
+        Ok(Self {
+            allowed_hosts,
+            forbidden_headers,
+            max_body_size,
+            connect_timeout,
+        })
+    }
+
+    pub(crate) fn is_forbidden_header(&self, name: &HeaderName) -> bool {
+        self.forbidden_headers.contains(name)
+    }
+
+    /// Check that a request can be sent, and apply the limits of the policy to it.
+    pub(crate) fn check(
+        &self,
+        request: OutgoingRequest,
+        mut config: OutgoingRequestConfig,
+    ) -> Result<(OutgoingRequest, OutgoingRequestConfig), ErrorCode> {
+        let (scheme, default_port) = match config.use_tls {
+            true => ("https", 443),
+            false => ("http", 80),
+        };
+        let uri = request.uri();
+        let host = uri.host().ok_or(ErrorCode::HttpRequestUriInvalid)?;
+        let port = uri.port_u16().unwrap_or(default_port);
+        if !self.is_allowed(scheme, host, port) {
+            log::warn!("denied outgoing request to {scheme}://{host}:{port}");
+            return Err(ErrorCode::HttpRequestDenied);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            config.connect_timeout = config.connect_timeout.min(timeout);
+        }
+
+        let Some(max) = self.max_body_size else {
+            return Ok((request, config));
+        };
+        if let Some(len) = content_length(&request).filter(|len| *len > max) {
+            log::warn!("denied outgoing request with a body of {len} bytes");
+            return Err(ErrorCode::HttpRequestBodySize(Some(len)));
+        }
+        // the body may also be streamed without a `content-length`
+        let request = request.map(|body| {
+            Limited::new(body, usize::try_from(max).unwrap_or(usize::MAX))
+                .map_err(move |err| match err.downcast::<ErrorCode>() {
+                    Ok(err) => *err,
+                    Err(_) => ErrorCode::HttpRequestBodySize(Some(max)),
+                })
+                .boxed()
+        });
+        Ok((request, config))
+    }
+
+    fn is_allowed(&self, scheme: &str, host: &str, port: u16) -> bool {
+        // IPv6 addresses are in brackets in URIs
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match &self.allowed_hosts {
+            Some(allowed_hosts) => allowed_hosts
+                .iter()
+                .any(|pattern| pattern.matches(scheme, host, port)),
+            None => true,
+        }
+    }
+}
+
+/// A destination of requests, `[<scheme>://]<host>[:<port>]`.
+///
+/// The host is either a name, an IP address, `*` for any host, or `*.<domain>` for
+/// any subdomain of `<domain>`. Any scheme and port match when they're omitted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct HostPattern {
+    scheme: Option<String>,
+    host: HostMatch,
+    port: Option<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum HostMatch {
+    Any,
+    /// The host, in lowercase.
+    Exact(String),
+    /// The domain, in lowercase, with a leading dot.
+    Subdomain(String),
+}
+
+impl std::str::FromStr for HostPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, s),
+        };
+        if let Some(scheme) = &scheme {
+            if scheme != "http" && scheme != "https" {
+                bail!("unsupported scheme in {s:?}");
+            }
+        }
+
+        let (host, port) = match rest.strip_prefix('[') {
+            // an IPv6 address
+            Some(rest) => {
+                let (host, rest) = rest
+                    .split_once(']')
+                    .with_context(|| format!("unterminated IPv6 address in {s:?}"))?;
+                match rest {
+                    "" => (host, None),
+                    _ => (
+                        host,
+                        Some(rest.strip_prefix(':').with_context(|| {
+                            format!("unexpected {rest:?} after IPv6 address in {s:?}")
+                        })?),
+                    ),
+                }
+            }
+            None => match rest.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            },
+        };
+        let port = match port {
+            None | Some("*") => None,
+            Some(port) => Some(
+                port.parse()
+                    .with_context(|| format!("invalid port in {s:?}"))?,
+            ),
+        };
+
+        let host = host.to_ascii_lowercase();
+        let host = match host.as_str() {
+            "" => bail!("missing host in {s:?}"),
+            "*" => HostMatch::Any,
+            _ => match host.strip_prefix("*.") {
+                Some(domain) if !domain.is_empty() => HostMatch::Subdomain(format!(".{domain}")),
+                _ if host.contains('*') => bail!("invalid wildcard in {s:?}"),
+                _ => HostMatch::Exact(host),
+            },
+        };
+
+        Ok(Self { scheme, host, port })
+    }
+}
+
+impl HostPattern {
+    fn matches(&self, scheme: &str, host: &str, port: u16) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.scheme.as_deref().is_none_or(|s| s == scheme)
+            && self.port.is_none_or(|p| p == port)
+            && match &self.host {
+                HostMatch::Any => true,
+                HostMatch::Exact(exact) => host == *exact,
+                HostMatch::Subdomain(domain) => host.ends_with(domain.as_str()),
+            }
+    }
+}
+
+/// Split a comma separated list, ignoring empty items.
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn content_length(request: &OutgoingRequest) -> Option<u64> {
+    request
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+
+    use super::*;
+
+    fn policy(allowed_hosts: &str) -> EgressPolicy {
+        EgressPolicy {
+            allowed_hosts: Some(list(allowed_hosts).map(|h| h.parse().unwrap()).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn request_config(use_tls: bool) -> OutgoingRequestConfig {
+        OutgoingRequestConfig {
+            use_tls,
+            connect_timeout: Duration::from_secs(600),
+            first_byte_timeout: Duration::from_secs(600),
+            between_bytes_timeout: Duration::from_secs(600),
+        }
+    }
+
+    fn request(uri: &str, body: &'static str) -> OutgoingRequest {
+        let body = Full::new(Bytes::from(body))
+            .map_err(|never| match never {})
+            .boxed();
+        hyper::Request::post(uri).body(body).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let policy = policy("https://api.example.com, *.internal:8080, [::1]");
+
+        assert!(policy.is_allowed("https", "api.example.com", 443));
+        assert!(policy.is_allowed("https", "API.example.com", 8443));
+        assert!(!policy.is_allowed("http", "api.example.com", 80));
+        assert!(!policy.is_allowed("https", "example.com", 443));
+
+        assert!(policy.is_allowed("http", "db.internal", 8080));
+        assert!(policy.is_allowed("https", "a.b.internal", 8080));
+        assert!(!policy.is_allowed("http", "db.internal", 80));
+        assert!(!policy.is_allowed("http", "internal", 8080));
+        assert!(!policy.is_allowed("http", "evilinternal", 8080));
+
+        assert!(policy.is_allowed("http", "[::1]", 80));
+    }
+
+    #[test]
+    fn test_deny_all() {
+        let policy = policy("");
+        assert!(!policy.is_allowed("https", "example.com", 443));
+        assert!(EgressPolicy::default().is_allowed("https", "example.com", 443));
+    }
+
+    #[test]
+    fn test_invalid_host_pattern() {
+        for pattern in [
+            "ftp://example.com",
+            "example.com:http",
+            "*example.com",
+            "[::1",
+        ] {
+            assert!(pattern.parse::<HostPattern>().is_err(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = EgressPolicy {
+            connect_timeout: Some(Duration::from_secs(5)),
+            ..policy("example.com")
+        };
+
+        let result = policy.check(request("https://example.com/", ""), request_config(true));
+        let (_, config) = result.unwrap();
+        assert_eq!(config.connect_timeout, Duration::from_secs(5));
+
+        // the default port of the scheme is used
+        let result = policy.check(request("http://other.com/", ""), request_config(false));
+        assert!(matches!(result, Err(ErrorCode::HttpRequestDenied)));
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let policy = EgressPolicy {
+            max_body_size: Some(4),
+            ..Default::default()
+        };
+
+        let mut req = request("http://example.com/", "hello");
+        req.headers_mut()
+            .insert(hyper::header::CONTENT_LENGTH, 5.into());
+        let result = policy.check(req, request_config(false));
+        assert!(matches!(
+            result,
+            Err(ErrorCode::HttpRequestBodySize(Some(5)))
+        ));
+
+        // without a content-length, sending the body fails
+        let (req, _) = policy
+            .check(
+                request("http://example.com/", "hello"),
+                request_config(false),
+            )
+            .unwrap();
+        let result = req.into_body().collect().await;
+        assert!(matches!(
+            result,
+            Err(ErrorCode::HttpRequestBodySize(Some(4)))
+        ));
+    }
+}
@@ -1,33 +1,61 @@
 // Heavily inspired by wasmtime serve command:
 // https://github.com/bytecodealliance/wasmtime/blob/main/src/commands/serve.rs
 
+mod h2c;
+mod pool;
+mod telemetry;
+mod tls;
+
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use anyhow::{Result, bail};
-use containerd_shim_wasm::sandbox::context::RuntimeContext;
-use hyper::server::conn::http1;
-use tokio::net::{TcpListener, TcpStream};
+use anyhow::{Context, Result, bail, ensure};
+use containerd_shim_wasm::sandbox::context::{Preopen, ResourceLimits, RuntimeContext};
+use http_body_util::{BodyExt, Limited};
+use hyper::StatusCode;
+use hyper::header::{CONNECTION, HeaderValue, UPGRADE};
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{Semaphore, watch};
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 use wasmtime::Store;
-use wasmtime::component::ResourceTable;
-use wasmtime_wasi_http::bindings::ProxyPre;
-use wasmtime_wasi_http::bindings::http::types::Scheme;
-use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime::component::{Resource, ResourceTable};
+use wasmtime_wasi_http::bindings::http::types::{ErrorCode, Scheme};
+use wasmtime_wasi_http::bindings::{Proxy, ProxyPre};
+use wasmtime_wasi_http::body::{HostIncomingBody, HyperOutgoingBody};
 use wasmtime_wasi_http::io::TokioIo;
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+use wasmtime_wasi_http::types::HostIncomingRequest;
+use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView, hyper_request_error};
 
-use crate::instance::{WasiPreview2Ctx, envs_from_ctx};
+use self::pool::{InstancePool, Pooled};
+use self::telemetry::Telemetry;
+use self::tls::Tls;
+use crate::http_egress::EgressPolicy;
+use crate::instance::{MemoryLimiter, StopSignal, WasiPreview2Ctx, envs_from_ctx, yield_on_epoch};
 
-const DEFAULT_ADDR: SocketAddr =
-    SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), 8080);
+const DEFAULT_ADDR: ListenAddr = ListenAddr::Tcp(SocketAddr::new(
+    IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
+    8080,
+));
 
 const DEFAULT_BACKLOG: u32 = 100;
 
+const ANNOTATION_PREFIX: &str = "wasmtime.runwasi.io";
+
+/// How long the guest waits for the next chunk of a request body, like in `new_incoming_request`.
+const BETWEEN_BYTES_TIMEOUT: Duration = Duration::from_secs(600);
+
 type Request = hyper::Request<hyper::body::Incoming>;
 
 fn is_connection_error(e: &std::io::Error) -> bool {
@@ -39,112 +67,723 @@ fn is_connection_error(e: &std::io::Error) -> bool {
     )
 }
 
-// [From axum](https://github.com/tokio-rs/axum/blob/280d16a61059f57230819a79b15aa12a263e8cca/axum/src/serve.rs#L425)
-async fn tcp_accept(listener: &TcpListener) -> Option<TcpStream> {
-    match listener.accept().await {
-        Ok((stream, _addr)) => Some(stream),
-        Err(e) => {
-            if is_connection_error(&e) {
-                return None;
+/// The address the proxy listens on, either a TCP socket address or `unix:<path>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ListenAddr {
+    Tcp(SocketAddr),
+    /// The path of a Unix socket in the container filesystem.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(ListenAddr::Unix(path.into())),
+            None => s.parse().map(ListenAddr::Tcp),
+        }
+    }
+}
+
+/// Permissions of the file of a Unix socket, as an octal number, e.g., `660`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileMode(u32);
+
+impl FromStr for FileMode {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s, 8).map(FileMode)
+    }
+}
+
+/// A connection accepted by the proxy.
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection for T {}
+
+enum Listener {
+    Tcp(TcpListener),
+    /// The socket file is removed when the listener is dropped.
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFile),
+}
+
+/// The file of a Unix socket, with its device and inode numbers.
+#[cfg(unix)]
+struct SocketFile {
+    path: PathBuf,
+    id: (u64, u64),
+}
+
+impl Listener {
+    fn bind(
+        ctx: &impl RuntimeContext,
+        addr: &ListenAddr,
+        backlog: u32,
+        mode: Option<FileMode>,
+    ) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let socket = match addr {
+                    SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+                    SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+                };
+
+                // Conditionally enable `SO_REUSEADDR` depending on the current
+                // platform. On Unix we want this to be able to rebind an address in
+                // the `TIME_WAIT` state which can happen then a server is killed with
+                // active TCP connections and then restarted. On Windows though if
+                // `SO_REUSEADDR` is specified then it enables multiple applications to
+                // bind the port at the same time which is not something we want. Hence
+                // this is conditionally set based on the platform (and deviates from
+                // Tokio's default from always-on).
+                socket.set_reuseaddr(!cfg!(windows))?;
+                socket.bind(*addr)?;
+
+                Ok(Listener::Tcp(socket.listen(backlog)?))
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+                // the path is resolved in the container, so that its symlinks can't point to the host
+                let path = ContainerFile::new(ctx, path)?.host_path()?;
+                // a socket left over by a previous run of the container, e.g., in a shared volume
+                if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(&path)?;
+                }
+
+                let socket = tokio::net::UnixSocket::new_stream()?;
+                socket
+                    .bind(&path)
+                    .with_context(|| format!("failed to bind unix socket {path:?}"))?;
+                // the listener owns the socket file from here, and removes it on drop
+                let metadata = std::fs::symlink_metadata(&path)?;
+                let file = SocketFile {
+                    path: path.clone(),
+                    id: (metadata.dev(), metadata.ino()),
+                };
+                let listener = Listener::Unix(socket.listen(backlog)?, file);
+                if let Some(FileMode(mode)) = mode {
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+                }
+
+                Ok(listener)
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                let _ = (ctx, mode);
+                bail!("unix sockets are not supported on this platform")
+            }
+        }
+    }
+
+    /// The URL of the listener, for logging.
+    fn url(&self, scheme: &str) -> Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(format!("{scheme}://{}/", listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, file) => Ok(format!("{scheme}+unix:{}", file.path.display())),
+        }
+    }
+
+    /// Accept a connection, along with the address of the client when it's a TCP connection.
+    // [From axum](https://github.com/tokio-rs/axum/blob/280d16a61059f57230819a79b15aa12a263e8cca/axum/src/serve.rs#L425)
+    async fn accept(&self) -> Option<(Box<dyn Connection>, Option<SocketAddr>)> {
+        let conn = match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(stream, addr)| (Box::new(stream) as Box<dyn Connection>, Some(addr))),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener
+                .accept()
+                .await
+                .map(|(stream, _)| (Box::new(stream) as Box<dyn Connection>, None)),
+        };
+        match conn {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                if is_connection_error(&e) {
+                    return None;
+                }
+
+                // [From `hyper::Server` in 0.14](https://github.com/hyperium/hyper/blob/v0.14.27/src/server/tcp.rs#L186)
+                //
+                // > A possible scenario is that the process has hit the max open files
+                // > allowed, and so trying to accept a new connection will fail with
+                // > `EMFILE`. In some cases, it's preferable to just wait for some time, if
+                // > the application will likely close some files (or connections), and try
+                // > to accept the connection again. If this option is `true`, the error
+                // > will be logged at the `error` level, since it is still a big deal,
+                // > and then the listener will sleep for 1 second.
+                log::error!("accept error: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, SocketFile { path, id }) = self {
+            use std::os::unix::fs::MetadataExt;
+
+            // the guest may have replaced the socket, or a directory of its path, in the meantime
+            let metadata = std::fs::symlink_metadata(&*path);
+            if !metadata.is_ok_and(|m| (m.dev(), m.ino()) == *id) {
+                log::warn!("not removing unix socket {path:?}, which was replaced");
+                return;
+            }
+            if let Err(e) = std::fs::remove_file(&*path) {
+                log::warn!("failed to remove unix socket {path:?}: {e}");
             }
+        }
+    }
+}
+
+/// A file of the container, in one of the directories of the container filesystem.
+#[derive(Clone, Debug)]
+struct ContainerFile {
+    /// The path of the file in the container.
+    path: PathBuf,
+    /// The innermost directory of the container the file is in.
+    preopen: Preopen,
+}
+
+impl ContainerFile {
+    fn new(ctx: &impl RuntimeContext, path: &Path) -> Result<Self> {
+        let preopen = ctx
+            .preopens()
+            .into_iter()
+            .filter(|preopen| path.starts_with(&preopen.guest_path))
+            .max_by_key(|preopen| preopen.guest_path.components().count())
+            .with_context(|| format!("{path:?} is not in the filesystem of the container"))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            preopen,
+        })
+    }
+
+    /// The path of the file as seen by the shim.
+    ///
+    /// This is the same path when running in a container, but the container
+    /// filesystem is somewhere else when running inside the shim process.
+    /// Symlinks are resolved inside the directory of the container, and again on
+    /// every call, so that the path follows symlinks that are replaced, e.g., when
+    /// a Kubernetes secret is updated.
+    fn host_path(&self) -> Result<PathBuf> {
+        self.preopen
+            .resolve(&self.path)
+            .context("file is not in its directory")?
+            .with_context(|| format!("failed to resolve {:?} in the container", self.path))
+    }
+}
+
+/// Returns the value of a proxy setting from the `wasmtime.runwasi.io/<name>` annotation,
+/// falling back to the `env_key` environment variable.
+/// The environment variable is always removed from `env` so that it isn't exposed to the guest.
+fn setting<T: FromStr>(
+    ctx: &impl RuntimeContext,
+    env: &mut HashMap<String, String>,
+    name: &str,
+    env_key: &str,
+) -> Option<T> {
+    let (key, value) = setting_value(ctx, env, name, env_key)?;
+    let value = value.parse().ok();
+    if value.is_none() {
+        log::warn!("ignoring invalid value for {key}");
+    }
+    value
+}
+
+/// Like [`setting`], but fails on an invalid value rather than ignoring it,
+/// for the settings that would otherwise silently turn off a limit.
+fn checked_setting<T: FromStr>(
+    ctx: &impl RuntimeContext,
+    env: &mut HashMap<String, String>,
+    name: &str,
+    env_key: &str,
+) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    let Some((key, value)) = setting_value(ctx, env, name, env_key) else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => bail!("invalid value for {key}: {value:?}: {err}"),
+    }
+}
+
+/// Returns the key and the raw value of a proxy setting, see [`setting`].
+fn setting_value(
+    ctx: &impl RuntimeContext,
+    env: &mut HashMap<String, String>,
+    name: &str,
+    env_key: &str,
+) -> Option<(String, String)> {
+    let env_value = env.remove(env_key);
+    let annotation = format!("{ANNOTATION_PREFIX}/{name}");
+    match ctx.annotations().get(&annotation) {
+        Some(value) => Some((annotation, value.clone())),
+        None => env_value.map(|value| (env_key.to_string(), value)),
+    }
+}
+
+/// Limits of the proxy, so that one slow handler can't exhaust the node.
+#[derive(Clone, Copy, Debug, Default)]
+struct ProxyLimits {
+    /// Maximum number of requests handled at the same time.
+    /// Further requests are rejected with a 503.
+    max_concurrent_requests: Option<usize>,
+    /// Deadline of the handler of each request.
+    /// A 504 is returned if the handler didn't respond by then.
+    request_timeout: Option<Duration>,
+    /// How long a connection can be idle before it's closed.
+    idle_timeout: Option<Duration>,
+    /// Maximum size of a request body.
+    /// Requests with a larger `content-length` are rejected with a 413.
+    max_body_size: Option<u64>,
+}
+
+impl ProxyLimits {
+    fn new(ctx: &impl RuntimeContext, env: &mut HashMap<String, String>) -> Result<Self> {
+        let duration = |env: &mut HashMap<String, String>, name, env_key| {
+            checked_setting::<humantime::Duration>(ctx, env, name, env_key)
+                .map(|value| value.map(Into::into))
+        };
+        let limits = Self {
+            max_concurrent_requests: checked_setting(
+                ctx,
+                env,
+                "http-proxy-max-concurrent-requests",
+                "WASMTIME_HTTP_PROXY_MAX_CONCURRENT_REQUESTS",
+            )?,
+            request_timeout: duration(
+                env,
+                "http-proxy-request-timeout",
+                "WASMTIME_HTTP_PROXY_REQUEST_TIMEOUT",
+            )?,
+            idle_timeout: duration(
+                env,
+                "http-proxy-idle-timeout",
+                "WASMTIME_HTTP_PROXY_IDLE_TIMEOUT",
+            )?,
+            max_body_size: checked_setting(
+                ctx,
+                env,
+                "http-proxy-max-body-size",
+                "WASMTIME_HTTP_PROXY_MAX_BODY_SIZE",
+            )?,
+        };
+        ensure!(
+            limits.max_concurrent_requests != Some(0),
+            "http-proxy-max-concurrent-requests must be at least 1, or no request could be handled"
+        );
+        Ok(limits)
+    }
+}
+
+/// Settings of the HTTP/1.1 and HTTP/2 connections of the proxy.
+#[derive(Clone, Copy, Debug)]
+struct ConnectionSettings {
+    /// Whether HTTP/1.1 connections are kept alive between requests.
+    keep_alive: bool,
+    /// Interval of the HTTP/2 pings that keep connections alive.
+    keep_alive_interval: Option<Duration>,
+    /// How long to wait for the acknowledgement of an HTTP/2 ping before closing the connection.
+    keep_alive_timeout: Option<Duration>,
+    /// Maximum number of headers of HTTP/1.1 requests.
+    max_headers: Option<usize>,
+    /// Maximum size in bytes of the headers of HTTP/2 requests.
+    max_header_list_size: Option<u32>,
+}
+
+impl ConnectionSettings {
+    fn new(ctx: &impl RuntimeContext, env: &mut HashMap<String, String>) -> Self {
+        let duration = |env: &mut HashMap<String, String>, name, env_key| {
+            setting::<humantime::Duration>(ctx, env, name, env_key).map(Into::into)
+        };
+        Self {
+            keep_alive: setting(
+                ctx,
+                env,
+                "http-proxy-keep-alive",
+                "WASMTIME_HTTP_PROXY_KEEP_ALIVE",
+            )
+            .unwrap_or(true),
+            keep_alive_interval: duration(
+                env,
+                "http-proxy-keep-alive-interval",
+                "WASMTIME_HTTP_PROXY_KEEP_ALIVE_INTERVAL",
+            ),
+            keep_alive_timeout: duration(
+                env,
+                "http-proxy-keep-alive-timeout",
+                "WASMTIME_HTTP_PROXY_KEEP_ALIVE_TIMEOUT",
+            ),
+            max_headers: setting(
+                ctx,
+                env,
+                "http-proxy-max-headers",
+                "WASMTIME_HTTP_PROXY_MAX_HEADERS",
+            ),
+            max_header_list_size: setting(
+                ctx,
+                env,
+                "http-proxy-max-header-list-size",
+                "WASMTIME_HTTP_PROXY_MAX_HEADER_LIST_SIZE",
+            ),
+        }
+    }
+
+    /// A builder of connections that serve both HTTP/1.1 and HTTP/2 with prior knowledge.
+    fn builder(&self, limits: &ProxyLimits) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+
+        let mut http1 = builder.http1();
+        http1.keep_alive(self.keep_alive);
+        if let Some(max) = self.max_headers {
+            http1.max_headers(max);
+        }
+        if let Some(idle_timeout) = limits.idle_timeout {
+            // hyper waits for the headers of the next request while the connection is idle
+            http1
+                .timer(TokioTimer::new())
+                .header_read_timeout(idle_timeout);
+        }
 
-            // [From `hyper::Server` in 0.14](https://github.com/hyperium/hyper/blob/v0.14.27/src/server/tcp.rs#L186)
-            //
-            // > A possible scenario is that the process has hit the max open files
-            // > allowed, and so trying to accept a new connection will fail with
-            // > `EMFILE`. In some cases, it's preferable to just wait for some time, if
-            // > the application will likely close some files (or connections), and try
-            // > to accept the connection again. If this option is `true`, the error
-            // > will be logged at the `error` level, since it is still a big deal,
-            // > and then the listener will sleep for 1 second.
-            log::error!("accept error: {e}");
-            tokio::time::sleep(Duration::from_secs(1)).await;
-            None
+        let mut http2 = builder.http2();
+        http2.timer(TokioTimer::new());
+        http2.keep_alive_interval(self.keep_alive_interval);
+        if let Some(timeout) = self.keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+        if let Some(max) = self.max_header_list_size {
+            http2.max_header_list_size(max);
         }
+
+        builder
+    }
+}
+
+/// The handler of a request didn't respond before the request timeout.
+#[derive(Debug)]
+struct RequestTimeout(Duration);
+
+impl Display for RequestTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request deadline of {:?} exceeded", self.0)
     }
 }
 
+impl std::error::Error for RequestTimeout {}
+
 pub(crate) async fn serve_conn(
     ctx: &impl RuntimeContext,
     instance: ProxyPre<WasiPreview2Ctx>,
     cancel: CancellationToken,
+    stop: StopSignal,
 ) -> Result<()> {
     let mut env = envs_from_ctx(ctx).into_iter().collect::<HashMap<_, _>>();
 
-    // Consume env variables for Proxy server settings before passing it to handler
-    let addr = env
-        .remove("WASMTIME_HTTP_PROXY_SOCKET_ADDR")
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(DEFAULT_ADDR);
-    let backlog = env
-        .remove("WASMTIME_HTTP_BACKLOG")
-        .and_then(|v| v.parse().ok())
+    // Consume env variables for Proxy server settings before passing it to handler.
+    // Annotations take precedence over the env variables.
+    let addr = setting(
+        ctx,
+        &mut env,
+        "http-proxy-socket-addr",
+        "WASMTIME_HTTP_PROXY_SOCKET_ADDR",
+    )
+    .unwrap_or(DEFAULT_ADDR);
+    let backlog = setting(ctx, &mut env, "http-proxy-backlog", "WASMTIME_HTTP_BACKLOG")
         .unwrap_or(DEFAULT_BACKLOG);
+    let mode = setting(
+        ctx,
+        &mut env,
+        "http-proxy-socket-mode",
+        "WASMTIME_HTTP_PROXY_SOCKET_MODE",
+    );
+    let limits = ProxyLimits::new(ctx, &mut env)?;
+    let settings = ConnectionSettings::new(ctx, &mut env);
+    let tls = Tls::new(ctx, &mut env)?;
+    let grace_period = setting::<humantime::Duration>(
+        ctx,
+        &mut env,
+        "http-proxy-shutdown-grace-period",
+        "WASMTIME_HTTP_PROXY_SHUTDOWN_GRACE_PERIOD",
+    )
+    .map(Duration::from);
 
-    let socket = match addr {
-        SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
-        SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
-    };
-
-    // Conditionally enable `SO_REUSEADDR` depending on the current
-    // platform. On Unix we want this to be able to rebind an address in
-    // the `TIME_WAIT` state which can happen then a server is killed with
-    // active TCP connections and then restarted. On Windows though if
-    // `SO_REUSEADDR` is specified then it enables multiple applications to
-    // bind the port at the same time which is not something we want. Hence
-    // this is conditionally set based on the platform (and deviates from
-    // Tokio's default from always-on).
-    socket.set_reuseaddr(!cfg!(windows))?;
-    socket.bind(addr)?;
-
-    let listener = socket.listen(backlog)?;
+    let listener = Listener::bind(ctx, &addr, backlog, mode)?;
     let tracker = TaskTracker::new();
 
-    log::info!("Serving HTTP on http://{}/", listener.local_addr()?);
+    let (scheme, url_scheme) = match tls {
+        Some(_) => (Scheme::Https, "https"),
+        None => (Scheme::Http, "http"),
+    };
+    let telemetry = Telemetry::new(ctx, &mut env, url_scheme)?;
+    let pool = InstancePool::new(ctx, &mut env);
+    let egress = EgressPolicy::new(ctx)?;
+    log::info!("Serving HTTP on {}", listener.url(url_scheme)?);
 
     let env = env.into_iter().collect();
-    let handler = Arc::new(ProxyHandler::new(instance, env, tracker.clone()));
-
-    loop {
-        let stream = tokio::select! {
-            conn = tcp_accept(&listener) => {
-                match conn {
-                    Some(conn) => conn,
-                    None => continue,
+    let handler = Arc::new(
+        ProxyHandler::new(
+            instance,
+            env,
+            ctx.resources(),
+            limits,
+            scheme,
+            telemetry,
+            tracker.clone(),
+        )
+        .with_instance_pool(pool)
+        .with_egress_policy(egress)
+        .with_stop_signal(stop),
+    );
+
+    let builder = Arc::new(settings.builder(&limits));
+    let acceptor = tls.as_ref().map(Tls::acceptor);
+
+    let accept = async {
+        loop {
+            let (stream, remote_addr) = tokio::select! {
+                conn = listener.accept() => {
+                    match conn {
+                        Some(conn) => conn,
+                        None => continue,
+                    }
+                }
+                _ = cancel.cancelled() => {
+                    break;
+                }
+            };
+
+            let h = handler.clone();
+            let builder = builder.clone();
+            let acceptor = acceptor.clone();
+            let cancel = cancel.clone();
+            tracker.spawn(async move {
+                let result = match acceptor {
+                    Some(acceptor) => {
+                        // the idle timeout also bounds the handshake, so that it can't hold the connection forever
+                        let handshake = acceptor.accept(stream);
+                        let stream = match limits.idle_timeout {
+                            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                                .await
+                                .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into())),
+                            None => handshake.await,
+                        };
+                        match stream {
+                            Ok(stream) => {
+                                serve_connection(builder, stream, remote_addr, h, cancel).await
+                            }
+                            Err(e) => {
+                                log::debug!("TLS handshake failed: {e}");
+                                return;
+                            }
+                        }
+                    }
+                    None => serve_connection(builder, stream, remote_addr, h, cancel).await,
+                };
+                if let Err(e) = result {
+                    log::error!("error: {e:?}");
                 }
+            });
+        }
+    };
+    let reload = async {
+        if let Some(tls) = &tls {
+            tls.watch(cancel.clone()).await;
+        }
+    };
+    tokio::join!(accept, reload);
+    // refuse new connections while draining, instead of leaving them in the backlog
+    drop(listener);
+
+    // Connections close once their current request is done, and the handlers
+    // keep running until they finish, or until the grace period is over.
+    tracker.close();
+    match grace_period {
+        Some(grace_period) => {
+            if tokio::time::timeout(grace_period, tracker.wait())
+                .await
+                .is_err()
+            {
+                log::warn!(
+                    "dropping {} connections and requests still in flight after {grace_period:?}",
+                    tracker.len()
+                );
             }
-            _ = cancel.cancelled() => {
-                break;
+        }
+        None => tracker.wait().await,
+    }
+
+    Ok(())
+}
+
+async fn serve_connection(
+    builder: Arc<auto::Builder<TokioExecutor>>,
+    stream: impl Connection,
+    remote_addr: Option<SocketAddr>,
+    handler: Arc<ProxyHandler>,
+    cancel: CancellationToken,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let requests = ConnectionRequests::default();
+    let idle = requests.idle(handler.limits.idle_timeout);
+    let service = {
+        let requests = requests.clone();
+        let builder = builder.clone();
+        let cancel = cancel.clone();
+        hyper::service::service_fn(move |req| {
+            let request = requests.start();
+            let handler = handler.clone();
+            let builder = builder.clone();
+            let cancel = cancel.clone();
+            async move {
+                let response = match h2c::upgrade_request(&req) {
+                    Some(frames) if matches!(handler.scheme, Scheme::Http) => {
+                        upgrade_h2c(req, frames, &builder, remote_addr, handler, cancel)
+                    }
+                    _ => handler.handle_request(req, remote_addr).await?,
+                };
+                // the connection isn't idle until the response is sent
+                anyhow::Ok(response.map(|body| {
+                    body.map_frame(move |frame| {
+                        let _request = &request;
+                        frame
+                    })
+                    .boxed()
+                }))
             }
+        })
+    };
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+    tokio::pin!(conn);
+    tokio::pin!(idle);
+
+    tokio::select! {
+        result = conn.as_mut() => return result,
+        _ = cancel.cancelled() => {}
+        _ = idle => {
+            log::debug!("closing idle connection");
+        }
+    }
+    // finish the requests of the connection, but don't accept new ones
+    conn.as_mut().graceful_shutdown();
+    conn.await
+}
+
+/// Answers a request asking to upgrade to h2c, and serves the connection over HTTP/2 once upgraded.
+fn upgrade_h2c(
+    mut req: Request,
+    frames: hyper::body::Bytes,
+    builder: &auto::Builder<TokioExecutor>,
+    remote_addr: Option<SocketAddr>,
+    handler: Arc<ProxyHandler>,
+    cancel: CancellationToken,
+) -> hyper::Response<HyperOutgoingBody> {
+    let upgrade = hyper::upgrade::on(&mut req);
+    let builder = Arc::new(builder.clone().http2_only());
+    let tracker = handler.tracker.clone();
+    tracker.spawn(async move {
+        let handshake = async {
+            let upgraded = upgrade.await.map_err(std::io::Error::other)?;
+            h2c::handshake(hyper_util::rt::TokioIo::new(upgraded), frames).await
         };
+        let stream = tokio::select! {
+            stream = handshake => stream,
+            _ = cancel.cancelled() => return,
+        };
+        match stream {
+            Ok(stream) => {
+                if let Err(e) =
+                    serve_connection(builder, stream, remote_addr, handler, cancel).await
+                {
+                    log::error!("error: {e:?}");
+                }
+            }
+            Err(e) => log::debug!("h2c upgrade failed: {e}"),
+        }
+    });
+
+    let mut response = status_response(StatusCode::SWITCHING_PROTOCOLS);
+    let headers = response.headers_mut();
+    headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(UPGRADE, HeaderValue::from_static("h2c"));
+    response
+}
 
-        let stream = TokioIo::new(stream);
-        let h = handler.clone();
+/// The requests of a connection that are being handled, or whose response is being sent.
+#[derive(Clone, Default)]
+struct ConnectionRequests(Arc<watch::Sender<usize>>);
 
-        tracker.spawn(async {
-            if let Err(e) = http1::Builder::new()
-                .keep_alive(true)
-                .serve_connection(
-                    stream,
-                    hyper::service::service_fn(move |req| h.clone().handle_request(req)),
-                )
-                .await
-            {
-                log::error!("error: {e:?}");
+impl ConnectionRequests {
+    fn start(&self) -> ConnectionRequest {
+        self.0.send_modify(|requests| *requests += 1);
+        ConnectionRequest(self.0.clone())
+    }
+
+    /// Resolves once the connection had no request for the idle timeout, or never without one.
+    fn idle(&self, timeout: Option<Duration>) -> impl Future<Output = ()> + use<> {
+        let mut requests = self.0.subscribe();
+        async move {
+            let Some(timeout) = timeout else {
+                return std::future::pending().await;
+            };
+            loop {
+                let idle = *requests.borrow_and_update() == 0;
+                // the sender is never dropped before the receiver
+                let changed = requests.changed();
+                if !idle {
+                    let _ = changed.await;
+                } else if tokio::time::timeout(timeout, changed).await.is_err() {
+                    return;
+                }
             }
-        });
+        }
     }
+}
 
-    tracker.close();
-    tracker.wait().await;
+struct ConnectionRequest(Arc<watch::Sender<usize>>);
 
-    Ok(())
+impl Drop for ConnectionRequest {
+    fn drop(&mut self) {
+        self.0.send_modify(|requests| *requests -= 1);
+    }
+}
+
+/// An instance of the component, with its store.
+struct ProxyInstance {
+    store: Store<WasiPreview2Ctx>,
+    proxy: Proxy,
 }
 
 struct ProxyHandler {
     instance_pre: ProxyPre<WasiPreview2Ctx>,
     next_id: AtomicU64,
     env: Vec<(String, String)>,
+    /// The memory limit of the container, shared by the stores of all the requests.
+    limiter: MemoryLimiter,
+    limits: ProxyLimits,
+    /// The scheme of the requests, `https` when the proxy terminates TLS.
+    scheme: Scheme,
+    telemetry: Arc<Telemetry>,
+    /// The instances kept to handle further requests, if they're reused.
+    pool: Option<Arc<InstancePool<ProxyInstance>>>,
+    /// The policy of the outgoing requests of the guest.
+    egress: Arc<EgressPolicy>,
+    /// Stops the handlers of the requests once a guest running inside the shim process is killed.
+    stop: StopSignal,
+    /// Permits for the requests being handled, if their number is limited.
+    in_flight: Option<Arc<Semaphore>>,
     tracker: TaskTracker,
 }
 
@@ -152,16 +791,45 @@ impl ProxyHandler {
     fn new(
         instance_pre: ProxyPre<WasiPreview2Ctx>,
         env: Vec<(String, String)>,
+        resources: ResourceLimits,
+        limits: ProxyLimits,
+        scheme: Scheme,
+        telemetry: Telemetry,
         tracker: TaskTracker,
     ) -> Self {
         ProxyHandler {
             instance_pre,
             env,
+            limiter: MemoryLimiter::new(&resources),
+            limits,
+            scheme,
+            telemetry: Arc::new(telemetry),
+            pool: None,
+            egress: Default::default(),
+            stop: Default::default(),
+            in_flight: limits
+                .max_concurrent_requests
+                .map(|max| Arc::new(Semaphore::new(max))),
             tracker,
             next_id: AtomicU64::from(0),
         }
     }
 
+    fn with_instance_pool(mut self, pool: Option<InstancePool<ProxyInstance>>) -> Self {
+        self.pool = pool.map(Arc::new);
+        self
+    }
+
+    fn with_egress_policy(mut self, egress: EgressPolicy) -> Self {
+        self.egress = Arc::new(egress);
+        self
+    }
+
+    fn with_stop_signal(mut self, stop: StopSignal) -> Self {
+        self.stop = stop;
+        self
+    }
+
     fn wasi_store_for_request(&self, req_id: u64) -> Store<WasiPreview2Ctx> {
         let engine = self.instance_pre.engine();
         let mut builder = wasmtime_wasi::WasiCtxBuilder::new();
@@ -173,17 +841,21 @@ impl ProxyHandler {
             wasi_ctx: builder.build(),
             wasi_http: WasiHttpCtx::new(),
             resource_table: ResourceTable::default(),
+            limiter: self.limiter.share(),
+            egress: self.egress.clone(),
         };
 
-        Store::new(engine, ctx)
+        let mut store = Store::new(engine, ctx);
+        store.limiter(|ctx| &mut ctx.limiter);
+        yield_on_epoch(&mut store, &self.stop);
+        store
     }
 
     async fn handle_request(
         self: Arc<Self>,
         req: Request,
+        remote_addr: Option<SocketAddr>,
     ) -> Result<hyper::Response<HyperOutgoingBody>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-
         let req_id = self.next_req_id();
 
         log::trace!(
@@ -192,22 +864,75 @@ impl ProxyHandler {
             req.uri()
         );
 
-        let mut store = self.wasi_store_for_request(req_id);
+        // the request is logged once its response is sent, or once it failed
+        let record = self.telemetry.start(req_id, &req, remote_addr);
+        let response = self.respond(req_id, req).await?;
+        Ok(record.finish(response))
+    }
+
+    async fn respond(
+        &self,
+        req_id: u64,
+        req: Request,
+    ) -> Result<hyper::Response<HyperOutgoingBody>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        // the permit is held until the handler finishes, even after it responded
+        let permit = match &self.in_flight {
+            Some(in_flight) => match in_flight.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    log::warn!("[{req_id}] too many concurrent requests");
+                    return Ok(status_response(StatusCode::SERVICE_UNAVAILABLE));
+                }
+            },
+            None => None,
+        };
+
+        if let Some(max) = self.limits.max_body_size {
+            if content_length(&req).is_some_and(|len| len > max) {
+                log::warn!("[{req_id}] request body is larger than {max} bytes");
+                return Ok(status_response(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+        }
+
+        let mut instance = self.instance(req_id).await?;
+        let store = &mut instance.instance.store;
 
-        let req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
+        let req = match self.limits.max_body_size {
+            Some(max) => {
+                new_limited_incoming_request(store.data_mut(), req, self.scheme.clone(), max)?
+            }
+            None => store
+                .data_mut()
+                .new_incoming_request(self.scheme.clone(), req)?,
+        };
         let out = store.data_mut().new_response_outparam(sender)?;
-        let proxy = self.instance_pre.instantiate_async(&mut store).await?;
 
+        let request_timeout = self.limits.request_timeout;
+        let pool = self.pool.clone();
         let task = self.tracker.spawn(async move {
-            if let Err(e) = proxy
+            let _permit = permit;
+            let ProxyInstance { store, proxy } = &mut instance.instance;
+            let handle = proxy
                 .wasi_http_incoming_handler()
-                .call_handle(store, req, out)
-                .await
-            {
+                .call_handle(store, req, out);
+            // epoch yields let the timeout interrupt a handler stuck in a loop
+            let result = match request_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, handle)
+                    .await
+                    .unwrap_or_else(|_| Err(RequestTimeout(timeout).into())),
+                None => handle.await,
+            };
+            if let Err(e) = result {
+                // the instance is dropped, as its state may be inconsistent after a trap
                 log::error!("[{req_id}] :: {:#?}", e);
                 return Err(e);
             }
 
+            if let Some(pool) = pool {
+                pool.put(instance);
+            }
             Ok(())
         });
 
@@ -229,12 +954,65 @@ impl ProxyHandler {
                     Err(e) => e.into(),
                 };
 
+                if e.is::<RequestTimeout>() {
+                    return Ok(status_response(StatusCode::GATEWAY_TIMEOUT));
+                }
+
                 bail!("guest never invoked `response-outparam::set` method: {e:?}")
             }
         }
     }
 
+    /// An idle instance of the component if they're reused, or a new one.
+    async fn instance(&self, req_id: u64) -> Result<Pooled<ProxyInstance>> {
+        if let Some(instance) = self.pool.as_ref().and_then(|pool| pool.take()) {
+            log::trace!(
+                "[{req_id}] reusing an instance that handled {} requests",
+                instance.uses()
+            );
+            return Ok(instance);
+        }
+        let mut store = self.wasi_store_for_request(req_id);
+        let proxy = self.instance_pre.instantiate_async(&mut store).await?;
+        Ok(Pooled::new(ProxyInstance { store, proxy }))
+    }
+
     fn next_req_id(&self) -> u64 {
         self.next_id.fetch_add(1, Ordering::Relaxed)
     }
 }
+
+fn status_response(status: StatusCode) -> hyper::Response<HyperOutgoingBody> {
+    let mut response = hyper::Response::new(HyperOutgoingBody::default());
+    *response.status_mut() = status;
+    response
+}
+
+fn content_length<B>(req: &hyper::Request<B>) -> Option<u64> {
+    req.headers()
+        .get(hyper::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Like [`WasiHttpView::new_incoming_request`], but reading more than `limit`
+/// bytes of the body fails.
+fn new_limited_incoming_request(
+    view: &mut WasiPreview2Ctx,
+    req: Request,
+    scheme: Scheme,
+    limit: u64,
+) -> Result<Resource<HostIncomingRequest>> {
+    let (parts, body) = req.into_parts();
+    let body = Limited::new(body, limit as usize)
+        .map_err(move |err| match err.downcast::<hyper::Error>() {
+            Ok(err) => hyper_request_error(*err),
+            Err(_) => ErrorCode::HttpRequestBodySize(Some(limit)),
+        })
+        .boxed();
+    let body = HostIncomingBody::new(body, BETWEEN_BYTES_TIMEOUT);
+    let req = HostIncomingRequest::new(view, parts, scheme, Some(body))?;
+    Ok(WasiHttpView::table(view).push(req)?)
+}
@@ -0,0 +1,360 @@
+//! Upgrade of HTTP/1.1 connections to HTTP/2 without TLS (h2c, RFC 7540 section 3.2).
+//!
+//! After the `101 Switching Protocols` response, the request that asked for the upgrade
+//! is answered on the stream 1 of the HTTP/2 connection. hyper doesn't know about that
+//! stream, so the request is replayed to it as if the client had sent it in a HEADERS
+//! frame, right after the connection preface and the SETTINGS frame of the client.
+//! The settings of the `HTTP2-Settings` header aren't applied, since the client sends
+//! them again in that SETTINGS frame anyway.
+//!
+//! Only requests without a body are upgraded, the others are served over HTTP/1.1,
+//! which the protocol allows.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::body::{Buf, Bytes};
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Method, Request, Version};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+/// The default `SETTINGS_MAX_FRAME_SIZE`, which the frames replayed to the server don't exceed.
+const MAX_FRAME_SIZE: usize = 16_384;
+
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// Headers that are specific to the HTTP/1.1 connection, and aren't allowed in HTTP/2.
+const CONNECTION_HEADERS: [HeaderName; 6] = [
+    header::CONNECTION,
+    header::HOST,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    HeaderName::from_static("http2-settings"),
+    HeaderName::from_static("keep-alive"),
+];
+
+/// Returns the frames replaying the request on the stream 1 if it asks to upgrade to h2c,
+/// or `None` if it's to be served over HTTP/1.1.
+pub(crate) fn upgrade_request<B>(req: &Request<B>) -> Option<Bytes> {
+    if req.version() != Version::HTTP_11
+        || req.method() == Method::CONNECT
+        || !has_token(req, header::UPGRADE, "h2c")
+        || !has_token(req, header::CONNECTION, "upgrade")
+        || !has_token(req, header::CONNECTION, "http2-settings")
+        || req.headers().get_all("http2-settings").iter().count() != 1
+        || req.headers().contains_key(header::TRANSFER_ENCODING)
+        || req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .is_some_and(|len| len != "0")
+    {
+        return None;
+    }
+    Some(headers_frames(req))
+}
+
+/// Whether the comma separated values of a header contain a token, ignoring the case.
+fn has_token<B>(req: &Request<B>, name: HeaderName, token: &str) -> bool {
+    req.headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// The HEADERS frame, and its CONTINUATION frames, of the request on the stream 1.
+fn headers_frames<B>(req: &Request<B>) -> Bytes {
+    let authority = req
+        .headers()
+        .get(header::HOST)
+        .map(HeaderValue::as_bytes)
+        .or_else(|| req.uri().authority().map(|a| a.as_str().as_bytes()))
+        .unwrap_or_default();
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+
+    // headers listed in `Connection` are specific to the connection too
+    let connection = req
+        .headers()
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    let mut block = Vec::new();
+    encode_header(&mut block, b":method", req.method().as_str().as_bytes());
+    encode_header(&mut block, b":scheme", b"http");
+    encode_header(&mut block, b":authority", authority);
+    encode_header(&mut block, b":path", path.as_bytes());
+    for (name, value) in req.headers() {
+        if CONNECTION_HEADERS.contains(name) || connection.contains(name) {
+            continue;
+        }
+        // `te` is only allowed with `trailers`
+        if name == header::TE && value != "trailers" {
+            continue;
+        }
+        encode_header(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+
+    let mut frames = Vec::with_capacity(block.len() + FRAME_HEADER_LEN);
+    let mut chunks = block.chunks(MAX_FRAME_SIZE).peekable();
+    let mut kind = FRAME_HEADERS;
+    let mut flags = FLAG_END_STREAM;
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        frames.extend_from_slice(&frame_header(chunk.len(), kind, flags, 1));
+        frames.extend_from_slice(chunk);
+        kind = FRAME_CONTINUATION;
+        flags = 0;
+    }
+    frames.into()
+}
+
+fn frame_header(len: usize, kind: u8, flags: u8, stream: u32) -> [u8; FRAME_HEADER_LEN] {
+    let len = (len as u32).to_be_bytes();
+    let stream = stream.to_be_bytes();
+    [
+        len[1], len[2], len[3], kind, flags, stream[0], stream[1], stream[2], stream[3],
+    ]
+}
+
+/// Encodes a header as a literal without indexing, so that the HPACK state of the server
+/// doesn't depend on it.
+fn encode_header(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    block.push(0);
+    encode_string(block, name);
+    encode_string(block, value);
+}
+
+/// Encodes a string literal without Huffman coding.
+fn encode_string(block: &mut Vec<u8>, s: &[u8]) {
+    encode_integer(block, s.len(), 7);
+    block.extend_from_slice(s);
+}
+
+fn encode_integer(block: &mut Vec<u8>, mut value: usize, prefix_bits: u32) {
+    let max = (1 << prefix_bits) - 1;
+    if value < max {
+        block.push(value as u8);
+        return;
+    }
+    block.push(max as u8);
+    value -= max;
+    while value >= 128 {
+        block.push((value % 128) as u8 | 0x80);
+        value /= 128;
+    }
+    block.push(value as u8);
+}
+
+/// Reads the connection preface and the SETTINGS frame the client sends after the upgrade,
+/// and returns the connection that replays them to the server, followed by the request.
+pub(crate) async fn handshake<T: AsyncRead + Unpin>(
+    mut io: T,
+    request: Bytes,
+) -> io::Result<Upgraded<T>> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut prefix = vec![0; PREFACE.len() + FRAME_HEADER_LEN];
+    io.read_exact(&mut prefix).await?;
+    if &prefix[..PREFACE.len()] != PREFACE {
+        return Err(invalid("invalid HTTP/2 connection preface"));
+    }
+
+    let header = &prefix[PREFACE.len()..];
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if header[3] != FRAME_SETTINGS || header[4] & FLAG_ACK != 0 || header[5..] != [0; 4] {
+        return Err(invalid(
+            "expected a SETTINGS frame after the connection preface",
+        ));
+    }
+    if len > MAX_FRAME_SIZE {
+        return Err(invalid("SETTINGS frame is too large"));
+    }
+
+    let start = prefix.len();
+    prefix.resize(start + len, 0);
+    io.read_exact(&mut prefix[start..]).await?;
+    prefix.extend_from_slice(&request);
+
+    Ok(Upgraded {
+        prefix: prefix.into(),
+        io,
+    })
+}
+
+/// A connection upgraded to HTTP/2, which reads the replayed frames first.
+pub(crate) struct Upgraded<T> {
+    prefix: Bytes,
+    io: T,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Upgraded<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.prefix.has_remaining() {
+            let len = self.prefix.len().min(buf.remaining());
+            buf.put_slice(&self.prefix[..len]);
+            self.prefix.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Upgraded<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade(builder: hyper::http::request::Builder) -> Option<Bytes> {
+        upgrade_request(&builder.body(()).unwrap())
+    }
+
+    fn h2c() -> hyper::http::request::Builder {
+        Request::get("http://localhost/hello?a=b")
+            .header(header::HOST, "localhost")
+            .header(header::CONNECTION, "Upgrade, HTTP2-Settings")
+            .header(header::UPGRADE, "h2c")
+            .header("http2-settings", "AAMAAABkAAQAoAAAAAIAAAAA")
+    }
+
+    #[test]
+    fn test_upgrade_request() {
+        let frames = upgrade(h2c().header(header::ACCEPT, "*/*")).unwrap();
+
+        let mut block = Vec::new();
+        encode_header(&mut block, b":method", b"GET");
+        encode_header(&mut block, b":scheme", b"http");
+        encode_header(&mut block, b":authority", b"localhost");
+        encode_header(&mut block, b":path", b"/hello?a=b");
+        encode_header(&mut block, b"accept", b"*/*");
+
+        let header = frame_header(
+            block.len(),
+            FRAME_HEADERS,
+            FLAG_END_STREAM | FLAG_END_HEADERS,
+            1,
+        );
+        assert_eq!(frames[..FRAME_HEADER_LEN], header);
+        assert_eq!(frames[FRAME_HEADER_LEN..], block);
+    }
+
+    #[test]
+    fn test_upgrade_request_not_upgraded() {
+        assert!(upgrade(h2c()).is_some());
+        assert!(upgrade(h2c().version(Version::HTTP_10)).is_none());
+        assert!(upgrade(h2c().header(header::CONTENT_LENGTH, "3")).is_none());
+        assert!(upgrade(h2c().header(header::TRANSFER_ENCODING, "chunked")).is_none());
+        assert!(upgrade(Request::get("/").header(header::UPGRADE, "h2c")).is_none());
+        assert!(
+            upgrade(
+                Request::get("/")
+                    .header(header::CONNECTION, "upgrade")
+                    .header(header::UPGRADE, "websocket")
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_continuation_frames() {
+        let frames = upgrade(h2c().header("x-large", "a".repeat(MAX_FRAME_SIZE))).unwrap();
+
+        let len = |frame: &[u8]| u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+        assert_eq!(len(&frames), MAX_FRAME_SIZE);
+        assert_eq!(frames[3], FRAME_HEADERS);
+        assert_eq!(frames[4], FLAG_END_STREAM);
+
+        let continuation = &frames[FRAME_HEADER_LEN + MAX_FRAME_SIZE..];
+        assert_eq!(continuation[3], FRAME_CONTINUATION);
+        assert_eq!(continuation[4], FLAG_END_HEADERS);
+        assert_eq!(continuation.len(), FRAME_HEADER_LEN + len(continuation));
+    }
+
+    #[test]
+    fn test_encode_integer() {
+        // examples of RFC 7541 appendix C.1
+        let mut block = Vec::new();
+        encode_integer(&mut block, 10, 5);
+        assert_eq!(block, [10]);
+
+        let mut block = Vec::new();
+        encode_integer(&mut block, 1337, 5);
+        assert_eq!(block, [31, 154, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let settings = [
+            frame_header(6, FRAME_SETTINGS, 0, 0).as_slice(),
+            &[0, 3, 0, 0, 0, 100],
+        ]
+        .concat();
+        let client = [PREFACE, &settings, b"rest"].concat();
+
+        let mut upgraded = handshake(client.as_slice(), Bytes::from_static(b"request"))
+            .await
+            .unwrap();
+        let mut read = Vec::new();
+        upgraded.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, [PREFACE, &settings, b"request", b"rest"].concat());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_invalid_preface() {
+        let client = [b"GET / HTTP/1.1\r\n\r\n".as_slice(), &[0; 16]].concat();
+        let err = handshake(client.as_slice(), Bytes::new())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,133 @@
+//! Reuse of instances of the component across requests.
+//!
+//! By default every request gets a new instance of the component, so that requests
+//! can't observe each other. Handlers with an expensive initialization can opt in to
+//! a pool of instances instead, which are reused by the following requests, one
+//! request at a time. An instance is discarded when its handler fails, e.g., with a
+//! trap, since its state can't be trusted anymore.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use containerd_shim_wasm::sandbox::context::RuntimeContext;
+
+use super::setting;
+
+/// The idle instances of the component, waiting for a request.
+pub(crate) struct InstancePool<T> {
+    idle: Mutex<Vec<Pooled<T>>>,
+    /// Maximum number of idle instances.
+    size: usize,
+    /// Maximum number of requests an instance handles before it's discarded.
+    max_reuse: Option<u32>,
+}
+
+/// An instance, with the number of requests it handled.
+pub(crate) struct Pooled<T> {
+    pub(crate) instance: T,
+    uses: u32,
+}
+
+impl<T> Pooled<T> {
+    pub(crate) fn new(instance: T) -> Self {
+        Self { instance, uses: 0 }
+    }
+
+    /// The number of requests the instance handled.
+    pub(crate) fn uses(&self) -> u32 {
+        self.uses
+    }
+}
+
+impl<T> InstancePool<T> {
+    /// Returns `None` if instances aren't reused, which is the default.
+    pub(crate) fn new(
+        ctx: &impl RuntimeContext,
+        env: &mut HashMap<String, String>,
+    ) -> Option<Self> {
+        let size = setting(
+            ctx,
+            env,
+            "http-proxy-instance-pool-size",
+            "WASMTIME_HTTP_PROXY_INSTANCE_POOL_SIZE",
+        );
+        let max_reuse = setting(
+            ctx,
+            env,
+            "http-proxy-instance-max-reuse",
+            "WASMTIME_HTTP_PROXY_INSTANCE_MAX_REUSE",
+        );
+        match size {
+            Some(0) | None => None,
+            Some(size) => Some(Self::with_limits(size, max_reuse)),
+        }
+    }
+
+    fn with_limits(size: usize, max_reuse: Option<u32>) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(size)),
+            size,
+            max_reuse,
+        }
+    }
+
+    /// Take an idle instance, if any.
+    pub(crate) fn take(&self) -> Option<Pooled<T>> {
+        // the most recently used instance is the most likely to be warm
+        self.idle.lock().unwrap().pop()
+    }
+
+    /// Return an instance that successfully handled a request.
+    /// It's discarded if it reached its maximum number of requests, or if the pool is full.
+    pub(crate) fn put(&self, mut pooled: Pooled<T>) {
+        pooled.uses += 1;
+        if self.max_reuse.is_some_and(|max| pooled.uses >= max) {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.size {
+            idle.push(pooled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let pool = InstancePool::with_limits(1, None);
+        assert!(pool.take().is_none());
+
+        pool.put(Pooled::new("a"));
+        let pooled = pool.take().unwrap();
+        assert_eq!((pooled.instance, pooled.uses()), ("a", 1));
+        // the instance is used by one request at a time
+        assert!(pool.take().is_none());
+
+        pool.put(pooled);
+        assert_eq!(pool.take().unwrap().uses(), 2);
+    }
+
+    #[test]
+    fn test_max_reuse() {
+        let pool = InstancePool::with_limits(1, Some(2));
+        pool.put(Pooled::new("a"));
+        let pooled = pool.take().unwrap();
+        // the second request was the last one of the instance
+        pool.put(pooled);
+        assert!(pool.take().is_none());
+    }
+
+    #[test]
+    fn test_size() {
+        let pool = InstancePool::with_limits(2, None);
+        for instance in ["a", "b", "c"] {
+            pool.put(Pooled::new(instance));
+        }
+        assert_eq!(pool.take().unwrap().instance, "b");
+        assert_eq!(pool.take().unwrap().instance, "a");
+        assert!(pool.take().is_none());
+    }
+}
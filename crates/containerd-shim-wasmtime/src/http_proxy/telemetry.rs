@@ -0,0 +1,416 @@
+//! Access logs and metrics of the requests handled by the HTTP proxy.
+//!
+//! The metrics follow the OpenTelemetry semantic conventions for HTTP servers, and
+//! are exported with the OTLP pipeline of the shim, when it's enabled.
+//! The access logs are written to the stdout of the container, one line per request.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use containerd_shim_wasm::sandbox::context::RuntimeContext;
+use http_body_util::BodyExt;
+use hyper::{Method, StatusCode, Version};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Histogram, Unit, UpDownCounter};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+
+use super::{content_length, setting};
+
+/// The format of the access logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AccessLogFormat {
+    /// The Common Log Format of web servers,
+    /// e.g., `10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /hello HTTP/1.1" 200 13`.
+    Common,
+    /// A JSON object per request.
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "common" => Ok(AccessLogFormat::Common),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => bail!("unknown access log format {s:?}"),
+        }
+    }
+}
+
+struct AccessLog {
+    format: AccessLogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    fn write(&self, record: &RequestRecord, duration: Duration) {
+        let mut line = match self.format {
+            AccessLogFormat::Common => common_log_line(record),
+            AccessLogFormat::Json => json_log_line(record, duration),
+        };
+        line.push('\n');
+        // a single write per line, so that the lines of concurrent requests don't interleave
+        if let Err(e) = self.out.lock().unwrap().write_all(line.as_bytes()) {
+            log::warn!("failed to write access log: {e}");
+        }
+    }
+}
+
+/// The access log and the instruments of the proxy.
+pub(crate) struct Telemetry {
+    access_log: Option<AccessLog>,
+    /// `http` or `https`.
+    scheme: &'static str,
+    container_id: String,
+    request_duration: Histogram<f64>,
+    active_requests: UpDownCounter<i64>,
+    request_body_size: Histogram<u64>,
+    response_body_size: Histogram<u64>,
+}
+
+impl Telemetry {
+    pub(crate) fn new(
+        ctx: &impl RuntimeContext,
+        env: &mut HashMap<String, String>,
+        scheme: &'static str,
+    ) -> Result<Self> {
+        let format: Option<AccessLogFormat> = setting(
+            ctx,
+            env,
+            "http-proxy-access-log",
+            "WASMTIME_HTTP_PROXY_ACCESS_LOG",
+        );
+        let access_log = match format {
+            Some(format) => {
+                // the stdout of the shim isn't the one of the container when running in-process
+                let out: Box<dyn Write + Send> = match ctx.stdio() {
+                    Some(stdio) => match &stdio.stdout {
+                        Some(stdout) => Box::new(stdout.try_clone()?),
+                        None => Box::new(std::io::sink()),
+                    },
+                    None => Box::new(std::io::stdout()),
+                };
+                Some(AccessLog {
+                    format,
+                    out: Mutex::new(out),
+                })
+            }
+            None => None,
+        };
+        Ok(Self::with_access_log(
+            access_log,
+            scheme,
+            ctx.container_id().to_string(),
+        ))
+    }
+
+    fn with_access_log(
+        access_log: Option<AccessLog>,
+        scheme: &'static str,
+        container_id: String,
+    ) -> Self {
+        let meter = opentelemetry::global::meter(env!("CARGO_PKG_NAME"));
+        Self {
+            access_log,
+            scheme,
+            container_id,
+            request_duration: meter
+                .f64_histogram("http.server.request.duration")
+                .with_unit(Unit::new("s"))
+                .with_description("Duration of HTTP server requests.")
+                .init(),
+            active_requests: meter
+                .i64_up_down_counter("http.server.active_requests")
+                .with_unit(Unit::new("{request}"))
+                .with_description("Number of active HTTP server requests.")
+                .init(),
+            request_body_size: meter
+                .u64_histogram("http.server.request.body.size")
+                .with_unit(Unit::new("By"))
+                .with_description("Size of HTTP server request bodies.")
+                .init(),
+            response_body_size: meter
+                .u64_histogram("http.server.response.body.size")
+                .with_unit(Unit::new("By"))
+                .with_description("Size of HTTP server response bodies.")
+                .init(),
+        }
+    }
+
+    /// Start recording a request.
+    pub(crate) fn start<B>(
+        self: &Arc<Self>,
+        req_id: u64,
+        req: &hyper::Request<B>,
+        remote_addr: Option<SocketAddr>,
+    ) -> RequestRecord {
+        let record = RequestRecord {
+            telemetry: self.clone(),
+            req_id,
+            remote_addr,
+            method: req.method().clone(),
+            target: req
+                .uri()
+                .path_and_query()
+                .map_or_else(|| req.uri().to_string(), ToString::to_string),
+            version: req.version(),
+            received: Utc::now(),
+            start: Instant::now(),
+            request_bytes: content_length(req),
+            status: None,
+            response_bytes: 0,
+        };
+        self.active_requests.add(1, &record.active_attributes());
+        record
+    }
+}
+
+/// A request being handled by the proxy.
+///
+/// The request is reported when this is dropped, which happens once its
+/// response body was sent, or when the request failed.
+pub(crate) struct RequestRecord {
+    telemetry: Arc<Telemetry>,
+    req_id: u64,
+    remote_addr: Option<SocketAddr>,
+    method: Method,
+    /// The path and query of the request.
+    target: String,
+    version: Version,
+    received: DateTime<Utc>,
+    start: Instant,
+    /// The `content-length` of the request, if any.
+    request_bytes: Option<u64>,
+    /// The status of the response, `None` until the handler responded.
+    status: Option<StatusCode>,
+    response_bytes: u64,
+}
+
+impl RequestRecord {
+    /// Count the bytes of the body of `response`, and report the request once it was sent.
+    pub(crate) fn finish(
+        mut self,
+        response: hyper::Response<HyperOutgoingBody>,
+    ) -> hyper::Response<HyperOutgoingBody> {
+        self.status = Some(response.status());
+        response.map(move |body| {
+            body.map_frame(move |frame| {
+                // borrow the whole record, so that the body owns it rather than just its byte count
+                let record = &mut self;
+                if let Some(data) = frame.data_ref() {
+                    record.response_bytes += data.len() as u64;
+                }
+                frame
+            })
+            .boxed()
+        })
+    }
+
+    fn active_attributes(&self) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("http.request.method", metric_method(&self.method)),
+            KeyValue::new("url.scheme", self.telemetry.scheme),
+            KeyValue::new("container.id", self.telemetry.container_id.clone()),
+        ]
+    }
+
+    fn attributes(&self) -> Vec<KeyValue> {
+        let mut attributes = self.active_attributes();
+        attributes.push(KeyValue::new(
+            "network.protocol.version",
+            protocol_version(self.version),
+        ));
+        match self.status {
+            Some(status) => attributes.push(KeyValue::new(
+                "http.response.status_code",
+                i64::from(status.as_u16()),
+            )),
+            None => attributes.push(KeyValue::new("error.type", "_OTHER")),
+        }
+        attributes
+    }
+}
+
+impl Drop for RequestRecord {
+    fn drop(&mut self) {
+        let duration = self.start.elapsed();
+        let telemetry = &self.telemetry;
+
+        telemetry.active_requests.add(-1, &self.active_attributes());
+        let attributes = self.attributes();
+        telemetry
+            .request_duration
+            .record(duration.as_secs_f64(), &attributes);
+        if let Some(request_bytes) = self.request_bytes {
+            telemetry
+                .request_body_size
+                .record(request_bytes, &attributes);
+        }
+        telemetry
+            .response_body_size
+            .record(self.response_bytes, &attributes);
+
+        if let Some(access_log) = &telemetry.access_log {
+            access_log.write(self, duration);
+        }
+    }
+}
+
+/// The method of a request, as a metric attribute.
+/// Unknown methods are reported as `_OTHER`, so that clients can't make up attribute values.
+fn metric_method(method: &Method) -> &'static str {
+    match *method {
+        Method::CONNECT => "CONNECT",
+        Method::DELETE => "DELETE",
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::PATCH => "PATCH",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::TRACE => "TRACE",
+        _ => "_OTHER",
+    }
+}
+
+fn protocol_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_11 => "1.1",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "_OTHER",
+    }
+}
+
+fn common_log_line(record: &RequestRecord) -> String {
+    let remote_addr = record
+        .remote_addr
+        .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+    let status = record
+        .status
+        .map_or_else(|| "-".to_string(), |status| status.as_u16().to_string());
+    // the format has a dash instead of an empty body
+    let response_bytes = match record.response_bytes {
+        0 => "-".to_string(),
+        bytes => bytes.to_string(),
+    };
+    format!(
+        "{remote_addr} - - [{}] \"{} {} {:?}\" {status} {response_bytes}",
+        record.received.format("%d/%b/%Y:%H:%M:%S %z"),
+        record.method,
+        record.target,
+        record.version,
+    )
+}
+
+fn json_log_line(record: &RequestRecord, duration: Duration) -> String {
+    serde_json::json!({
+        "time": record.received.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "request_id": record.req_id,
+        "remote_addr": record.remote_addr.map(|addr| addr.to_string()),
+        "method": record.method.as_str(),
+        "uri": record.target,
+        "protocol": format!("{:?}", record.version),
+        "status": record.status.map(|status| status.as_u16()),
+        "request_bytes": record.request_bytes,
+        "response_bytes": record.response_bytes,
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+
+    use super::*;
+
+    /// An access log output that can be read back.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Record a `GET /hello?name=wasm` request answered with `Hello, wasm!`, and return its log line.
+    async fn log_request(format: AccessLogFormat) -> String {
+        let output = Output::default();
+        let access_log = AccessLog {
+            format,
+            out: Mutex::new(Box::new(output.clone())),
+        };
+        let telemetry = Arc::new(Telemetry::with_access_log(
+            Some(access_log),
+            "http",
+            "test".to_string(),
+        ));
+
+        let req = hyper::Request::get("/hello?name=wasm")
+            .header(hyper::header::CONTENT_LENGTH, "4")
+            .body(())
+            .unwrap();
+        let record = telemetry.start(7, &req, Some("10.0.0.1:4321".parse().unwrap()));
+
+        let body = Full::new(Bytes::from("Hello, wasm!"))
+            .map_err(|never| match never {})
+            .boxed();
+        let response = record.finish(hyper::Response::new(body));
+        // the request is logged once its response was sent
+        assert!(output.0.lock().unwrap().is_empty());
+        let body = response.into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "Hello, wasm!");
+
+        let line = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        line.strip_suffix('\n').unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_common_log_format() {
+        let line = log_request(AccessLogFormat::Common).await;
+        assert!(line.starts_with("10.0.0.1 - - ["), "{line}");
+        assert!(
+            line.ends_with(" +0000] \"GET /hello?name=wasm HTTP/1.1\" 200 12"),
+            "{line}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_log_format() -> Result<()> {
+        let line = log_request(AccessLogFormat::Json).await;
+        let log: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(log["request_id"], 7);
+        assert_eq!(log["remote_addr"], "10.0.0.1:4321");
+        assert_eq!(log["method"], "GET");
+        assert_eq!(log["uri"], "/hello?name=wasm");
+        assert_eq!(log["protocol"], "HTTP/1.1");
+        assert_eq!(log["status"], 200);
+        assert_eq!(log["request_bytes"], 4);
+        assert_eq!(log["response_bytes"], 12);
+        assert!(log["duration_ms"].is_f64());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_method() {
+        assert_eq!(metric_method(&Method::GET), "GET");
+        let custom = Method::from_bytes(b"PURGE").unwrap();
+        assert_eq!(metric_method(&custom), "_OTHER");
+    }
+}
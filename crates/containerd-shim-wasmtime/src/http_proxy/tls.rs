@@ -0,0 +1,381 @@
+//! TLS termination for the HTTP proxy.
+//!
+//! The certificates and keys are read from the container filesystem, so they can
+//! be shipped in the image or mounted from a secret. They are reloaded when their
+//! files change, and the certificate to present is selected from the server name
+//! the client asked for (SNI).
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, bail, ensure};
+use containerd_shim_wasm::sandbox::context::RuntimeContext;
+use rustls::ServerConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+use super::{ContainerFile, setting};
+
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// TLS termination of the connections of the proxy.
+pub(crate) struct Tls {
+    resolver: Arc<CertResolver>,
+    acceptor: TlsAcceptor,
+    reload_interval: Duration,
+}
+
+impl Tls {
+    /// Returns `None` if no certificate is configured, in which case the proxy serves plain HTTP.
+    pub(crate) fn new(
+        ctx: &impl RuntimeContext,
+        env: &mut HashMap<String, String>,
+    ) -> Result<Option<Self>> {
+        let certs: Option<String> = setting(
+            ctx,
+            env,
+            "http-proxy-tls-cert",
+            "WASMTIME_HTTP_PROXY_TLS_CERT",
+        );
+        let keys: Option<String> = setting(
+            ctx,
+            env,
+            "http-proxy-tls-key",
+            "WASMTIME_HTTP_PROXY_TLS_KEY",
+        );
+        let reload_interval = setting::<humantime::Duration>(
+            ctx,
+            env,
+            "http-proxy-tls-reload-interval",
+            "WASMTIME_HTTP_PROXY_TLS_RELOAD_INTERVAL",
+        )
+        .map(Into::into)
+        .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+
+        let (certs, keys) = match (certs, keys) {
+            (None, None) => return Ok(None),
+            (Some(certs), Some(keys)) => (files(ctx, &certs)?, files(ctx, &keys)?),
+            _ => bail!("both a TLS certificate and a key are needed to serve HTTPS"),
+        };
+        ensure!(
+            !certs.is_empty() && certs.len() == keys.len(),
+            "expected a TLS key for each of the {} certificates, got {}",
+            certs.len(),
+            keys.len()
+        );
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let slots = certs
+            .into_iter()
+            .zip(keys)
+            .map(|(cert, key)| CertSlot::load(cert, key, &provider))
+            .collect::<Result<_>>()?;
+        let resolver = Arc::new(CertResolver { slots, provider });
+
+        let mut config = ServerConfig::builder_with_provider(resolver.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Some(Self {
+            resolver,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            reload_interval,
+        }))
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.clone()
+    }
+
+    /// Reload the certificates whose files changed, until `cancel` is triggered.
+    pub(crate) async fn watch(&self, cancel: CancellationToken) {
+        let mut interval = tokio::time::interval(self.reload_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.resolver.reload(),
+                _ = cancel.cancelled() => break,
+            }
+        }
+    }
+}
+
+/// Parse a comma separated list of paths in the container filesystem.
+fn files(ctx: &impl RuntimeContext, value: &str) -> Result<Vec<ContainerFile>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| ContainerFile::new(ctx, Path::new(path)))
+        .collect()
+}
+
+/// Selects the certificate to present from the server name the client asked for.
+///
+/// The first certificate is the default one, presented when the client didn't
+/// send a server name or when no certificate is valid for it.
+struct CertResolver {
+    slots: Vec<CertSlot>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl CertResolver {
+    fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let default = self.slots.first()?.key();
+        let Some(server_name) = server_name.and_then(|name| ServerName::try_from(name).ok()) else {
+            return Some(default);
+        };
+        self.slots
+            .iter()
+            .map(CertSlot::key)
+            .find(|key| is_valid_for(key, &server_name))
+            .or(Some(default))
+    }
+
+    fn reload(&self) {
+        for slot in &self.slots {
+            slot.reload(&self.provider);
+        }
+    }
+}
+
+impl Debug for CertResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.slots.iter().map(|slot| &slot.cert.path))
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.select(client_hello.server_name())
+    }
+}
+
+fn is_valid_for(key: &CertifiedKey, server_name: &ServerName<'_>) -> bool {
+    key.end_entity_cert()
+        .ok()
+        .and_then(|cert| webpki::EndEntityCert::try_from(cert).ok())
+        .is_some_and(|cert| cert.verify_is_valid_for_subject_name(server_name).is_ok())
+}
+
+/// A certificate and its key, with the files they were loaded from.
+struct CertSlot {
+    cert: ContainerFile,
+    key: ContainerFile,
+    loaded: RwLock<(Arc<CertifiedKey>, Modified)>,
+}
+
+/// The files of a certificate and its key once their symlinks are resolved,
+/// with the time they were last modified.
+type Modified = [(PathBuf, Option<SystemTime>); 2];
+
+impl CertSlot {
+    fn load(cert: ContainerFile, key: ContainerFile, provider: &CryptoProvider) -> Result<Self> {
+        let modified = modified(&cert, &key)?;
+        let certified_key = load_certified_key(&modified[0].0, &modified[1].0, provider)?;
+        Ok(Self {
+            cert,
+            key,
+            loaded: RwLock::new((Arc::new(certified_key), modified)),
+        })
+    }
+
+    fn key(&self) -> Arc<CertifiedKey> {
+        self.loaded.read().unwrap().0.clone()
+    }
+
+    /// Reload the certificate if its files changed, or if their symlinks point to other files.
+    /// The current certificate is kept if the new one can't be loaded, e.g., while it's being written.
+    fn reload(&self, provider: &CryptoProvider) {
+        let result = modified(&self.cert, &self.key).and_then(|modified| {
+            if self.loaded.read().unwrap().1 == modified {
+                return Ok(());
+            }
+            let key = load_certified_key(&modified[0].0, &modified[1].0, provider)?;
+            log::info!("reloaded TLS certificate {:?}", self.cert.path);
+            *self.loaded.write().unwrap() = (Arc::new(key), modified);
+            Ok(())
+        });
+        if let Err(err) = result {
+            log::warn!(
+                "failed to reload TLS certificate {:?}: {err:#}",
+                self.cert.path
+            );
+        }
+    }
+}
+
+fn modified(cert: &ContainerFile, key: &ContainerFile) -> Result<Modified> {
+    let modified = |file: &ContainerFile| {
+        let path = file.host_path()?;
+        let modified = path.metadata().and_then(|m| m.modified()).ok();
+        anyhow::Ok((path, modified))
+    };
+    Ok([modified(cert)?, modified(key)?])
+}
+
+fn load_certified_key(cert: &Path, key: &Path, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("failed to read TLS certificate {cert:?}"))?;
+    ensure!(!certs.is_empty(), "no certificate found in {cert:?}");
+    let private_key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("failed to read TLS key {key:?}"))?;
+    let private_key = provider
+        .key_provider
+        .load_private_key(private_key)
+        .with_context(|| format!("unsupported TLS key {key:?}"))?;
+
+    let certified_key = CertifiedKey::new(certs, private_key);
+    certified_key
+        .keys_match()
+        .with_context(|| format!("TLS key {key:?} doesn't match certificate {cert:?}"))?;
+    Ok(certified_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim_wasm::sandbox::context::Preopen;
+
+    use super::*;
+
+    const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+
+    /// A file in `dir`, as the root of the container filesystem.
+    fn file(dir: impl AsRef<Path>, name: &str) -> ContainerFile {
+        ContainerFile {
+            path: Path::new("/").join(name),
+            preopen: Preopen {
+                host_path: dir.as_ref().to_path_buf(),
+                guest_path: "/".into(),
+                readonly: true,
+            },
+        }
+    }
+
+    fn resolver(names: &[&str]) -> Result<CertResolver> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let slots = names
+            .iter()
+            .map(|name| {
+                let cert = file(TESTDATA, &format!("{name}.crt"));
+                let key = file(TESTDATA, &format!("{name}.key"));
+                CertSlot::load(cert, key, &provider)
+            })
+            .collect::<Result<_>>()?;
+        Ok(CertResolver { slots, provider })
+    }
+
+    fn is_example(key: Option<Arc<CertifiedKey>>) -> bool {
+        let name = ServerName::try_from("example.test").unwrap();
+        is_valid_for(&key.unwrap(), &name)
+    }
+
+    #[test]
+    fn test_select_by_server_name() -> Result<()> {
+        let resolver = resolver(&["localhost", "example.test"])?;
+        assert!(is_example(resolver.select(Some("example.test"))));
+        assert!(!is_example(resolver.select(Some("localhost"))));
+        // the first certificate is the default
+        assert!(!is_example(resolver.select(Some("other.test"))));
+        assert!(!is_example(resolver.select(None)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (cert, key) = (dir.path().join("tls.crt"), dir.path().join("tls.key"));
+        std::fs::copy(Path::new(TESTDATA).join("localhost.crt"), &cert)?;
+        std::fs::copy(Path::new(TESTDATA).join("localhost.key"), &key)?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let resolver = CertResolver {
+            slots: vec![CertSlot::load(
+                file(&dir, "tls.crt"),
+                file(&dir, "tls.key"),
+                &provider,
+            )?],
+            provider,
+        };
+        assert!(!is_example(resolver.select(None)));
+
+        // a half written update is ignored
+        std::fs::write(&cert, "")?;
+        resolver.reload();
+        assert!(!is_example(resolver.select(None)));
+
+        std::fs::copy(Path::new(TESTDATA).join("example.test.crt"), &cert)?;
+        std::fs::copy(Path::new(TESTDATA).join("example.test.key"), &key)?;
+        // make sure the modification time changes, regardless of its resolution
+        let later = SystemTime::now() + Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&cert)?
+            .set_modified(later)?;
+        resolver.reload();
+        assert!(is_example(resolver.select(None)));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reload_symlinks() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        // the layout of a Kubernetes secret volume, which is updated by replacing `..data`
+        let dir = tempfile::tempdir()?;
+        for (version, name) in [("..v1", "localhost"), ("..v2", "example.test")] {
+            std::fs::create_dir(dir.path().join(version))?;
+            for ext in ["crt", "key"] {
+                std::fs::copy(
+                    Path::new(TESTDATA).join(format!("{name}.{ext}")),
+                    dir.path().join(version).join(format!("tls.{ext}")),
+                )?;
+            }
+        }
+        symlink("..v1", dir.path().join("..data"))?;
+        symlink("..data/tls.crt", dir.path().join("tls.crt"))?;
+        symlink("..data/tls.key", dir.path().join("tls.key"))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let resolver = CertResolver {
+            slots: vec![CertSlot::load(
+                file(&dir, "tls.crt"),
+                file(&dir, "tls.key"),
+                &provider,
+            )?],
+            provider,
+        };
+        assert!(!is_example(resolver.select(None)));
+
+        symlink("..v2", dir.path().join("..data_tmp"))?;
+        std::fs::rename(dir.path().join("..data_tmp"), dir.path().join("..data"))?;
+        resolver.reload();
+        assert!(is_example(resolver.select(None)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatched_key() {
+        let provider = rustls::crypto::ring::default_provider();
+        let result = load_certified_key(
+            &Path::new(TESTDATA).join("localhost.crt"),
+            &Path::new(TESTDATA).join("example.test.key"),
+            &provider,
+        );
+        assert!(result.is_err());
+    }
+}
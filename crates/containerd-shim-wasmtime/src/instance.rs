@@ -1,24 +1,47 @@
+use std::borrow::Cow;
 use std::hash::Hash;
-use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::time::Duration;
 
-use anyhow::{Context, Result, bail};
-use containerd_shim_wasm::sandbox::Sandbox;
+use anyhow::{Context, Result, anyhow, bail};
 use containerd_shim_wasm::sandbox::context::{
-    Entrypoint, RuntimeContext, WasmBinaryType, WasmLayer,
+    Entrypoint, ResourceLimits, RuntimeContext, Source, WasmBinaryType, WasmLayer,
 };
+use containerd_shim_wasm::sandbox::network::{NetworkPolicy, SocketUse};
+use containerd_shim_wasm::sandbox::{Sandbox, SignalHandle, Termination};
 use containerd_shim_wasm::shim::{Compiler, Shim, Version, version};
+use tokio::sync::{oneshot, watch};
 use tokio_util::sync::CancellationToken;
+use tokio_util::task::AbortOnDropHandle;
 use wasi_preview1::WasiP1Ctx;
 use wasi_preview2::bindings::Command;
 use wasmtime::component::types::ComponentItem;
 use wasmtime::component::{self, Component, ResourceTable};
-use wasmtime::{Config, Module, Precompiled, Store};
+use wasmtime::{
+    AsContextMut, Config, Module, Precompiled, ResourceLimiter, Store, Trap, UpdateDeadline,
+    WasmBacktrace, WasmCoreDump,
+};
 use wasmtime_wasi::preview1::{self as wasi_preview1};
 use wasmtime_wasi::{self as wasi_preview2};
 use wasmtime_wasi_http::bindings::ProxyPre;
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    HostFutureIncomingResponse, OutgoingRequestConfig, default_send_request,
+};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 
+use crate::composition::compose;
+use crate::http_egress::EgressPolicy;
 use crate::http_proxy::serve_conn;
+use crate::snapshot::{FuncRefs, RESUME_EXPORT, Snapshot};
+
+/// How often the epoch is incremented while the guest runs.
+/// On every epoch the guest yields to the executor, and modules take any pending checkpoint.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Annotation setting the wall-clock deadline of a module or a command component, e.g., `"30s"`.
+const MAX_EXECUTION_TIME_ANNOTATION: &str = "wasmtime.runwasi.io/max-execution-time";
 
 /// Represents the WASI API that the component is targeting.
 enum ComponentTarget<'a> {
@@ -56,38 +79,129 @@ pub struct WasmtimeShim;
 pub struct WasmtimeCompiler(wasmtime::Engine);
 
 pub struct WasmtimeSandbox {
-    engine: wasmtime::Engine,
+    // Created when the guest runs, as its configuration depends on the runtime options
+    engine: OnceLock<wasmtime::Engine>,
     cancel: CancellationToken,
+    checkpoints: Arc<Checkpoints>,
+    /// Whether the first SIGTERM drains the guest, which is an HTTP server, rather than stopping it.
+    drain_on_sigterm: Arc<AtomicBool>,
+    stop: StopSignal,
 }
 
-impl Default for WasmtimeSandbox {
+/// Stops the guest of a container running inside the shim process, once it's killed.
+/// The stores of the guest trap on their next epoch, even if the guest is stuck in a loop.
+#[derive(Clone)]
+pub(crate) struct StopSignal(Arc<watch::Sender<Option<i32>>>);
+
+impl Default for StopSignal {
     fn default() -> Self {
-        let mut config = wasmtime::Config::new();
+        Self(Arc::new(watch::Sender::new(None)))
+    }
+}
+
+impl StopSignal {
+    fn stop(&self, signal: i32) {
+        // the guest is reported as killed by the first signal that stopped it
+        self.0.send_if_modified(|stop| {
+            let first = stop.is_none();
+            stop.get_or_insert(signal);
+            first
+        });
+    }
 
-        // Disable Wasmtime parallel compilation for the tests
-        // see https://github.com/containerd/runwasi/pull/405#issuecomment-1928468714 for details
-        config.parallel_compilation(!cfg!(test));
-        config.wasm_component_model(true); // enable component linking
-        config.async_support(true); // must be on
+    fn get(&self) -> Option<i32> {
+        *self.0.borrow()
+    }
 
-        if use_pooling_allocator_by_default() {
-            let cfg = wasmtime::PoolingAllocationConfig::default();
-            config.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(cfg));
-        }
+    /// Waits for the guest to be stopped, e.g., while it waits on the host.
+    async fn stopped<T>(&self) -> Result<T> {
+        let mut stop = self.0.subscribe();
+        let signal = stop
+            .wait_for(Option::is_some)
+            .await
+            .map(|signal| signal.unwrap_or_default());
+        let Ok(signal) = signal else {
+            // the sandbox holds the sender, the guest can't be stopped anymore once it's dropped
+            return std::future::pending().await;
+        };
+        Err(Termination::Signaled { signal }.into())
+    }
+}
+
+/// Checkpoint requests for the running module.
+#[derive(Default)]
+struct Checkpoints {
+    running: AtomicBool,
+    pending: Mutex<Vec<oneshot::Sender<Result<Vec<u8>>>>>,
+}
 
+/// The configuration shared by the sandbox engine and the precompilation engine,
+/// so that precompiled modules are compatible with the sandbox engine.
+fn engine_config() -> Config {
+    let mut config = Config::new();
+
+    // Disable Wasmtime parallel compilation for the tests
+    // see https://github.com/containerd/runwasi/pull/405#issuecomment-1928468714 for details
+    config.parallel_compilation(!cfg!(test));
+    config.wasm_component_model(true); // enable component linking
+    config.async_support(true); // must be on
+    config.epoch_interruption(true); // lets deadlines stop guests stuck in a loop, and checkpoints modules
+    config
+}
+
+/// Create the engine that runs the guest.
+/// Core dumps are only captured when they are written, as capturing them slows down traps.
+fn sandbox_engine(core_dump_on_trap: bool, resources: &ResourceLimits) -> wasmtime::Engine {
+    let mut config = engine_config();
+    config.coredump_on_trap(core_dump_on_trap);
+    // compiling on more threads than the CPU quota of the container only gets them throttled
+    if resources.cpus().is_some_and(|cpus| cpus <= 1.0) {
+        config.parallel_compilation(false);
+    }
+
+    if use_pooling_allocator_by_default() {
+        let cfg = wasmtime::PoolingAllocationConfig::default();
+        config.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(cfg));
+    }
+
+    wasmtime::Engine::new(&config)
+        .context("failed to create wasmtime engine")
+        .unwrap()
+}
+
+impl Default for WasmtimeSandbox {
+    fn default() -> Self {
         Self {
-            engine: wasmtime::Engine::new(&config)
-                .context("failed to create wasmtime engine")
-                .unwrap(),
+            engine: OnceLock::new(),
             cancel: CancellationToken::new(),
+            checkpoints: Default::default(),
+            drain_on_sigterm: Default::default(),
+            stop: Default::default(),
         }
     }
 }
 
+pub struct WasiPreview1Ctx {
+    pub(crate) wasi_ctx: WasiP1Ctx,
+    pub(crate) limiter: MemoryLimiter,
+}
+
+impl WasiPreview1Ctx {
+    pub fn new(ctx: &impl RuntimeContext) -> Result<Self> {
+        log::debug!("Creating new WasiPreview1Ctx");
+        Ok(Self {
+            wasi_ctx: wasi_builder(ctx)?.build_p1(),
+            limiter: MemoryLimiter::new(&ctx.resources()),
+        })
+    }
+}
+
 pub struct WasiPreview2Ctx {
     pub(crate) wasi_ctx: wasi_preview2::WasiCtx,
     pub(crate) wasi_http: WasiHttpCtx,
     pub(crate) resource_table: ResourceTable,
+    pub(crate) limiter: MemoryLimiter,
+    pub(crate) egress: Arc<EgressPolicy>,
 }
 
 impl WasiPreview2Ctx {
@@ -97,10 +211,93 @@ impl WasiPreview2Ctx {
             wasi_ctx: wasi_builder(ctx)?.build(),
             wasi_http: WasiHttpCtx::new(),
             resource_table: ResourceTable::default(),
+            limiter: MemoryLimiter::new(&ctx.resources()),
+            egress: Arc::new(EgressPolicy::new(ctx)?),
         })
     }
 }
 
+/// Enforces the container memory limit on the linear memories of a store.
+///
+/// Growing the memories of a store past the limit results in a trap, instead
+/// of the whole container being killed by the OOM killer.
+/// Limiters created with [`MemoryLimiter::share`] draw from the same budget, so that
+/// the stores of a container, e.g., one per HTTP request, are limited together.
+#[derive(Default)]
+pub(crate) struct MemoryLimiter {
+    limit: Option<usize>,
+    /// The memory used by all the stores sharing the limit.
+    used: Arc<AtomicUsize>,
+    /// The memory used by this store.
+    own: usize,
+}
+
+impl MemoryLimiter {
+    pub(crate) fn new(resources: &ResourceLimits) -> Self {
+        let limit = resources
+            .memory_limit
+            .map(|limit| usize::try_from(limit).unwrap_or(usize::MAX));
+        Self {
+            limit,
+            used: Default::default(),
+            own: 0,
+        }
+    }
+
+    /// Returns a limiter for another store, sharing the limit with this one.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            limit: self.limit,
+            used: self.used.clone(),
+            own: 0,
+        }
+    }
+}
+
+impl Drop for MemoryLimiter {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.own, Ordering::Relaxed);
+    }
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        let growth = desired.saturating_sub(current);
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let result = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(growth).filter(|used| *used <= limit)
+            });
+        match result {
+            Ok(_) => {
+                self.own += growth;
+                Ok(true)
+            }
+            Err(used) => {
+                bail!(
+                    "memory limit exceeded: growing linear memory to {desired} bytes would use {} bytes, but the container is limited to {limit} bytes",
+                    used.saturating_add(growth)
+                )
+            }
+        }
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
 /// This impl is required to use wasmtime_wasi::preview2::WasiView trait.
 impl wasi_preview2::WasiView for WasiPreview2Ctx {
     fn table(&mut self) -> &mut ResourceTable {
@@ -120,6 +317,19 @@ impl WasiHttpView for WasiPreview2Ctx {
     fn ctx(&mut self) -> &mut wasmtime_wasi_http::WasiHttpCtx {
         &mut self.wasi_http
     }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let (request, config) = self.egress.check(request, config)?;
+        Ok(default_send_request(request, config))
+    }
+
+    fn is_forbidden_header(&mut self, name: &hyper::header::HeaderName) -> bool {
+        self.egress.is_forbidden_header(name)
+    }
 }
 
 impl Shim for WasmtimeShim {
@@ -133,17 +343,17 @@ impl Shim for WasmtimeShim {
 
     type Sandbox = WasmtimeSandbox;
 
-    #[allow(refining_impl_trait)]
-    async fn compiler() -> Option<WasmtimeCompiler> {
-        let mut config = wasmtime::Config::new();
+    fn supports_checkpoint() -> bool {
+        cfg!(feature = "checkpoint")
+    }
 
-        // Disable Wasmtime parallel compilation for the tests
-        // see https://github.com/containerd/runwasi/pull/405#issuecomment-1928468714 for details
-        config.parallel_compilation(!cfg!(test));
-        config.wasm_component_model(true); // enable component linking
-        config.async_support(true); // must be on
+    fn supports_in_process() -> bool {
+        true
+    }
 
-        let engine = wasmtime::Engine::new(&config)
+    #[allow(refining_impl_trait)]
+    async fn compiler() -> Option<WasmtimeCompiler> {
+        let engine = wasmtime::Engine::new(&engine_config())
             .expect("failed to create wasmtime precompilation engine");
 
         Some(WasmtimeCompiler(engine))
@@ -161,9 +371,63 @@ impl Sandbox for WasmtimeSandbox {
             name: _,
         } = ctx.entrypoint();
 
-        let wasm_bytes = &source.as_bytes()?;
+        let wasm_bytes = match source {
+            Source::Oci(layers) if layers.len() > 1 => Cow::Owned(compose(layers)?),
+            source => source.as_bytes()?,
+        };
+
+        let core_dump_on_trap = ctx.core_dump_dir().is_some();
+        self.engine
+            .get_or_init(|| sandbox_engine(core_dump_on_trap, &ctx.resources()));
+
+        let _ticker = {
+            let engine = self.engine().clone();
+            AbortOnDropHandle::new(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(EPOCH_TICK);
+                loop {
+                    interval.tick().await;
+                    engine.increment_epoch();
+                }
+            }))
+        };
+
+        tokio::select! {
+            status = self.execute(ctx, &wasm_bytes, func) => status.into_error_code(),
+            status = self.stop.stopped() => status,
+        }
+    }
+
+    /// Take a snapshot of the running module.
+    /// The snapshot is taken by the module itself on the next epoch.
+    async fn checkpoint(&self) -> Result<Vec<u8>> {
+        if !self.checkpoints.running.load(Ordering::SeqCst) {
+            bail!(
+                "checkpoint is only supported for wasm modules exporting a {RESUME_EXPORT:?} function"
+            );
+        }
+        let (sender, receiver) = oneshot::channel();
+        self.checkpoints.pending.lock().unwrap().push(sender);
+        receiver
+            .await
+            .context("module exited before taking a checkpoint")?
+    }
 
-        self.execute(ctx, wasm_bytes, func).await.into_error_code()
+    fn signal_handle(&self) -> Option<SignalHandle> {
+        let cancel = self.cancel.clone();
+        let drain_on_sigterm = self.drain_on_sigterm.clone();
+        let stop = self.stop.clone();
+        Some(Arc::new(move |signal| {
+            // like `handle_signals`, the first SIGTERM or SIGINT drains an HTTP server,
+            // and any other signal stops the guest
+            let drain = matches!(signal, libc::SIGTERM | libc::SIGINT)
+                && drain_on_sigterm.load(Ordering::SeqCst)
+                && !cancel.is_cancelled();
+            if drain {
+                cancel.cancel();
+            } else {
+                stop.stop(signal);
+            }
+        }))
     }
 }
 
@@ -173,6 +437,13 @@ impl Compiler for WasmtimeCompiler {
     }
 
     async fn compile(&self, layers: &[WasmLayer]) -> Result<Vec<Option<Vec<u8>>>> {
+        // Components from multiple layers are composed before running them,
+        // which requires the original components rather than precompiled ones.
+        if layers.len() > 1 {
+            log::info!("Not precompiling an image with multiple layers");
+            return Ok(vec![None; layers.len()]);
+        }
+
         let mut compiled_layers = Vec::<Option<Vec<u8>>>::with_capacity(layers.len());
 
         for layer in layers {
@@ -199,6 +470,12 @@ impl Compiler for WasmtimeCompiler {
 }
 
 impl WasmtimeSandbox {
+    /// The engine that runs the guest, once [`Sandbox::run_wasi`] created it.
+    fn engine(&self) -> &wasmtime::Engine {
+        self.engine
+            .get_or_init(|| sandbox_engine(false, &ResourceLimits::default()))
+    }
+
     /// Execute a wasm module.
     ///
     /// This function adds wasi_preview1 to the linker and can be utilized
@@ -211,50 +488,92 @@ impl WasmtimeSandbox {
     ) -> Result<i32> {
         log::debug!("execute module");
 
-        let ctx_p1 = wasi_builder(ctx)?.build_p1();
-        let mut store = Store::new(&self.engine, ctx_p1);
-        let mut module_linker = wasmtime::Linker::new(&self.engine);
+        let ctx_p1 = WasiPreview1Ctx::new(ctx)?;
+        let mut store = Store::new(self.engine(), ctx_p1);
+        store.limiter(|ctx| &mut ctx.limiter);
+        let mut module_linker = wasmtime::Linker::new(self.engine());
 
         log::debug!("init linker");
-        wasi_preview1::add_to_linker_async(&mut module_linker, |wasi_ctx: &mut WasiP1Ctx| {
-            wasi_ctx
+        wasi_preview1::add_to_linker_async(&mut module_linker, |ctx: &mut WasiPreview1Ctx| {
+            &mut ctx.wasi_ctx
         })?;
 
         log::info!("instantiating instance");
-        let instance: wasmtime::Instance =
-            module_linker.instantiate_async(&mut store, &module).await?;
+        let instance: wasmtime::Instance = module_linker
+            .instantiate_async(&mut store, &module)
+            .await
+            .inspect_err(|err| write_core_dump(ctx, err, &mut store))?;
+
+        // Only modules exporting a resume function can be checkpointed, see the `snapshot` module
+        let resume_func = instance
+            .get_typed_func::<(), ()>(&mut store, RESUME_EXPORT)
+            .ok();
+        let funcs = FuncRefs::new(&instance, &mut store);
+
+        let start_func = match ctx.checkpoint() {
+            Some(checkpoint) => {
+                log::info!("restoring instance from checkpoint");
+                let resume_func = resume_func.as_ref().with_context(|| {
+                    format!("module does not export a {RESUME_EXPORT:?} function to resume from a checkpoint")
+                })?;
+                Snapshot::from_bytes(checkpoint)?.restore(&instance, &funcs, &mut store)?;
+                *resume_func.func()
+            }
+            None => {
+                log::debug!("getting start function");
+                instance
+                    .get_func(&mut store, func)
+                    .context("module does not have a WASI start function")?
+            }
+        };
 
-        log::debug!("getting start function");
-        let start_func = instance
-            .get_func(&mut store, func)
-            .context("module does not have a WASI start function")?;
+        let checkpoints = self.checkpoints.clone();
+        let stop = self.stop.clone();
+        store.epoch_deadline_callback(move |store| {
+            if let Some(signal) = stop.get() {
+                return Err(Termination::Signaled { signal }.into());
+            }
+            let pending = std::mem::take(&mut *checkpoints.pending.lock().unwrap());
+            if !pending.is_empty() {
+                log::info!("taking snapshot of instance");
+                let snapshot =
+                    Snapshot::capture(&instance, &funcs, store).and_then(|s| s.to_bytes());
+                for sender in pending {
+                    let snapshot = snapshot.as_ref().map_err(|err| anyhow!("{err:#}"));
+                    let _ = sender.send(snapshot.cloned());
+                }
+            }
+            Ok(UpdateDeadline::Yield(1))
+        });
+        store.set_epoch_deadline(1);
 
         log::info!("running start function {func:?}");
 
-        start_func
-            .call_async(&mut store, &[], &mut [])
-            .await
-            .into_error_code()
+        self.checkpoints
+            .running
+            .store(resume_func.is_some(), Ordering::SeqCst);
+        let call = start_func.call_async(&mut store, &[], &mut []);
+        let status = with_deadline(ctx, call).await;
+        let status = status.inspect_err(|err| write_core_dump(ctx, err, &mut store));
+        self.checkpoints.running.store(false, Ordering::SeqCst);
+        self.checkpoints.pending.lock().unwrap().clear();
+
+        status.into_error_code()
     }
 
     async fn execute_component_async(
         &self,
         ctx: &impl RuntimeContext,
         component: Component,
-        func: String,
+        target: ComponentTarget<'_>,
     ) -> Result<i32> {
         log::info!("instantiating component");
 
-        let target = ComponentTarget::new(
-            component.component_type().exports(&self.engine),
-            func.as_str(),
-        );
-
         // This is a adapter logic that converts wasip1 `_start` function to wasip2 `run` function.
         let status = match target {
             ComponentTarget::HttpProxy => {
                 log::info!("Found HTTP proxy target");
-                let mut linker = component::Linker::new(&self.engine);
+                let mut linker = component::Linker::new(self.engine());
                 wasmtime_wasi::add_to_linker_async(&mut linker)?;
                 wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
 
@@ -264,19 +583,19 @@ impl WasmtimeSandbox {
 
                 log::info!("starting HTTP server");
                 let cancel = self.cancel.clone();
-                serve_conn(ctx, instance, cancel).await
+                serve_conn(ctx, instance, cancel, self.stop.clone()).await
             }
             ComponentTarget::Command => {
                 log::info!("Found command target");
                 let wasi_ctx = WasiPreview2Ctx::new(ctx)?;
-                let (mut store, linker) = store_for_context(&self.engine, wasi_ctx)?;
+                let (mut store, linker) = store_for_context(self.engine(), wasi_ctx, &self.stop)?;
 
                 let command = Command::instantiate_async(&mut store, &component, &linker).await?;
 
-                command
-                    .wasi_cli_run()
-                    .call_run(&mut store)
-                    .await?
+                let run = command.wasi_cli_run().call_run(&mut store);
+                with_deadline(ctx, run)
+                    .await
+                    .inspect_err(|err| write_core_dump(ctx, err, &mut store))?
                     .map_err(|_| {
                         anyhow::anyhow!(
                             "failed to run component targeting `wasi:cli/command` world"
@@ -286,7 +605,7 @@ impl WasmtimeSandbox {
             ComponentTarget::Core(func) => {
                 log::info!("Found Core target");
                 let wasi_ctx = WasiPreview2Ctx::new(ctx)?;
-                let (mut store, linker) = store_for_context(&self.engine, wasi_ctx)?;
+                let (mut store, linker) = store_for_context(self.engine(), wasi_ctx, &self.stop)?;
 
                 let pre = linker.instantiate_pre(&component)?;
                 let instance = pre.instantiate_async(&mut store).await?;
@@ -297,7 +616,10 @@ impl WasmtimeSandbox {
                 ))?;
 
                 log::debug!("running exported function {func:?} {start_func:?}");
-                start_func.call_async(&mut store, &[], &mut []).await
+                let call = start_func.call_async(&mut store, &[], &mut []);
+                with_deadline(ctx, call)
+                    .await
+                    .inspect_err(|err| write_core_dump(ctx, err, &mut store))
             }
         };
 
@@ -315,30 +637,51 @@ impl WasmtimeSandbox {
         func: String,
     ) -> Result<i32> {
         log::debug!("loading wasm component");
+        if ctx.checkpoint().is_some() {
+            bail!("checkpoint is only supported for wasm modules");
+        }
+        let target = ComponentTarget::new(
+            component.component_type().exports(self.engine()),
+            func.as_str(),
+        );
+        // An HTTP server drains its in-flight requests when asked to stop, as by Kubernetes
+        let drain_on_sigterm = matches!(target, ComponentTarget::HttpProxy);
+
+        // Inside the shim process, the signals of the shim are not meant for the guest,
+        // which gets the signals of its container from the `signal_handle` instead
+        if ctx.stdio().is_some() {
+            self.drain_on_sigterm
+                .store(drain_on_sigterm, Ordering::SeqCst);
+            return self.execute_component_async(ctx, component, target).await;
+        }
+
         tokio::select! {
-            status = self.execute_component_async(ctx, component, func) => {
+            status = self.execute_component_async(ctx, component, target) => {
                 status
             }
-            status = self.handle_signals() => {
+            status = self.handle_signals(drain_on_sigterm) => {
                 status
             }
         }
     }
 
-    async fn handle_signals(&self) -> Result<i32> {
+    async fn handle_signals(&self, drain_on_sigterm: bool) -> Result<i32> {
         match wait_for_signal().await? {
             libc::SIGINT => {
                 // Request graceful shutdown;
                 self.cancel.cancel();
             }
+            libc::SIGTERM if drain_on_sigterm => {
+                self.cancel.cancel();
+            }
             sig => {
                 // On other signal, terminate the process without waiting for spawned tasks to finish.
                 return Ok(128 + sig);
             }
         }
 
-        // On a second SIGINT, terminate the process as well
-        wait_for_signal().await
+        // On a second signal, terminate the process as well
+        wait_for_signal().await.map(|sig| 128 + sig)
     }
 
     async fn execute(
@@ -350,22 +693,22 @@ impl WasmtimeSandbox {
         match WasmBinaryType::from_bytes(wasm_binary) {
             Some(WasmBinaryType::Module) => {
                 log::debug!("loading wasm module");
-                let module = Module::from_binary(&self.engine, wasm_binary)?;
+                let module = Module::from_binary(self.engine(), wasm_binary)?;
                 self.execute_module(ctx, module, &func).await
             }
             Some(WasmBinaryType::Component) => {
-                let component = Component::from_binary(&self.engine, wasm_binary)?;
+                let component = Component::from_binary(self.engine(), wasm_binary)?;
                 self.execute_component(ctx, component, func).await
             }
-            None => match &self.engine.detect_precompiled(wasm_binary) {
+            None => match self.engine().detect_precompiled(wasm_binary) {
                 Some(Precompiled::Module) => {
                     log::info!("using precompiled module");
-                    let module = unsafe { Module::deserialize(&self.engine, wasm_binary) }?;
+                    let module = unsafe { Module::deserialize(self.engine(), wasm_binary) }?;
                     self.execute_module(ctx, module, &func).await
                 }
                 Some(Precompiled::Component) => {
                     log::info!("using precompiled component");
-                    let component = unsafe { Component::deserialize(&self.engine, wasm_binary) }?;
+                    let component = unsafe { Component::deserialize(self.engine(), wasm_binary) }?;
                     self.execute_component(ctx, component, func).await
                 }
                 None => {
@@ -386,11 +729,14 @@ pub(crate) fn envs_from_ctx(ctx: &impl RuntimeContext) -> Vec<(String, String)>
         .collect()
 }
 
-fn store_for_context<T: wasi_preview2::WasiView>(
+fn store_for_context(
     engine: &wasmtime::Engine,
-    ctx: T,
-) -> Result<(Store<T>, component::Linker<T>)> {
-    let store = Store::new(engine, ctx);
+    ctx: WasiPreview2Ctx,
+    stop: &StopSignal,
+) -> Result<(Store<WasiPreview2Ctx>, component::Linker<WasiPreview2Ctx>)> {
+    let mut store = Store::new(engine, ctx);
+    store.limiter(|ctx| &mut ctx.limiter);
+    yield_on_epoch(&mut store, stop);
 
     log::debug!("init linker");
     let mut linker = component::Linker::new(engine);
@@ -399,26 +745,137 @@ fn store_for_context<T: wasi_preview2::WasiView>(
     Ok((store, linker))
 }
 
+/// Runs the guest until the deadline set with the `max-execution-time` annotation, if any.
+///
+/// Even a guest stuck in a loop yields to the executor on every epoch, which lets the deadline stop it.
+async fn with_deadline<T>(
+    ctx: &impl RuntimeContext,
+    run: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(deadline) = execution_deadline(ctx)? else {
+        return run.await;
+    };
+    match tokio::time::timeout(deadline, run).await {
+        Ok(result) => result,
+        Err(_) => {
+            log::info!("guest exceeded its execution deadline of {deadline:?}");
+            Err(Termination::DeadlineExceeded.into())
+        }
+    }
+}
+
+/// Returns the deadline set with the `max-execution-time` annotation.
+/// An invalid value fails rather than letting the guest run without a deadline.
+fn execution_deadline(ctx: &impl RuntimeContext) -> Result<Option<Duration>> {
+    let Some(value) = ctx.annotations().get(MAX_EXECUTION_TIME_ANNOTATION) else {
+        return Ok(None);
+    };
+    let deadline = humantime::parse_duration(value)
+        .with_context(|| format!("invalid value for {MAX_EXECUTION_TIME_ANNOTATION}: {value:?}"))?;
+    Ok(Some(deadline))
+}
+
+/// Write the core dump attached to the error of a guest that trapped,
+/// if the container has a core dump directory.
+fn write_core_dump(ctx: &impl RuntimeContext, err: &anyhow::Error, store: impl AsContextMut) {
+    let Some(dir) = ctx.core_dump_dir() else {
+        return;
+    };
+    // core dumps are also captured when the guest calls `proc_exit`
+    if err.downcast_ref::<Trap>().is_none() {
+        return;
+    }
+    let Some(core_dump) = err.downcast_ref::<WasmCoreDump>() else {
+        return;
+    };
+    let core_dump = core_dump.serialize(store, ctx.container_id());
+    match dir.write(&core_dump) {
+        Ok(name) => log::info!("wrote core dump {name}"),
+        Err(err) => log::warn!("failed to write core dump: {err}"),
+    }
+}
+
+/// Stores that don't take checkpoints simply yield to the executor on every epoch,
+/// so that timeouts can stop a guest stuck in a loop, and trap once the guest is stopped.
+pub(crate) fn yield_on_epoch<T>(store: &mut Store<T>, stop: &StopSignal) {
+    let stop = stop.clone();
+    store.epoch_deadline_callback(move |_| match stop.get() {
+        Some(signal) => Err(Termination::Signaled { signal }.into()),
+        None => Ok(UpdateDeadline::Yield(1)),
+    });
+    store.set_epoch_deadline(1);
+}
+
 fn wasi_builder(ctx: &impl RuntimeContext) -> Result<wasi_preview2::WasiCtxBuilder, anyhow::Error> {
-    // TODO: make this more configurable (e.g. allow the user to specify the
-    // preopened directories and their permissions)
-    // https://github.com/containerd/runwasi/issues/413
     log::debug!("building WASI context");
 
-    let file_perms = wasi_preview2::FilePerms::all();
-    let dir_perms = wasi_preview2::DirPerms::all();
     let envs = envs_from_ctx(ctx);
+    let network = NetworkPolicy::new(ctx)?;
 
     let mut builder = wasi_preview2::WasiCtxBuilder::new();
     builder
         .args(ctx.args())
         .envs(&envs)
-        .inherit_stdio()
-        .inherit_network()
         .allow_tcp(true)
         .allow_udp(true)
-        .allow_ip_name_lookup(true)
-        .preopened_dir("/", "/", dir_perms, file_perms)?;
+        .allow_ip_name_lookup(network.allows_ip_name_lookup())
+        .socket_addr_check(move |addr, addr_use| {
+            let usage = match addr_use {
+                wasi_preview2::SocketAddrUse::TcpBind | wasi_preview2::SocketAddrUse::UdpBind => {
+                    SocketUse::Bind
+                }
+                wasi_preview2::SocketAddrUse::TcpConnect
+                | wasi_preview2::SocketAddrUse::UdpConnect
+                | wasi_preview2::SocketAddrUse::UdpOutgoingDatagram => SocketUse::Connect,
+            };
+            let allowed = network.is_allowed(addr, usage);
+            if !allowed {
+                log::warn!("denied socket {usage} to {addr}");
+            }
+            Box::pin(std::future::ready(allowed))
+        });
+
+    match ctx.stdio() {
+        Some(stdio) => {
+            if let Some(stdin) = &stdio.stdin {
+                let stdin = tokio::fs::File::from_std(stdin.try_clone()?);
+                builder.stdin(wasi_preview2::AsyncStdinStream::new(
+                    wasi_preview2::pipe::AsyncReadStream::new(stdin),
+                ));
+            }
+            if let Some(stdout) = &stdio.stdout {
+                builder.stdout(wasi_preview2::OutputFile::new(stdout.try_clone()?));
+            }
+            if let Some(stderr) = &stdio.stderr {
+                builder.stderr(wasi_preview2::OutputFile::new(stderr.try_clone()?));
+            }
+        }
+        None => {
+            builder.inherit_stdio();
+        }
+    }
+
+    for preopen in ctx.preopens() {
+        let (dir_perms, file_perms) = if preopen.readonly {
+            (
+                wasi_preview2::DirPerms::READ,
+                wasi_preview2::FilePerms::READ,
+            )
+        } else {
+            (
+                wasi_preview2::DirPerms::all(),
+                wasi_preview2::FilePerms::all(),
+            )
+        };
+        builder
+            .preopened_dir(
+                &preopen.host_path,
+                preopen.guest_path.to_string_lossy(),
+                dir_perms,
+                file_perms,
+            )
+            .with_context(|| format!("failed to preopen {:?}", preopen.guest_path))?;
+    }
 
     log::debug!("WASI context built successfully");
     Ok(builder)
@@ -468,14 +925,35 @@ pub trait IntoErrorCode {
 }
 
 impl IntoErrorCode for Result<i32> {
+    /// Report a call to `proc_exit` or a trap as the [`Termination`] of the guest.
     fn into_error_code(self) -> Result<i32> {
-        self.or_else(|err| match err.downcast_ref::<wasmtime_wasi::I32Exit>() {
-            Some(exit) => Ok(exit.0),
-            _ => Err(err),
+        self.map_err(|err| match termination(&err) {
+            Some(termination) => termination.into(),
+            None => err,
         })
     }
 }
 
+fn termination(err: &anyhow::Error) -> Option<Termination> {
+    if let Some(exit) = err.downcast_ref::<wasmtime_wasi::I32Exit>() {
+        return Some(Termination::ProcExit { code: exit.0 });
+    }
+    let termination = match err.downcast_ref::<Trap>()? {
+        Trap::Interrupt => Termination::DeadlineExceeded,
+        trap => Termination::Trap {
+            // the trap message is already prefixed with "wasm trap: "
+            trap: trap
+                .to_string()
+                .trim_start_matches("wasm trap: ")
+                .to_string(),
+            backtrace: err
+                .downcast_ref::<WasmBacktrace>()
+                .map(|backtrace| backtrace.to_string()),
+        },
+    };
+    Some(termination)
+}
+
 impl IntoErrorCode for Result<()> {
     fn into_error_code(self) -> Result<i32> {
         self.map(|_| 0).into_error_code()
@@ -1,5 +1,8 @@
+mod composition;
+mod http_egress;
 mod http_proxy;
 pub mod instance;
+mod snapshot;
 
 pub use instance::WasmtimeShim;
 
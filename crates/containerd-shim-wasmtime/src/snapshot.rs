@@ -0,0 +1,352 @@
+//! Snapshots of the state of a module instance, used to checkpoint and restore containers.
+//!
+//! A snapshot contains the exported linear memories, the exported mutable globals, and
+//! the exported tables of an instance.
+//! Functions in the tables are saved by their export name, or by their index in the initial
+//! contents of an exported table, so that they can be resolved in the restored instance.
+//! Tables holding any other function or reference can't be snapshotted.
+//! Neither the call stack of the instance, its non-exported state (e.g., the `__stack_pointer`
+//! global of most toolchains), nor the host state of WASI (e.g., open files) are part of the snapshot.
+//! So only modules exporting a [`RESUME_EXPORT`] function can be checkpointed: by exporting it,
+//! the module declares that all its state is exported, and restoring the container calls it
+//! instead of the entrypoint, on top of the restored state.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use wasmtime::{AsContextMut, Extern, Func, Instance, Mutability, Ref, Val};
+
+/// Name of the function a module exports to resume its execution from a snapshot.
+pub(crate) const RESUME_EXPORT: &str = "runwasi_resume";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Snapshot {
+    memories: Vec<MemorySnapshot>,
+    globals: Vec<GlobalSnapshot>,
+    tables: Vec<TableSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MemorySnapshot {
+    name: String,
+    data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GlobalSnapshot {
+    name: String,
+    value: GlobalValue,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    V128(u128),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TableSnapshot {
+    name: String,
+    elements: Vec<Element>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+enum Element {
+    Null,
+    /// The function exported with this name.
+    Export(String),
+    /// The function initially at this index of an exported table.
+    Initial {
+        table: String,
+        index: u64,
+    },
+}
+
+/// The functions of an instance that tables can refer to in a snapshot.
+/// It must be built before the instance runs, or is restored, while its tables have their initial contents.
+pub(crate) struct FuncRefs {
+    funcs: Vec<(Element, Func)>,
+    elements: HashMap<usize, Element>,
+}
+
+impl FuncRefs {
+    pub(crate) fn new(instance: &Instance, mut store: impl AsContextMut) -> Self {
+        let mut store = store.as_context_mut();
+        let exports: Vec<_> = instance
+            .exports(&mut store)
+            .map(|export| (export.name().to_string(), export.into_extern()))
+            .collect();
+
+        let mut funcs = vec![];
+        for (name, export) in &exports {
+            if let Extern::Func(func) = export {
+                funcs.push((Element::Export(name.clone()), *func));
+            }
+        }
+        for (name, export) in &exports {
+            let Extern::Table(table) = export else {
+                continue;
+            };
+            for index in 0..table.size(&store) {
+                if let Some(Ref::Func(Some(func))) = table.get(&mut store, index) {
+                    let table = name.clone();
+                    funcs.push((Element::Initial { table, index }, func));
+                }
+            }
+        }
+
+        // Prefer the export names, as they come first
+        let mut elements = HashMap::new();
+        for (element, func) in &funcs {
+            let raw = unsafe { func.to_raw(&mut store) } as usize;
+            elements.entry(raw).or_insert_with(|| element.clone());
+        }
+
+        Self { funcs, elements }
+    }
+
+    fn element(&self, func: &Func, store: impl AsContextMut) -> Option<Element> {
+        let raw = unsafe { func.to_raw(store) } as usize;
+        self.elements.get(&raw).cloned()
+    }
+
+    fn func(&self, element: &Element) -> Option<Func> {
+        self.funcs
+            .iter()
+            .find(|(e, _)| e == element)
+            .map(|(_, func)| *func)
+    }
+}
+
+impl Snapshot {
+    /// Take a snapshot of the exports of `instance`.
+    pub(crate) fn capture(
+        instance: &Instance,
+        funcs: &FuncRefs,
+        mut store: impl AsContextMut,
+    ) -> Result<Self> {
+        let mut store = store.as_context_mut();
+        let exports: Vec<_> = instance
+            .exports(&mut store)
+            .map(|export| (export.name().to_string(), export.into_extern()))
+            .collect();
+
+        let mut snapshot = Self::default();
+        for (name, export) in exports {
+            match export {
+                Extern::Memory(memory) => {
+                    let data = memory.data(&store).to_vec();
+                    snapshot.memories.push(MemorySnapshot { name, data });
+                }
+                Extern::Global(global) if global.ty(&store).mutability() == Mutability::Var => {
+                    let value = match global.get(&mut store) {
+                        Val::I32(v) => GlobalValue::I32(v),
+                        Val::I64(v) => GlobalValue::I64(v),
+                        Val::F32(v) => GlobalValue::F32(v),
+                        Val::F64(v) => GlobalValue::F64(v),
+                        Val::V128(v) => GlobalValue::V128(v.as_u128()),
+                        _ => {
+                            log::warn!("skipping global {name:?} with a reference type");
+                            continue;
+                        }
+                    };
+                    snapshot.globals.push(GlobalSnapshot { name, value });
+                }
+                Extern::Table(table) => {
+                    let mut elements = vec![];
+                    for index in 0..table.size(&store) {
+                        let element = match table.get(&mut store, index) {
+                            Some(Ref::Func(Some(func))) => funcs.element(&func, &mut store),
+                            Some(value) if value.is_null() => Some(Element::Null),
+                            _ => None,
+                        };
+                        let Some(element) = element else {
+                            bail!(
+                                "can't snapshot element {index} of table {name:?}, as it's not an exported or initial function"
+                            );
+                        };
+                        elements.push(element);
+                    }
+                    snapshot.tables.push(TableSnapshot { name, elements });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Restore the snapshot into the exports of `instance`.
+    pub(crate) fn restore(
+        &self,
+        instance: &Instance,
+        funcs: &FuncRefs,
+        mut store: impl AsContextMut,
+    ) -> Result<()> {
+        let mut store = store.as_context_mut();
+
+        for MemorySnapshot { name, data } in &self.memories {
+            let memory = instance
+                .get_memory(&mut store, name)
+                .with_context(|| format!("module does not export memory {name:?}"))?;
+            let current = memory.data_size(&store);
+            if data.len() > current {
+                let page_size = memory.page_size(&store) as usize;
+                let delta = (data.len() - current).div_ceil(page_size);
+                memory
+                    .grow(&mut store, delta as u64)
+                    .with_context(|| format!("failed to grow memory {name:?}"))?;
+            }
+            memory.data_mut(&mut store)[..data.len()].copy_from_slice(data);
+        }
+
+        for GlobalSnapshot { name, value } in &self.globals {
+            let global = instance
+                .get_global(&mut store, name)
+                .with_context(|| format!("module does not export global {name:?}"))?;
+            let value = match value {
+                GlobalValue::I32(v) => Val::I32(*v),
+                GlobalValue::I64(v) => Val::I64(*v),
+                GlobalValue::F32(v) => Val::F32(*v),
+                GlobalValue::F64(v) => Val::F64(*v),
+                GlobalValue::V128(v) => Val::V128((*v).into()),
+            };
+            global
+                .set(&mut store, value)
+                .with_context(|| format!("failed to restore global {name:?}"))?;
+        }
+
+        for TableSnapshot { name, elements } in &self.tables {
+            let table = instance
+                .get_table(&mut store, name)
+                .with_context(|| format!("module does not export table {name:?}"))?;
+            let null = Ref::null(table.ty(&store).element().heap_type());
+            let size = elements.len() as u64;
+            let current = table.size(&store);
+            if size > current {
+                table
+                    .grow(&mut store, size - current, null.clone())
+                    .with_context(|| format!("failed to grow table {name:?}"))?;
+            }
+            for (index, element) in (0..).zip(elements) {
+                let value = match element {
+                    Element::Null => null.clone(),
+                    element => {
+                        let func = funcs.func(element).with_context(|| {
+                            format!(
+                                "module does not have the function {element:?} of table {name:?}"
+                            )
+                        })?;
+                        Ref::Func(Some(func))
+                    }
+                };
+                table
+                    .set(&mut store, index, value)
+                    .with_context(|| format!("failed to restore table {name:?}"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).context("invalid snapshot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Engine, Linker, Module, Store};
+
+    use super::*;
+
+    const MODULE: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $counter (export "counter") (mut i32) (i32.const 0))
+        (table $table (export "table") 2 funcref)
+        (elem (table $table) (i32.const 0) func $one)
+        (elem declare func $two $three)
+        (type $get (func (result i32)))
+        (func $one (result i32) (i32.const 1))
+        (func $two (export "two") (result i32) (i32.const 2))
+        (func $three (result i32) (i32.const 3))
+        (func (export "bump")
+            (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+            (i32.store (i32.const 0) (global.get $counter))
+            (drop (memory.grow (i32.const 1)))
+            (drop (table.grow (ref.null func) (i32.const 1)))
+            (table.set $table (i32.const 1) (ref.func $two))
+            (table.set $table (i32.const 2) (table.get $table (i32.const 0))))
+        (func (export "mutate") (table.set $table (i32.const 0) (ref.func $three)))
+        (func (export "counter_in_memory") (result i32) (i32.load (i32.const 0)))
+        (func (export "call") (param i32) (result i32) (call_indirect (type $get) (local.get 0)))
+    )"#;
+
+    fn instantiate(engine: &Engine, module: &Module) -> Result<(Store<()>, Instance, FuncRefs)> {
+        let mut store = Store::new(engine, ());
+        let instance = Linker::new(engine).instantiate(&mut store, module)?;
+        let funcs = FuncRefs::new(&instance, &mut store);
+        Ok((store, instance, funcs))
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() -> Result<()> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, MODULE)?;
+
+        let (mut store, instance, funcs) = instantiate(&engine, &module)?;
+        let bump = instance.get_typed_func::<(), ()>(&mut store, "bump")?;
+        bump.call(&mut store, ())?;
+        bump.call(&mut store, ())?;
+
+        let bytes = Snapshot::capture(&instance, &funcs, &mut store)?.to_bytes()?;
+
+        let (mut store, instance, funcs) = instantiate(&engine, &module)?;
+        Snapshot::from_bytes(&bytes)?.restore(&instance, &funcs, &mut store)?;
+
+        let counter = instance.get_global(&mut store, "counter").unwrap();
+        assert_eq!(counter.get(&mut store).i32(), Some(2));
+        let counter_in_memory =
+            instance.get_typed_func::<(), i32>(&mut store, "counter_in_memory")?;
+        assert_eq!(counter_in_memory.call(&mut store, ())?, 2);
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert_eq!(memory.size(&store), 3);
+        let table = instance.get_table(&mut store, "table").unwrap();
+        assert_eq!(table.size(&store), 4);
+
+        let call = instance.get_typed_func::<i32, i32>(&mut store, "call")?;
+        assert_eq!(call.call(&mut store, 0)?, 1);
+        assert_eq!(call.call(&mut store, 1)?, 2);
+        assert_eq!(call.call(&mut store, 2)?, 1);
+        assert!(call.call(&mut store, 3).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_unknown_table_element() -> Result<()> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, MODULE)?;
+
+        let (mut store, instance, funcs) = instantiate(&engine, &module)?;
+        let mutate = instance.get_typed_func::<(), ()>(&mut store, "mutate")?;
+        mutate.call(&mut store, ())?;
+
+        assert!(Snapshot::capture(&instance, &funcs, &mut store).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_invalid() {
+        assert!(Snapshot::from_bytes(b"not a snapshot").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use containerd_shim_wasm::sandbox::Termination;
 use containerd_shim_wasm::testing::modules::*;
 use containerd_shim_wasm::testing::{WasiTest, oci_helpers};
 use serial_test::serial;
@@ -140,13 +141,201 @@ fn test_custom_entrypoint() -> anyhow::Result<()> {
 #[test]
 #[serial]
 fn test_unreachable() -> anyhow::Result<()> {
-    let (exit_code, _, _) = WasiTest::<WasiEngine>::builder()?
+    let test = WasiTest::<WasiEngine>::builder()?
         .with_wasm(UNREACHABLE)?
-        .build()?
-        .start()?
-        .wait(Duration::from_secs(10))?;
+        .build()?;
+    let (exit_code, _, stderr) = test.start()?.wait(Duration::from_secs(10))?;
+
+    // traps are reported like an abort, and not like a SIGKILL
+    assert_eq!(exit_code, 134);
+    assert!(stderr.contains("wasm trap"), "{stderr}");
+    assert!(matches!(
+        test.termination(),
+        Some(Termination::Trap {
+            backtrace: Some(_),
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_unreachable_in_process() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(UNREACHABLE)?
+        .with_in_process()
+        .build()?;
+    let (exit_code, _, stderr) = test.start()?.wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 134);
+    assert!(stderr.contains("wasm trap"), "{stderr}");
+    assert!(matches!(test.termination(), Some(Termination::Trap { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_unreachable_core_dump() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(UNREACHABLE)?
+        .with_core_dump_on_trap()
+        .build()?;
+    let (exit_code, _, _) = test.start()?.wait(Duration::from_secs(10))?;
+    assert_eq!(exit_code, 134);
+
+    let core_dumps: Vec<_> = std::fs::read_dir(test.root())?
+        .map(|entry| entry.map(|e| e.path()))
+        .filter(|path| {
+            path.as_ref()
+                .is_ok_and(|p| p.extension() == Some("coredump".as_ref()))
+        })
+        .collect::<Result<_, _>>()?;
+    assert_eq!(core_dumps.len(), 1);
+
+    // core dumps are wasm modules with a `core` custom section
+    let core_dump = std::fs::read(&core_dumps[0])?;
+    assert!(core_dump.starts_with(b"\0asm"));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_execution_deadline() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(SLEEP_LOOP)?
+        .with_annotation("wasmtime.runwasi.io/max-execution-time", "200ms")
+        .build()?;
+    let (exit_code, _, _) = test.start()?.wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 124);
+    assert_eq!(test.termination(), Some(Termination::DeadlineExceeded));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_execution_deadline_invalid() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(SLEEP_LOOP)?
+        .with_annotation("wasmtime.runwasi.io/max-execution-time", "forever")
+        .build()?;
+    let (exit_code, _, stderr) = test.start()?.wait(Duration::from_secs(10))?;
+
+    // the guest doesn't run without a deadline
+    assert_eq!(exit_code, 137);
+    assert!(
+        stderr.contains("invalid value for wasmtime.runwasi.io/max-execution-time"),
+        "{stderr}"
+    );
+    assert!(matches!(
+        test.termination(),
+        Some(Termination::EngineError { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_execution_deadline_in_process() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(SLEEP_LOOP)?
+        .with_annotation("wasmtime.runwasi.io/max-execution-time", "200ms")
+        .with_in_process()
+        .build()?;
+    let (exit_code, _, _) = test.start()?.wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 124);
+    assert_eq!(test.termination(), Some(Termination::DeadlineExceeded));
+
+    Ok(())
+}
+
+// A guest stuck in a loop only yields to the executor with epoch interruption
+#[test]
+#[serial]
+fn test_execution_deadline_infinite_loop() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(INFINITE_LOOP)?
+        .with_annotation("wasmtime.runwasi.io/max-execution-time", "200ms")
+        .build()?;
+    let (exit_code, _, _) = test.start()?.wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 124);
+    assert_eq!(test.termination(), Some(Termination::DeadlineExceeded));
+
+    Ok(())
+}
+
+// Killing a guest running in the shim process interrupts it, even if it never yields
+#[test]
+#[serial]
+fn test_infinite_loop_in_process_kill() -> anyhow::Result<()> {
+    let test = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(INFINITE_LOOP)?
+        .with_in_process()
+        .build()?;
+    test.start()?;
+    std::thread::sleep(Duration::from_millis(200));
+
+    let (exit_code, _, _) = test.kill()?.wait(Duration::from_secs(5))?;
+
+    assert_eq!(exit_code, 128 + libc::SIGKILL as u32);
+    assert_eq!(
+        test.termination(),
+        Some(Termination::Signaled {
+            signal: libc::SIGKILL
+        })
+    );
+
+    Ok(())
+}
+
+// Test that a module checkpointed while it runs resumes from the checkpoint in a new container
+#[test]
+#[serial]
+#[cfg(feature = "checkpoint")]
+fn test_checkpoint_restore() -> anyhow::Result<()> {
+    test_checkpoint_restore_impl(false)
+}
+
+#[test]
+#[serial]
+#[cfg(feature = "checkpoint")]
+fn test_checkpoint_restore_in_process() -> anyhow::Result<()> {
+    test_checkpoint_restore_impl(true)
+}
+
+#[cfg(feature = "checkpoint")]
+fn test_checkpoint_restore_impl(in_process: bool) -> anyhow::Result<()> {
+    let checkpoint = tempfile::tempdir()?;
 
-    assert_ne!(exit_code, 0);
+    let mut builder = WasiTest::<WasiEngine>::builder()?.with_wasm(CHECKPOINT)?;
+    if in_process {
+        builder = builder.with_in_process();
+    }
+    let test = builder.build()?;
+    test.start()?;
+    std::thread::sleep(Duration::from_millis(200));
+    test.checkpoint(checkpoint.path())?;
+    test.kill()?.wait(Duration::from_secs(5))?;
+    test.delete()?;
+
+    let mut builder = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(CHECKPOINT)?
+        .with_checkpoint(checkpoint.path());
+    if in_process {
+        builder = builder.with_in_process();
+    }
+    let (exit_code, stdout, _) = builder.build()?.start()?.wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "restored\n");
 
     Ok(())
 }
@@ -154,13 +343,13 @@ fn test_unreachable() -> anyhow::Result<()> {
 #[test]
 #[serial]
 fn test_exit_code() -> anyhow::Result<()> {
-    let (exit_code, _, _) = WasiTest::<WasiEngine>::builder()?
+    let test = WasiTest::<WasiEngine>::builder()?
         .with_wasm(EXIT_CODE)?
-        .build()?
-        .start()?
-        .wait(Duration::from_secs(10))?;
+        .build()?;
+    let (exit_code, _, _) = test.start()?.wait(Duration::from_secs(10))?;
 
     assert_eq!(exit_code, 42);
+    assert_eq!(test.termination(), Some(Termination::ProcExit { code: 42 }));
 
     Ok(())
 }
@@ -237,6 +426,27 @@ fn test_wasip2_component() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_wasip2_component_network_deny() -> anyhow::Result<()> {
+    let _allowed = std::net::TcpListener::bind("127.0.0.1:8081")?;
+    let _denied = std::net::TcpListener::bind("127.0.0.1:8082")?;
+
+    let (exit_code, stdout, _) = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(TCP_CONNECT)?
+        .with_host_network()
+        .with_annotation("runwasi.io/network-default", "deny")
+        .with_annotation("runwasi.io/network-allowed-connect", "127.0.0.1:8081")
+        .build()?
+        .start()?
+        .wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "8081 connected\n8082 denied\n");
+
+    Ok(())
+}
+
 // Test that the shim can execute a wasm component that is
 // compiled with wasi:http/proxy.
 //
@@ -296,39 +506,724 @@ fn test_wasip2_component_http_proxy_csharp() -> anyhow::Result<()> {
     Ok(())
 }
 
-// Test that the shim can terminate component targeting wasi:http/proxy by sending SIGTERM.
+// Test that the shim stops a component targeting wasi:http/proxy gracefully on SIGTERM,
+// closing the idle connections instead of waiting for the clients to close them.
 #[test]
 #[serial]
-fn test_wasip2_component_http_proxy_force_shutdown() -> anyhow::Result<()> {
+fn test_wasip2_component_http_proxy_graceful_shutdown() -> anyhow::Result<()> {
     let srv = WasiTest::<WasiEngine>::builder()?
         .with_wasm(HELLO_WASI_HTTP)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-shutdown-grace-period",
+            "30s",
+        )
         .with_host_network()
         .build()?;
 
     let srv = srv.start()?;
-    assert!(http_get().unwrap().status().is_success());
+    // keep the connection alive while the server shuts down
+    let response = http_get()?;
+    assert!(response.status().is_success());
 
     // Send SIGTERM
     let (exit_code, _, _) = srv.terminate()?.wait(Duration::from_secs(5))?;
-    // The exit code indicates that the process did not exit cleanly
-    assert_eq!(exit_code, 128 + libc::SIGTERM as u32);
+    assert_eq!(exit_code, 0);
+    drop(response);
+
+    Ok(())
+}
+
+// Test that on SIGTERM the proxy finishes the requests in flight within the grace period,
+// while refusing new connections.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_drain() -> anyhow::Result<()> {
+    use std::net::TcpStream;
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-shutdown-grace-period",
+            "30s",
+        )
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let slow = std::thread::spawn(|| reqwest::blocking::get("http://127.0.0.1:8080/slow"));
+    std::thread::sleep(Duration::from_millis(300));
+
+    // Send SIGTERM while the slow request is in flight
+    srv.terminate()?;
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!slow.is_finished());
+    let err = TcpStream::connect("127.0.0.1:8080").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+
+    let response = slow.join().unwrap()?;
+    assert!(response.status().is_success());
+    assert_eq!(response.text()?, "slow\n");
+
+    let (exit_code, _, _) = srv.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy writes an access log line per request to the stdout of the container.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_access_log() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-access-log", "json")
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    let response = http_get()?;
+    assert!(response.status().is_success());
+    let body = response.text()?;
+
+    let (exit_code, stdout, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    let log: serde_json::Value = stdout
+        .lines()
+        .find_map(|line| serde_json::from_str(line).ok())
+        .expect("no access log line in stdout");
+    assert_eq!(log["method"], "GET");
+    assert_eq!(log["uri"], "/");
+    assert_eq!(log["status"], 200);
+    assert_eq!(log["response_bytes"], body.len());
+
+    Ok(())
+}
+
+// Test that the proxy handles requests with instances reused from a pool,
+// until an instance reached its maximum number of requests or trapped.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_instance_pool() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-instance-pool-size", "1")
+        .with_annotation("wasmtime.runwasi.io/http-proxy-instance-max-reuse", "3")
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+
+    // The component counts the requests its instance handled
+    let count = || -> anyhow::Result<String> {
+        let response = http_get()?;
+        assert!(response.status().is_success());
+        let count = response.text()?;
+        // the instance goes back to the pool once the handler returned, after the response
+        std::thread::sleep(Duration::from_millis(100));
+        Ok(count)
+    };
+    for expected in ["1\n", "2\n", "3\n", "1\n", "2\n"] {
+        assert_eq!(count()?, expected);
+    }
+
+    // The instance traps before sending a response, and is discarded
+    assert!(reqwest::blocking::get("http://127.0.0.1:8080/trap").is_err());
+    assert_eq!(count()?, "1\n");
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy serves HTTP/2 clients with prior knowledge.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_http2() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let response = reqwest::blocking::Client::builder()
+        .http2_prior_knowledge()
+        .build()?
+        .get("http://127.0.0.1:8080")
+        .send()?;
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.text()?,
+        "Hello, this is your first wasi:http/proxy world!\n"
+    );
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy upgrades HTTP/1.1 connections asking for h2c, answering the request on the stream 1.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_h2c_upgrade() -> anyhow::Result<()> {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let mut stream = TcpStream::connect("127.0.0.1:8080")?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(
+        b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+          Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAoAAAAAIAAAAA\r\n\r\n",
+    )?;
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head)?;
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+
+    // the connection preface, with an empty SETTINGS frame
+    stream.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")?;
+
+    let mut status = None;
+    let mut body = Vec::new();
+    loop {
+        let (kind, flags, id, payload) = read_h2_frame(&mut stream)?;
+        match (kind, id) {
+            // HEADERS, starting with `:status: 200` from the static table
+            (0x1, 1) => status = payload.first().copied(),
+            // DATA
+            (0x0, 1) => {
+                body.extend_from_slice(&payload);
+                if flags & 0x1 != 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(status, Some(0x88));
+    assert_eq!(
+        String::from_utf8(body)?,
+        "Hello, this is your first wasi:http/proxy world!\n"
+    );
+    drop(stream);
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy terminates TLS, selecting the certificate from the requested server name.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_tls() -> anyhow::Result<()> {
+    const LOCALHOST_CERT: &str = include_str!("../testdata/localhost.crt");
+    const EXAMPLE_CERT: &str = include_str!("../testdata/example.test.crt");
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_file("/tls/localhost.crt", LOCALHOST_CERT)?
+        .with_file(
+            "/tls/localhost.key",
+            include_str!("../testdata/localhost.key"),
+        )?
+        .with_file("/tls/example.test.crt", EXAMPLE_CERT)?
+        .with_file(
+            "/tls/example.test.key",
+            include_str!("../testdata/example.test.key"),
+        )?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-tls-cert",
+            "/tls/localhost.crt,/tls/example.test.crt",
+        )
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-tls-key",
+            "/tls/localhost.key,/tls/example.test.key",
+        )
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+
+    // each client only trusts the certificate of the server name it asks for
+    for (host, cert) in [
+        ("localhost", LOCALHOST_CERT),
+        ("example.test", EXAMPLE_CERT),
+    ] {
+        let client = reqwest::blocking::Client::builder()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?)
+            .resolve(host, "127.0.0.1:8080".parse()?)
+            .build()?;
+        let response = retry_get(|| client.get(format!("https://{host}:8080")).send())?;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.text()?,
+            "Hello, this is your first wasi:http/proxy world!\n"
+        );
+    }
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy serves a renewed certificate once its files are replaced, like in a Kubernetes
+// secret volume, resolving their symlinks in the container filesystem.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_tls_reload() -> anyhow::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    const LOCALHOST_CERT: &str = include_str!("../testdata/localhost.crt");
+    const EXAMPLE_CERT: &str = include_str!("../testdata/example.test.crt");
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_file("/tls/..v1/tls.crt", LOCALHOST_CERT)?
+        .with_file(
+            "/tls/..v1/tls.key",
+            include_str!("../testdata/localhost.key"),
+        )?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-tls-cert", "/tls/tls.crt")
+        .with_annotation("wasmtime.runwasi.io/http-proxy-tls-key", "/tls/tls.key")
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-tls-reload-interval",
+            "100ms",
+        )
+        // the files are resolved in the rootfs of in-process containers, not in the one of the host
+        .with_in_process()
+        .build()?;
+
+    let tls = srv.root().join("rootfs").join("tls");
+    symlink("..v1", tls.join("..data"))?;
+    symlink("/tls/..data/tls.crt", tls.join("tls.crt"))?;
+    symlink("/tls/..data/tls.key", tls.join("tls.key"))?;
+
+    let srv = srv.start()?;
+
+    let client = |host: &str, cert: &str| {
+        reqwest::blocking::Client::builder()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes())?)
+            .resolve(host, "127.0.0.1:8080".parse()?)
+            .build()
+            .map_err(anyhow::Error::from)
+    };
+    let localhost = client("localhost", LOCALHOST_CERT)?;
+    let example = client("example.test", EXAMPLE_CERT)?;
+
+    let response = retry_get(|| localhost.get("https://localhost:8080").send())?;
+    assert!(response.status().is_success());
+    assert!(example.get("https://example.test:8080").send().is_err());
+
+    // rotate the certificate by replacing the symlink to its directory
+    std::fs::create_dir(tls.join("..v2"))?;
+    std::fs::write(tls.join("..v2/tls.crt"), EXAMPLE_CERT)?;
+    std::fs::write(
+        tls.join("..v2/tls.key"),
+        include_str!("../testdata/example.test.key"),
+    )?;
+    symlink("..v2", tls.join("..data_tmp"))?;
+    std::fs::rename(tls.join("..data_tmp"), tls.join("..data"))?;
+    std::fs::remove_dir_all(tls.join("..v1"))?;
+
+    let response = retry_get(|| example.get("https://example.test:8080").send())?;
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.text()?,
+        "Hello, this is your first wasi:http/proxy world!\n"
+    );
+    // a new connection, since the ones opened before keep their certificate
+    let localhost = client("localhost", LOCALHOST_CERT)?;
+    assert!(localhost.get("https://localhost:8080").send().is_err());
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that the proxy can listen on a Unix socket, which is removed on shutdown.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_unix_socket() -> anyhow::Result<()> {
+    use std::io::{Read, Write};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-socket-addr",
+            "unix:/proxy.sock",
+        )
+        .with_annotation("wasmtime.runwasi.io/http-proxy-socket-mode", "600")
+        // the rootfs of the test containers is read-only, but not the one of in-process containers
+        .with_in_process()
+        .build()?;
+
+    let srv = srv.start()?;
+    let socket = srv.root().join("rootfs").join("proxy.sock");
+
+    let mut stream = retry(|| UnixStream::connect(&socket))?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(response.contains("Hello, this is your first wasi:http/proxy world!\n"));
+
+    let metadata = std::fs::symlink_metadata(&socket)?;
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+    assert!(!socket.exists());
 
     Ok(())
 }
 
+// Test that the path of the Unix socket is resolved in the rootfs of in-process containers,
+// so that a symlink in the image can't make the shim create or remove files of the host.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_unix_socket_symlink() -> anyhow::Result<()> {
+    use std::io::{Read, Write};
+    use std::os::unix::fs::{FileTypeExt, symlink};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-socket-addr",
+            "unix:/proxy.sock",
+        )
+        .with_in_process()
+        .build()?;
+
+    // a socket of the host, next to the rootfs, where the symlink points when followed on the host
+    let host = srv.root().join("host");
+    std::fs::create_dir(&host)?;
+    let host_socket = UnixListener::bind(host.join("proxy.sock"))?;
+
+    let rootfs = srv.root().join("rootfs");
+    std::fs::create_dir(rootfs.join("host"))?;
+    symlink("../host/proxy.sock", rootfs.join("proxy.sock"))?;
+
+    let srv = srv.start()?;
+    let socket = rootfs.join("host").join("proxy.sock");
+
+    let mut stream = retry(|| UnixStream::connect(&socket))?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+    assert!(!socket.exists());
+
+    // the socket of the host is left alone
+    let metadata = std::fs::symlink_metadata(host.join("proxy.sock"))?;
+    assert!(metadata.file_type().is_socket());
+    drop(host_socket);
+
+    Ok(())
+}
+
+// Test that requests with a body larger than the maximum body size are rejected.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_max_body_size() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WASI_HTTP)?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-max-body-size", "16")
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let response = reqwest::blocking::Client::new()
+        .post("http://127.0.0.1:8080")
+        .body(vec![b'a'; 64])
+        .send()?;
+    assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    // close the connection, so that the server can shut down
+    drop(response);
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that requests beyond the maximum number of concurrent requests are rejected with a 503.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_max_concurrent_requests() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-max-concurrent-requests",
+            "1",
+        )
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let slow = std::thread::spawn(|| reqwest::blocking::get("http://127.0.0.1:8080/slow"));
+    std::thread::sleep(Duration::from_millis(300));
+
+    let response = reqwest::blocking::get("http://127.0.0.1:8080")?;
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = slow.join().unwrap()?;
+    assert!(response.status().is_success());
+    assert_eq!(response.text()?, "slow\n");
+
+    // the slot is released once the slow request is done
+    assert!(http_get()?.status().is_success());
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that invalid limits fail the proxy, rather than turning the limits off.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_invalid_limits() -> anyhow::Result<()> {
+    for (name, value) in [
+        ("http-proxy-max-concurrent-requests", "0"),
+        ("http-proxy-max-concurrent-requests", "many"),
+        ("http-proxy-request-timeout", "5"),
+        ("http-proxy-idle-timeout", "forever"),
+        ("http-proxy-max-body-size", "1MB"),
+    ] {
+        let srv = WasiTest::<WasiEngine>::builder()?
+            .with_wasm(HTTP_COUNTER)?
+            .with_annotation(format!("wasmtime.runwasi.io/{name}"), value)
+            .with_host_network()
+            .build()?;
+
+        let (exit_code, _, stderr) = srv.start()?.wait(Duration::from_secs(10))?;
+
+        assert_ne!(exit_code, 0, "{name}={value}");
+        assert!(stderr.contains(name), "{name}={value}: {stderr}");
+    }
+
+    Ok(())
+}
+
+// Test that requests whose handler doesn't respond before the request timeout get a 504.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_request_timeout() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-request-timeout", "200ms")
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let response = reqwest::blocking::get("http://127.0.0.1:8080/slow")?;
+    assert_eq!(response.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+
+    assert!(http_get()?.status().is_success());
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that connections that don't send the headers of a request before the idle timeout are closed.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_idle_timeout() -> anyhow::Result<()> {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Instant;
+
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation("wasmtime.runwasi.io/http-proxy-idle-timeout", "500ms")
+        .with_host_network()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let mut stream = TcpStream::connect("127.0.0.1:8080")?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n")?;
+
+    let start = Instant::now();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(
+        !response.starts_with(b"HTTP/1.1 200"),
+        "{}",
+        String::from_utf8_lossy(&response)
+    );
+
+    // HTTP/2 connections without any request are closed with a GOAWAY frame
+    let mut stream = TcpStream::connect("127.0.0.1:8080")?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")?;
+
+    let start = Instant::now();
+    let mut frames = Vec::new();
+    while let Ok((kind, flags, _, payload)) = read_h2_frame(&mut stream) {
+        // the server waits for the acknowledgement of a PING before it closes the connection
+        if kind == 0x6 && flags & 0x1 == 0 {
+            stream.write_all(&[&[0, 0, 8, 0x6, 0x1, 0, 0, 0, 0], payload.as_slice()].concat())?;
+        }
+        frames.push(kind);
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(frames.contains(&0x7), "{frames:?}");
+
+    let (exit_code, _, _) = srv.ctrl_c()?.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_hello_world_in_process() -> anyhow::Result<()> {
+    let (exit_code, stdout, _) = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HELLO_WORLD)?
+        .with_in_process()
+        .build()?
+        .start()?
+        .wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "hello world\n");
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_exit_code_in_process() -> anyhow::Result<()> {
+    let (exit_code, _, _) = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(EXIT_CODE)?
+        .with_in_process()
+        .build()?
+        .start()?
+        .wait(Duration::from_secs(10))?;
+
+    assert_eq!(exit_code, 42);
+
+    Ok(())
+}
+
+// Test that an HTTP proxy running in-process drains its in-flight requests when killed with SIGTERM.
+#[test]
+#[serial]
+fn test_wasip2_component_http_proxy_in_process_kill() -> anyhow::Result<()> {
+    let srv = WasiTest::<WasiEngine>::builder()?
+        .with_wasm(HTTP_COUNTER)?
+        .with_annotation(
+            "wasmtime.runwasi.io/http-proxy-shutdown-grace-period",
+            "30s",
+        )
+        .with_in_process()
+        .build()?;
+
+    let srv = srv.start()?;
+    assert!(http_get().unwrap().status().is_success());
+
+    let slow = std::thread::spawn(|| reqwest::blocking::get("http://127.0.0.1:8080/slow"));
+    std::thread::sleep(Duration::from_millis(300));
+
+    // SIGTERM drains the in-flight requests, as for a proxy running in a container
+    srv.terminate()?;
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!slow.is_finished());
+
+    let response = slow.join().unwrap()?;
+    assert!(response.status().is_success());
+    assert_eq!(response.text()?, "slow\n");
+
+    let (exit_code, _, _) = srv.wait(Duration::from_secs(5))?;
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Helper method to read an HTTP/2 frame, as its type, flags, stream id and payload
+fn read_h2_frame(stream: &mut impl std::io::Read) -> std::io::Result<(u8, u8, u32, Vec<u8>)> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+    let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((header[3], header[4], id, payload))
+}
+
 fn http_get() -> reqwest::Result<reqwest::blocking::Response> {
     http_get_with_backoff_secs(1)
 }
 
 // Helper method to make a `GET` request
 fn http_get_with_backoff_secs(backoff: u64) -> reqwest::Result<reqwest::blocking::Response> {
+    retry_with_backoff_secs(backoff, || reqwest::blocking::get("http://127.0.0.1:8080"))
+}
+
+// Helper method to retry a request until the server is up
+fn retry_get(
+    send: impl Fn() -> reqwest::Result<reqwest::blocking::Response>,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    retry_with_backoff_secs(1, send)
+}
+
+// Helper method to retry connecting until the server is up
+fn retry<T, E>(connect: impl Fn() -> Result<T, E>) -> Result<T, E> {
+    retry_with_backoff_secs(1, connect)
+}
+
+fn retry_with_backoff_secs<T, E>(backoff: u64, send: impl Fn() -> Result<T, E>) -> Result<T, E> {
     const MAX_ATTEMPTS: u32 = 10;
     let backoff_duration: Duration = Duration::from_secs(backoff);
 
     let mut attempts = 0;
 
     loop {
-        match reqwest::blocking::get("http://127.0.0.1:8080") {
+        match send() {
             Ok(resp) => break Ok(resp),
             Err(err) if attempts == MAX_ATTEMPTS => break Err(err),
             Err(_) => {
@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+use ttrpc_codegen::{Codegen, ProtobufCustomize};
+
+fn main() {
+    println!("cargo:rerun-if-changed=protos");
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("protos");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    Codegen::new()
+        .out_dir(&out_dir)
+        .include("protos")
+        .input("protos/events.proto")
+        .rust_protobuf()
+        .rust_protobuf_customize(ProtobufCustomize::default().gen_mod_rs(true))
+        .run()
+        .expect("failed to generate protos");
+}
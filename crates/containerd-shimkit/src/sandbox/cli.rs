@@ -93,7 +93,8 @@
 //! is available through environment variables:
 //!
 //! - `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`: Enable OpenTelemetry tracing
-//! - `OTEL_EXPORTER_OTLP_ENDPOINT`: Enable OpenTelemetry tracing as above
+//! - `OTEL_EXPORTER_OTLP_ENDPOINT`: Enable OpenTelemetry tracing as above, and metrics
+//! - `OTEL_EXPORTER_OTLP_METRICS_ENDPOINT`: Export OpenTelemetry metrics, when tracing is enabled
 //! - `OTEL_SDK_DISABLED`: Disable OpenTelemetry SDK
 //!
 
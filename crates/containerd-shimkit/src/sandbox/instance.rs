@@ -1,11 +1,14 @@
 //! Abstractions for running/managing a wasm/wasi instance.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
+use containerd_shim::error::Error as ShimError;
+use oci_spec::runtime::{LinuxResources, Process};
 use serde::{Deserialize, Serialize};
 
 use super::error::Error;
+use super::termination::Termination;
 use crate::sandbox::shim::Config;
 
 /// Generic options builder for creating a wasm instance.
@@ -18,6 +21,8 @@ pub struct InstanceConfig {
     pub stdout: PathBuf,
     /// Optional stderr named pipe path.
     pub stderr: PathBuf,
+    /// Optional pseudo-terminal path, used instead of the stdio named pipes.
+    pub terminal: Option<PathBuf>,
     /// Path to the OCI bundle directory.
     pub bundle: PathBuf,
     /// Namespace for containerd
@@ -26,6 +31,24 @@ pub struct InstanceConfig {
     pub containerd_address: String,
     /// containerd runtime options config
     pub config: Config,
+    /// Optional path to a checkpoint image to restore the instance from.
+    pub checkpoint: Option<PathBuf>,
+}
+
+/// Options for running an additional process in an existing instance.
+/// This is passed to the `Instance::exec` method.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ExecConfig {
+    /// Optional stdin named pipe path.
+    pub stdin: PathBuf,
+    /// Optional stdout named pipe path.
+    pub stdout: PathBuf,
+    /// Optional stderr named pipe path.
+    pub stderr: PathBuf,
+    /// Optional pseudo-terminal path, used instead of the stdio named pipes.
+    pub terminal: Option<PathBuf>,
+    /// The OCI process spec of the process to run, e.g., its args and env.
+    pub process: Process,
 }
 
 /// Represents a WASI module(s).
@@ -39,6 +62,21 @@ pub trait Instance: 'static {
     where
         Self: Sized;
 
+    /// Whether the instance runs the OCI lifecycle hooks of the runtime spec itself,
+    /// e.g., because it uses an OCI runtime to create the container.
+    /// Otherwise the hooks are run by the shim at the matching points of the task lifecycle.
+    /// This is called once the instance is created.
+    fn runs_oci_hooks(&self) -> bool {
+        false
+    }
+
+    /// The pid of the process of the instance, once it's created and before it's started
+    /// This is passed to the OCI hooks that run before the start.
+    /// The default implementation returns `None`.
+    fn pid(&self) -> Option<u32> {
+        None
+    }
+
     /// Start the instance
     /// The returned value should be a unique ID (such as a PID) for the instance.
     /// Nothing internally should be using this ID, but it is returned to containerd where a user may want to use it.
@@ -47,6 +85,41 @@ pub trait Instance: 'static {
     /// Send a signal to the instance
     async fn kill(&self, signal: u32) -> Result<(), Error>;
 
+    /// Write a checkpoint of the running instance to the `path` directory
+    /// The checkpoint can later be restored by creating an instance with `InstanceConfig::checkpoint`.
+    /// The default implementation reports checkpoint as not supported.
+    async fn checkpoint(&self, path: &Path) -> Result<(), Error> {
+        async move {
+            let _ = path;
+            Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into())
+        }
+    }
+
+    /// Pause the instance, whose process has the given `pid`
+    /// The default implementation freezes the cgroup of the process with the cgroup freezer.
+    /// It fails if the process is in the cgroup of the shim.
+    async fn pause(&self, pid: u32) -> Result<(), Error> {
+        async move { crate::sys::cgroup::freeze(pid, true) }
+    }
+
+    /// Resume a paused instance, whose process has the given `pid`
+    /// The default implementation thaws the cgroup of the process with the cgroup freezer.
+    async fn resume(&self, pid: u32) -> Result<(), Error> {
+        async move { crate::sys::cgroup::freeze(pid, false) }
+    }
+
+    /// Update the resource limits of the instance, whose process has the given `pid` once started
+    /// The default implementation applies the resources to the cgroup of the process.
+    /// It fails if the instance hasn't started, or if the process is in the cgroup of the shim.
+    async fn update(&self, pid: Option<u32>, resources: &LinuxResources) -> Result<(), Error> {
+        async move {
+            match pid {
+                Some(pid) => crate::sys::cgroup::update(pid, resources),
+                None => Err(Error::FailedPrecondition("task has no process".to_string())),
+            }
+        }
+    }
+
     /// Delete any reference to the instance
     /// This is called after the instance has exited.
     async fn delete(&self) -> Result<(), Error>;
@@ -54,4 +127,57 @@ pub trait Instance: 'static {
     /// Waits for the instance to finish and returns its exit code
     /// This is an async call.
     async fn wait(&self) -> (u32, DateTime<Utc>);
+
+    /// Returns why the instance terminated, once [`Instance::wait`] has returned.
+    /// The shim publishes it in a `TaskTerminated` event, alongside the `TaskExit` event.
+    /// Returns `None` if the reason is not known.
+    async fn termination(&self) -> Option<Termination> {
+        async move { None }
+    }
+
+    /// Start an additional process in the instance
+    /// This is called when a user runs `ctr task exec` or `kubectl exec`.
+    /// The returned value should be a unique ID (such as a PID) for the process.
+    async fn exec(&self, exec_id: &str, cfg: &ExecConfig) -> Result<u32, Error> {
+        async move {
+            let _ = (exec_id, cfg);
+            Err(ShimError::Unimplemented("exec is not supported".to_string()).into())
+        }
+    }
+
+    /// Send a signal to a process started with `exec`
+    async fn kill_exec(&self, exec_id: &str, signal: u32) -> Result<(), Error> {
+        async move {
+            let _ = (exec_id, signal);
+            Err(ShimError::Unimplemented("exec is not supported".to_string()).into())
+        }
+    }
+
+    /// Delete any reference to a process started with `exec`
+    /// This is called after the process has exited.
+    async fn delete_exec(&self, exec_id: &str) -> Result<(), Error> {
+        async move {
+            let _ = exec_id;
+            Ok(())
+        }
+    }
+
+    /// Waits for a process started with `exec` to finish and returns its exit code
+    /// This is an async call.
+    async fn wait_exec(&self, exec_id: &str) -> (u32, DateTime<Utc>) {
+        async move {
+            let _ = exec_id;
+            std::future::pending().await
+        }
+    }
+
+    /// Returns why a process started with `exec` terminated, once [`Instance::wait_exec`]
+    /// has returned, like [`Instance::termination`] does for the instance.
+    /// Returns `None` if the reason is not known.
+    async fn exec_termination(&self, exec_id: &str) -> Option<Termination> {
+        async move {
+            let _ = exec_id;
+            None
+        }
+    }
 }
@@ -2,11 +2,11 @@
 
 use std::fs::File;
 use std::io::{Error as IoError, ErrorKind, Result as IoResult};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use super::{Error, InstanceConfig};
+use super::{Error, ExecConfig, InstanceConfig};
 use crate::sys::DEFAULT_CONTAINER_ROOT_DIR;
 use crate::sys::stdio::open;
 
@@ -18,7 +18,8 @@ struct Options {
 impl InstanceConfig {
     /// Determine the root directory for the container runtime.
     ///
-    /// If the `bundle` directory contains an `options.json` file, the root directory is read from the
+    /// If the `root` option of the shim [`Config`](super::Config) is set, it is used as the root directory.
+    /// Otherwise, if the `bundle` directory contains an `options.json` file, the root directory is read from the
     /// file. Otherwise, the root directory is determined by `{DEFAULT_CONTAINER_ROOT_DIR}/{runtime}/{namespace}`.
     ///
     /// The default root directory is `/run/containerd/<wasm engine name>/<namespace>`.
@@ -27,6 +28,11 @@ impl InstanceConfig {
         &self,
         runtime: impl AsRef<str> + std::fmt::Debug,
     ) -> Result<PathBuf, Error> {
+        if let Some(root) = &self.config.root {
+            let path = root.join(&self.namespace);
+            log::info!("container runtime root path is {path:?}");
+            return Ok(path);
+        }
         let rootdir = DEFAULT_CONTAINER_ROOT_DIR.join(runtime.as_ref());
         let file = match File::open(self.bundle.join("options.json")) {
             Ok(f) => f,
@@ -42,33 +48,46 @@ impl InstanceConfig {
     }
 
     pub fn open_stdin(&self) -> IoResult<File> {
-        if self.stdin.as_os_str().is_empty() {
-            return Err(IoError::new(ErrorKind::NotFound, "File not found"));
-        }
-        open(&self.stdin)
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stdin))
     }
 
     pub fn open_stdout(&self) -> IoResult<File> {
-        if self.stdout.as_os_str().is_empty() {
-            return Err(IoError::new(ErrorKind::NotFound, "File not found"));
-        }
-        open(&self.stdout)
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stdout))
     }
 
     pub fn open_stderr(&self) -> IoResult<File> {
-        if self.stderr.as_os_str().is_empty() {
-            return Err(IoError::new(ErrorKind::NotFound, "File not found"));
-        }
-        open(&self.stderr)
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stderr))
     }
 }
 
+impl ExecConfig {
+    pub fn open_stdin(&self) -> IoResult<File> {
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stdin))
+    }
+
+    pub fn open_stdout(&self) -> IoResult<File> {
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stdout))
+    }
+
+    pub fn open_stderr(&self) -> IoResult<File> {
+        open_if_set(self.terminal.as_ref().unwrap_or(&self.stderr))
+    }
+}
+
+fn open_if_set(path: &Path) -> IoResult<File> {
+    if path.as_os_str().is_empty() {
+        return Err(IoError::new(ErrorKind::NotFound, "File not found"));
+    }
+    open(path)
+}
+
 #[cfg(unix)]
 #[cfg(test)]
 mod tests {
     use tempfile::tempdir;
 
     use super::*;
+    use crate::sandbox::Config;
 
     #[test]
     fn test_determine_rootdir_with_options_file() -> Result<(), Error> {
@@ -92,6 +111,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_determine_rootdir_with_root_option() -> Result<(), Error> {
+        let namespace = "test_namespace";
+        let dir = tempdir()?;
+        let rootdir = dir.path().join("runwasi");
+        let opts = Options {
+            root: Some(dir.path().join("ignored")),
+        };
+        std::fs::write(
+            dir.path().join("options.json"),
+            serde_json::to_string(&opts)?,
+        )?;
+        let cfg = InstanceConfig {
+            bundle: dir.path().to_path_buf(),
+            namespace: namespace.to_string(),
+            config: Config {
+                root: Some(rootdir.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let root = cfg.determine_rootdir("runtime")?;
+        assert_eq!(root, rootdir.join(namespace));
+        Ok(())
+    }
+
     #[test]
     fn test_determine_rootdir_without_options_file() -> Result<(), Error> {
         let dir = tempdir()?;
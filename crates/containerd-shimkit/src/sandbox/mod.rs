@@ -57,11 +57,15 @@ pub mod error;
 pub mod instance;
 pub mod shim;
 pub mod sync;
+#[cfg(all(unix, feature = "opentelemetry"))]
+pub mod telemetry;
+pub mod termination;
 
 pub use error::{Error, Result};
-pub use instance::{Instance, InstanceConfig};
-pub use shim::Config;
+pub use instance::{ExecConfig, Instance, InstanceConfig};
 pub(crate) use shim::Shim;
+pub use shim::{Config, EngineOptions, Feature};
+pub use termination::Termination;
 
 pub(crate) mod instance_utils;
 pub(crate) mod oci;
@@ -1,14 +1,18 @@
 //! Generic helpers for working with OCI specs that can be consumed by any runtime.
 
 use std::collections::HashMap;
-use std::io::{ErrorKind, Write};
-#[cfg(unix)]
-use std::os::unix::process::CommandExt;
-use std::process;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
 
-use anyhow::Context;
+use oci_spec::runtime::{Hook, Hooks, Spec};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
 
-use super::error::Result;
+use super::error::{Error, Result};
 
 fn parse_env(envs: &[String]) -> HashMap<String, String> {
     // make NAME=VALUE to HashMap<NAME, VALUE>.
@@ -24,73 +28,344 @@ fn parse_env(envs: &[String]) -> HashMap<String, String> {
         .collect()
 }
 
-pub(crate) fn setup_prestart_hooks(hooks: &Option<oci_spec::runtime::Hooks>) -> Result<()> {
-    if let Some(hooks) = hooks {
-        let prestart_hooks = hooks.prestart().as_ref().unwrap();
-
-        for hook in prestart_hooks {
-            let mut hook_command = process::Command::new(hook.path());
-            // Based on OCI spec, the first argument of the args vector is the
-            // arg0, which can be different from the path.  For example, path
-            // may be "/usr/bin/true" and arg0 is set to "true". However, rust
-            // command differentiates arg0 from args, where rust command arg
-            // doesn't include arg0. So we have to make the split arg0 from the
-            // rest of args.
-            if let Some((arg0, args)) = hook.args().as_ref().and_then(|a| a.split_first()) {
-                log::debug!("run_hooks arg0: {:?}, args: {:?}", arg0, args);
-
-                #[cfg(unix)]
-                {
-                    hook_command.arg0(arg0).args(args);
-                }
+/// The points of the container lifecycle where OCI hooks run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HookPhase {
+    Prestart,
+    CreateRuntime,
+    CreateContainer,
+    StartContainer,
+    Poststart,
+    Poststop,
+}
+
+impl Display for HookPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HookPhase::Prestart => "prestart",
+            HookPhase::CreateRuntime => "createRuntime",
+            HookPhase::CreateContainer => "createContainer",
+            HookPhase::StartContainer => "startContainer",
+            HookPhase::Poststart => "poststart",
+            HookPhase::Poststop => "poststop",
+        })
+    }
+}
+
+impl HookPhase {
+    /// The status of the container when the hooks of this phase run.
+    fn status(self) -> ContainerStatus {
+        match self {
+            HookPhase::Prestart | HookPhase::CreateRuntime | HookPhase::CreateContainer => {
+                ContainerStatus::Creating
+            }
+            HookPhase::StartContainer => ContainerStatus::Created,
+            HookPhase::Poststart => ContainerStatus::Running,
+            HookPhase::Poststop => ContainerStatus::Stopped,
+        }
+    }
+}
+
+/// The status of the container in the state passed to the OCI hooks.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ContainerStatus {
+    Creating,
+    Created,
+    Running,
+    Stopped,
+}
+
+/// The state of the container, as defined by the OCI runtime spec.
+/// This is passed to the hooks on their stdin.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct State<'a> {
+    oci_version: &'a str,
+    id: &'a str,
+    status: ContainerStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    bundle: &'a PathBuf,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    annotations: &'a HashMap<String, String>,
+}
+
+/// The OCI hooks of a container, and the information needed to run them.
+#[derive(Debug)]
+pub(crate) struct ContainerHooks {
+    id: String,
+    bundle: PathBuf,
+    oci_version: String,
+    annotations: HashMap<String, String>,
+    hooks: Hooks,
+}
+
+impl ContainerHooks {
+    /// Returns `None` if the spec doesn't have any hooks.
+    pub fn new(id: impl Into<String>, bundle: impl Into<PathBuf>, spec: &Spec) -> Option<Self> {
+        Some(Self {
+            id: id.into(),
+            bundle: bundle.into(),
+            oci_version: spec.version().clone(),
+            annotations: spec.annotations().clone().unwrap_or_default(),
+            hooks: spec.hooks().clone()?,
+        })
+    }
+
+    /// Run the hooks of the given phase, in order, stopping at the first failure.
+    pub async fn run(&self, phase: HookPhase, pid: Option<u32>) -> Result<()> {
+        let hooks = match phase {
+            HookPhase::Prestart => self.hooks.prestart(),
+            HookPhase::CreateRuntime => self.hooks.create_runtime(),
+            HookPhase::CreateContainer => self.hooks.create_container(),
+            HookPhase::StartContainer => self.hooks.start_container(),
+            HookPhase::Poststart => self.hooks.poststart(),
+            HookPhase::Poststop => self.hooks.poststop(),
+        };
+        let Some(hooks) = hooks else {
+            return Ok(());
+        };
 
-                #[cfg(windows)]
-                {
-                    if !&hook.path().ends_with(arg0) {
-                        return Err(crate::sandbox::Error::InvalidArgument("Running with arg0 as different name than executable is not supported on Windows due to rust std library process implementation.".to_string()));
-                    }
+        let state = serde_json::to_vec(&State {
+            oci_version: &self.oci_version,
+            id: &self.id,
+            status: phase.status(),
+            pid,
+            bundle: &self.bundle,
+            annotations: &self.annotations,
+        })?;
 
-                    hook_command.args(args);
+        for hook in hooks {
+            log::debug!("running {phase} hook {:?}", hook.path());
+            run_hook(hook, &state).await.map_err(|err| match err {
+                Error::InvalidArgument(msg) => {
+                    Error::InvalidArgument(format!("{phase} hook {:?}: {msg}", hook.path()))
                 }
-            } else {
-                #[cfg(unix)]
-                hook_command.arg0(hook.path());
-            };
-
-            let envs: HashMap<String, String> = if let Some(env) = hook.env() {
-                parse_env(env)
-            } else {
-                HashMap::new()
-            };
-            log::debug!("run_hooks envs: {:?}", envs);
-
-            let mut hook_process = hook_command
-                .env_clear()
-                .envs(envs)
-                .stdin(process::Stdio::piped())
-                .spawn()
-                .with_context(|| "Failed to execute hook")?;
-
-            if let Some(stdin) = &mut hook_process.stdin {
-                // We want to ignore BrokenPipe here. A BrokenPipe indicates
-                // either the hook is crashed/errored or it ran successfully.
-                // Either way, this is an indication that the hook command
-                // finished execution.  If the hook command was successful,
-                // which we will check later in this function, we should not
-                // fail this step here. We still want to check for all the other
-                // error, in the case that the hook command is waiting for us to
-                // write to stdin.
-                let state = format!("{{ \"pid\": {} }}", std::process::id());
-                if let Err(e) = stdin.write_all(state.as_bytes()) {
-                    if e.kind() != ErrorKind::BrokenPipe {
-                        // Not a broken pipe. The hook command may be waiting
-                        // for us.
-                        let _ = hook_process.kill();
-                    }
+                Error::FailedPrecondition(msg) => {
+                    Error::FailedPrecondition(format!("{phase} hook {:?}: {msg}", hook.path()))
+                }
+                err => err,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn run_hook(hook: &Hook, state: &[u8]) -> Result<()> {
+    let timeout = match hook.timeout() {
+        Some(t) if t <= 0 => {
+            return Err(Error::InvalidArgument(format!(
+                "timeout must be greater than zero, got {t}"
+            )));
+        }
+        t => t.map(|t| Duration::from_secs(t as u64)),
+    };
+
+    let mut hook_command = Command::new(hook.path());
+    // Based on OCI spec, the first argument of the args vector is the
+    // arg0, which can be different from the path.  For example, path
+    // may be "/usr/bin/true" and arg0 is set to "true". However, rust
+    // command differentiates arg0 from args, where rust command arg
+    // doesn't include arg0. So we have to make the split arg0 from the
+    // rest of args.
+    if let Some((arg0, args)) = hook.args().as_ref().and_then(|a| a.split_first()) {
+        log::debug!("run_hooks arg0: {:?}, args: {:?}", arg0, args);
+
+        #[cfg(unix)]
+        {
+            hook_command.arg0(arg0).args(args);
+        }
+
+        #[cfg(windows)]
+        {
+            if !&hook.path().ends_with(arg0) {
+                return Err(Error::InvalidArgument("Running with arg0 as different name than executable is not supported on Windows due to rust std library process implementation.".to_string()));
+            }
+
+            hook_command.args(args);
+        }
+    } else {
+        #[cfg(unix)]
+        hook_command.arg0(hook.path());
+    };
+
+    let envs: HashMap<String, String> = if let Some(env) = hook.env() {
+        parse_env(env)
+    } else {
+        HashMap::new()
+    };
+    log::debug!("run_hooks envs: {:?}", envs);
+
+    let mut hook_process = hook_command
+        .env_clear()
+        .envs(envs)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| Error::InvalidArgument(format!("failed to execute hook: {err}")))?;
+
+    // Take stdin so that it's closed once the state is written, as hooks may
+    // read it until EOF.
+    let stdin = hook_process.stdin.take();
+    // The timeout covers writing the state too, as a hook that doesn't read
+    // its stdin blocks the write once the pipe is full.
+    let run = async {
+        if let Some(mut stdin) = stdin {
+            // We want to ignore BrokenPipe here. A BrokenPipe indicates
+            // either the hook is crashed/errored or it ran successfully.
+            // Either way, this is an indication that the hook command
+            // finished execution.  If the hook command was successful,
+            // which we will check later in this function, we should not
+            // fail this step here. We still want to check for all the other
+            // error, in the case that the hook command is waiting for us to
+            // write to stdin.
+            if let Err(e) = stdin.write_all(state).await {
+                if e.kind() != ErrorKind::BrokenPipe {
+                    // Not a broken pipe. The hook command may be waiting
+                    // for us.
+                    return Err(e.into());
                 }
             }
-            hook_process.wait()?;
         }
+        Ok(hook_process.wait().await?)
+    };
+
+    let res = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, run).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::FailedPrecondition(format!(
+                "hook timed out after {}s",
+                timeout.as_secs()
+            ))),
+        },
+        None => run.await,
+    };
+    let status = match res {
+        Ok(status) => status,
+        Err(err) => {
+            let _ = hook_process.kill().await;
+            return Err(err);
+        }
+    };
+
+    if !status.success() {
+        return Err(Error::FailedPrecondition(format!("hook failed: {status}")));
     }
+
     Ok(())
 }
+
+#[cfg(unix)]
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use oci_spec::runtime::{HookBuilder, HooksBuilder, SpecBuilder};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn sh(script: &str) -> Hook {
+        HookBuilder::default()
+            .path("/bin/sh")
+            .args(vec!["sh".to_string(), "-c".to_string(), script.to_string()])
+            .build()
+            .unwrap()
+    }
+
+    fn container_hooks(hooks: Hooks) -> ContainerHooks {
+        let spec = SpecBuilder::default()
+            .hooks(hooks)
+            .annotations(HashMap::from([("foo".to_string(), "bar".to_string())]))
+            .build()
+            .unwrap();
+        ContainerHooks::new("test", "/bundle", &spec).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hook_receives_state() -> Result<()> {
+        let dir = tempdir()?;
+        let out = dir.path().join("state.json");
+        let hooks = HooksBuilder::default()
+            .poststart(vec![sh(&format!("cat > {}", out.display()))])
+            .build()
+            .unwrap();
+
+        container_hooks(hooks)
+            .run(HookPhase::Poststart, Some(42))
+            .await?;
+
+        let state: serde_json::Value = serde_json::from_slice(&std::fs::read(out)?)?;
+        assert_eq!(state["id"], "test");
+        assert_eq!(state["status"], "running");
+        assert_eq!(state["pid"], 42);
+        assert_eq!(state["bundle"], "/bundle");
+        assert_eq!(state["annotations"]["foo"], "bar");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hook_failure() {
+        let hooks = HooksBuilder::default()
+            .create_runtime(vec![sh("exit 1")])
+            .build()
+            .unwrap();
+
+        let err = container_hooks(hooks)
+            .run(HookPhase::CreateRuntime, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::FailedPrecondition(_)), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_hook_timeout() {
+        let mut hook = sh("sleep 10");
+        hook.set_timeout(Some(1));
+        let hooks = HooksBuilder::default()
+            .poststop(vec![hook])
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let err = container_hooks(hooks)
+            .run(HookPhase::Poststop, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::FailedPrecondition(_)), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_hook_timeout_writing_state() {
+        let mut hook = sh("sleep 10");
+        hook.set_timeout(Some(1));
+        // larger than the pipe buffer, so that the write blocks as the hook doesn't read it
+        let state = vec![b' '; 1 << 20];
+
+        let start = Instant::now();
+        let err = run_hook(&hook, &state).await.unwrap_err();
+        assert!(matches!(err, Error::FailedPrecondition(_)), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_missing_hook() {
+        let hook = HookBuilder::default()
+            .path("/does/not/exist")
+            .build()
+            .unwrap();
+        let hooks = HooksBuilder::default()
+            .prestart(vec![hook])
+            .build()
+            .unwrap();
+
+        let err = container_hooks(hooks)
+            .run(HookPhase::Prestart, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)), "{err}");
+    }
+}
@@ -6,6 +6,26 @@ use containerd_shim::publisher::RemotePublisher;
 use log::warn;
 use protobuf::well_known_types::timestamp::Timestamp;
 
+// the generated code allows lints that have been removed since
+#[allow(renamed_and_removed_lints)]
+mod protos {
+    include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
+}
+
+pub use protos::events::{TaskRejected, TaskTerminated};
+
+impl Event for TaskRejected {
+    fn topic(&self) -> String {
+        "/runwasi/tasks/rejected".to_string()
+    }
+}
+
+impl Event for TaskTerminated {
+    fn topic(&self) -> String {
+        "/runwasi/tasks/terminated".to_string()
+    }
+}
+
 pub trait EventSender: Clone + Send + Sync + 'static {
     fn send(&self, event: impl Event);
 }
@@ -1,14 +1,40 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
 use chrono::{DateTime, Utc};
-use tokio::sync::{OnceCell, RwLock};
+use oci_spec::runtime::LinuxResources;
+use tokio::sync::{Mutex, OnceCell, RwLock};
 
+use crate::sandbox::oci::{ContainerHooks, HookPhase};
 use crate::sandbox::shim::task_state::TaskState;
-use crate::sandbox::{Instance, InstanceConfig, Result};
+use crate::sandbox::{Error, ExecConfig, Instance, InstanceConfig, Result};
+use crate::sys::pty::Pty;
 
 pub(super) struct InstanceData<T: Instance> {
     pub instance: T,
     pub config: InstanceConfig,
     pid: OnceCell<u32>,
     state: RwLock<TaskState>,
+    execs: RwLock<HashMap<String, Arc<ExecData>>>,
+    pty: Option<Pty>,
+    hooks: Option<ContainerHooks>,
+    // Serializes checkpoints without holding `state`, which would block `kill` and `delete`
+    checkpoint: Mutex<()>,
+}
+
+/// An additional process started in the instance with `exec`.
+pub(super) struct ExecData {
+    pub config: ExecConfig,
+    pid: OnceCell<u32>,
+    state: RwLock<TaskState>,
+    pty: Option<Pty>,
+}
+
+impl ExecData {
+    pub fn pid(&self) -> Option<u32> {
+        self.pid.get().copied()
+    }
 }
 
 impl<T: Instance> InstanceData<T> {
@@ -16,14 +42,21 @@ impl<T: Instance> InstanceData<T> {
     pub async fn new(
         id: impl AsRef<str> + std::fmt::Debug,
         config: InstanceConfig,
+        pty: Option<Pty>,
+        hooks: Option<ContainerHooks>,
     ) -> Result<Self> {
         let id = id.as_ref().to_string();
         let instance = T::new(id, &config).await?;
+        let hooks = hooks.filter(|_| !instance.runs_oci_hooks());
         Ok(Self {
             instance,
             config,
             pid: OnceCell::default(),
             state: RwLock::new(TaskState::Created),
+            execs: RwLock::default(),
+            pty,
+            hooks,
+            checkpoint: Mutex::default(),
         })
     }
 
@@ -32,13 +65,40 @@ impl<T: Instance> InstanceData<T> {
         self.pid.get().copied()
     }
 
+    /// Run the OCI hooks of the given phase, if the instance doesn't run them itself.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn run_hooks(&self, phase: HookPhase) -> Result<()> {
+        let Some(hooks) = &self.hooks else {
+            return Ok(());
+        };
+        // The hooks that run before the start get the pid of the created process
+        let pid = self.pid().or_else(|| self.instance.pid());
+        hooks.run(phase, pid).await
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub async fn start(&self) -> Result<u32> {
         let mut s = self.state.write().await;
         s.start()?;
 
+        // The process is only reported as started once its terminal is forwarded
+        if let Some(pty) = &self.pty {
+            if let Err(err) = pty.forward(&self.config.stdin, &self.config.stdout) {
+                // Always `Ok(())` because we hold the lock since `s.start()`
+                let _ = s.stop();
+                return Err(err.into());
+            }
+        }
+
         let res = self.instance.start().await;
 
+        if let Some(pty) = &self.pty {
+            pty.release_terminal();
+            if res.is_err() {
+                pty.stop();
+            }
+        }
+
         // These state transitions are always `Ok(())` because
         // we hold the lock since `s.start()`
         let _ = match res {
@@ -60,6 +120,65 @@ impl<T: Instance> InstanceData<T> {
         self.instance.kill(signal).await
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn checkpoint(&self, path: &Path) -> Result<()> {
+        let _guard = self.checkpoint.lock().await;
+        self.state.write().await.checkpoint()?;
+
+        self.instance.checkpoint(path).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn update(&self, resources: &LinuxResources) -> Result<()> {
+        let mut s = self.state.write().await;
+        s.update()?;
+
+        self.instance.update(self.pid(), resources).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn pause(&self) -> Result<()> {
+        let mut s = self.state.write().await;
+        s.pause()?;
+
+        // The task is running, so it has a pid
+        let res = match self.pid() {
+            Some(pid) => self.instance.pause(pid).await,
+            None => Err(Error::FailedPrecondition("task has no process".to_string())),
+        };
+
+        if res.is_err() {
+            // Always `Ok(())` because we hold the lock since `s.pause()`
+            let _ = s.resume();
+        }
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn resume(&self) -> Result<()> {
+        let mut s = self.state.write().await;
+        s.resume()?;
+
+        // The task is running, so it has a pid
+        let res = match self.pid() {
+            Some(pid) => self.instance.resume(pid).await,
+            None => Err(Error::FailedPrecondition("task has no process".to_string())),
+        };
+
+        if res.is_err() {
+            // Always `Ok(())` because we hold the lock since `s.resume()`
+            let _ = s.pause();
+        }
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn is_paused(&self) -> bool {
+        matches!(*self.state.read().await, TaskState::Paused)
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub async fn delete(&self) -> Result<()> {
         let mut s = self.state.write().await;
@@ -78,8 +197,131 @@ impl<T: Instance> InstanceData<T> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub async fn wait(&self) -> (u32, DateTime<Utc>) {
         let res = self.instance.wait().await;
+        if let Some(pty) = &self.pty {
+            pty.stop();
+        }
         let mut s = self.state.write().await;
         *s = TaskState::Exited;
         res
     }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn add_exec(
+        &self,
+        exec_id: &str,
+        config: ExecConfig,
+        pty: Option<Pty>,
+    ) -> Result<()> {
+        let mut execs = self.execs.write().await;
+        if execs.contains_key(exec_id) {
+            return Err(Error::AlreadyExists(exec_id.to_string()));
+        }
+        let exec = ExecData {
+            config,
+            pid: OnceCell::default(),
+            state: RwLock::new(TaskState::Created),
+            pty,
+        };
+        execs.insert(exec_id.to_string(), Arc::new(exec));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn get_exec(&self, exec_id: &str) -> Result<Arc<ExecData>> {
+        let exec = self.execs.read().await.get(exec_id).cloned();
+        exec.ok_or_else(|| Error::NotFound(exec_id.to_string()))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn start_exec(&self, exec_id: &str) -> Result<u32> {
+        let exec = self.get_exec(exec_id).await?;
+        let mut s = exec.state.write().await;
+        s.start()?;
+
+        // The process is only reported as started once its terminal is forwarded
+        if let Some(pty) = &exec.pty {
+            if let Err(err) = pty.forward(&exec.config.stdin, &exec.config.stdout) {
+                // Always `Ok(())` because we hold the lock since `s.start()`
+                let _ = s.stop();
+                return Err(err.into());
+            }
+        }
+
+        let res = self.instance.exec(exec_id, &exec.config).await;
+
+        if let Some(pty) = &exec.pty {
+            pty.release_terminal();
+            if res.is_err() {
+                pty.stop();
+            }
+        }
+
+        // These state transitions are always `Ok(())` because
+        // we hold the lock since `s.start()`
+        let _ = match res {
+            Ok(pid) => {
+                let _ = exec.pid.set(pid);
+                s.started()
+            }
+            Err(_) => s.stop(),
+        };
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn resize_pty(&self, exec_id: &str, width: u32, height: u32) -> Result<()> {
+        let no_terminal = || Error::FailedPrecondition("process has no terminal".to_string());
+        if exec_id.is_empty() {
+            let pty = self.pty.as_ref().ok_or_else(no_terminal)?;
+            pty.resize(width, height)?;
+        } else {
+            let exec = self.get_exec(exec_id).await?;
+            let pty = exec.pty.as_ref().ok_or_else(no_terminal)?;
+            pty.resize(width, height)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn kill_exec(&self, exec_id: &str, signal: u32) -> Result<()> {
+        let exec = self.get_exec(exec_id).await?;
+        let mut s = exec.state.write().await;
+        s.kill()?;
+
+        self.instance.kill_exec(exec_id, signal).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn delete_exec(&self, exec_id: &str) -> Result<()> {
+        let exec = self.get_exec(exec_id).await?;
+        let mut s = exec.state.write().await;
+        s.delete()?;
+
+        let res = self.instance.delete_exec(exec_id).await;
+
+        match res {
+            Ok(()) => {
+                self.execs.write().await.remove(exec_id);
+            }
+            Err(_) => {
+                // Always `Ok(())` because we hold the lock since `s.delete()`
+                let _ = s.stop();
+            }
+        }
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub async fn wait_exec(&self, exec_id: &str) -> Result<(u32, DateTime<Utc>)> {
+        let exec = self.get_exec(exec_id).await?;
+        let res = self.instance.wait_exec(exec_id).await;
+        if let Some(pty) = &exec.pty {
+            pty.stop();
+        }
+        let mut s = exec.state.write().await;
+        *s = TaskState::Exited;
+        Ok(res)
+    }
 }
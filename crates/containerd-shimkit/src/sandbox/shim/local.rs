@@ -1,26 +1,34 @@
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs::create_dir_all;
-use std::ops::Not;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::ensure;
 use containerd_shim::api::{
-    ConnectRequest, ConnectResponse, CreateTaskRequest, CreateTaskResponse, DeleteRequest, Empty,
-    KillRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse,
-    StatsRequest, StatsResponse, WaitRequest, WaitResponse,
+    CheckpointTaskRequest, ConnectRequest, ConnectResponse, CreateTaskRequest, CreateTaskResponse,
+    DeleteRequest, Empty, ExecProcessRequest, KillRequest, PauseRequest, ResizePtyRequest,
+    ResumeRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse,
+    StatsRequest, StatsResponse, UpdateTaskRequest, WaitRequest, WaitResponse,
 };
 use containerd_shim::error::Error as ShimError;
-use containerd_shim::protos::events::task::{TaskCreate, TaskDelete, TaskExit, TaskIO, TaskStart};
+use containerd_shim::protos::events::task::{
+    TaskCheckpointed, TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskExit, TaskIO,
+    TaskPaused, TaskResumed, TaskStart,
+};
+use containerd_shim::protos::shim::oci::CheckpointOptions;
 use containerd_shim::protos::shim::shim_ttrpc::Task;
 use containerd_shim::protos::types::task::Status;
 use containerd_shim::util::IntoOption;
 use containerd_shim::{DeleteResponse, TtrpcContext, TtrpcResult};
 use futures::FutureExt as _;
-use log::debug;
-use oci_spec::runtime::Spec;
+use log::{LevelFilter, debug};
+use oci_spec::runtime::{LinuxResources, Process, Spec};
 use prost::Message;
+use protobuf::Message as _;
 use protobuf::well_known_types::any::Any;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 #[cfg(feature = "opentelemetry")]
@@ -29,16 +37,24 @@ use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 #[cfg(feature = "opentelemetry")]
 use super::otel::extract_context;
 use crate::sandbox::async_utils::AmbientRuntime as _;
-use crate::sandbox::instance::{Instance, InstanceConfig};
-use crate::sandbox::shim::events::{EventSender, RemoteEventSender, ToTimestamp};
+use crate::sandbox::instance::{ExecConfig, Instance, InstanceConfig};
+use crate::sandbox::oci::{ContainerHooks, HookPhase};
+use crate::sandbox::shim::events::{
+    EventSender, RemoteEventSender, TaskRejected, TaskTerminated, ToTimestamp,
+};
 use crate::sandbox::shim::instance_data::InstanceData;
 use crate::sandbox::sync::WaitableCell;
-use crate::sandbox::{Error, Result, oci};
+use crate::sandbox::{Error, Result, Termination};
 use crate::sys::metrics::get_metrics;
+use crate::sys::pty::Pty;
+use crate::vendor::containerd_shim::logger::LOG_ENV;
 
 #[cfg(test)]
 mod tests;
 
+/// The signal that stops a task that exits after a checkpoint.
+const SIGKILL: u32 = 9;
+
 /// containerd runtime options
 #[derive(Message, Clone, PartialEq)]
 struct Options {
@@ -53,15 +69,112 @@ struct Options {
 /// This is generated by decoding the `options` field of a `CreateTaskRequest` to get an `Options` struct,
 /// interpreting the `config_body` field as TOML,
 /// and deserializing it.
+///
+/// The `config_body` is the TOML of the `options` table of the runtime handler
+/// in containerd's `config.toml`, e.g.:
+///
+/// ```toml
+/// [plugins."io.containerd.grpc.v1.cri".containerd.runtimes.wasm.options]
+/// SystemdCgroup = true
+/// LogLevel = "debug"
+/// AllowedFeatures = ["exec", "pause"]
+///
+/// [plugins."io.containerd.grpc.v1.cri".containerd.runtimes.wasm.options.Engine]
+/// cache = true
+/// ```
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(default)]
 pub struct Config {
     /// Enables systemd cgroup.
     #[serde(alias = "SystemdCgroup")]
     pub systemd_cgroup: bool,
+    /// Runs the tasks inside the shim process instead of in a container,
+    /// for shims that support it.
+    #[serde(alias = "InProcess")]
+    pub in_process: bool,
+    /// Forbids running native executables, so that only Wasm containers can run,
+    /// for shims that would otherwise run them as regular Linux containers.
+    #[serde(alias = "DisableLinuxFallback")]
+    pub disable_linux_fallback: bool,
+    /// Cgroup the shim process is moved to when it starts, e.g., the cgroup of the pod,
+    /// so that the tasks running in-process are accounted to it.
+    #[serde(alias = "ShimCgroup")]
+    pub shim_cgroup: String,
+    /// Default log level of the shim, e.g., `"debug"`.
+    /// The `RUST_LOG` environment variable takes precedence over it.
+    #[serde(alias = "LogLevel")]
+    pub log_level: Option<String>,
+    /// Disables the precompilation of Wasm modules, for shims that support it.
+    #[serde(alias = "DisablePrecompilation")]
+    pub disable_precompilation: bool,
+    /// Overrides the root directory of the container runtime state.
+    /// The namespace is appended to it.
+    #[serde(alias = "Root")]
+    pub root: Option<PathBuf>,
+    /// Features the tasks are allowed to use. All features are allowed if this is not set.
+    #[serde(alias = "AllowedFeatures")]
+    pub allowed_features: Option<Vec<Feature>>,
+    /// Writes a Wasm core dump of the guests that trap, for shims that support it.
+    #[serde(alias = "CoreDumpOnTrap")]
+    pub core_dump_on_trap: bool,
+    /// Directory the core dumps are written to, instead of the bundle of the task.
+    #[serde(alias = "CoreDumpDir")]
+    pub core_dump_dir: Option<PathBuf>,
+    /// Engine specific options, passed as is to the shim implementation.
+    #[serde(alias = "Engine")]
+    pub engine: EngineOptions,
+}
+
+/// Features of the shim that can be restricted with [`Config::allowed_features`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    /// Running additional processes in a task with `exec`.
+    Exec,
+    /// Pausing and resuming tasks.
+    Pause,
+    /// Updating the resource limits of running tasks.
+    Update,
+    /// Checkpointing tasks, and restoring them from a checkpoint.
+    Checkpoint,
+    /// Running tasks and processes with a terminal.
+    Terminal,
+    /// Running tasks inside the shim process.
+    InProcess,
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Feature::Exec => "exec",
+            Feature::Pause => "pause",
+            Feature::Update => "update",
+            Feature::Checkpoint => "checkpoint",
+            Feature::Terminal => "terminal",
+            Feature::InProcess => "in-process",
+        })
+    }
+}
+
+/// Opaque engine specific options, from the `Engine` table of the runtime options.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct EngineOptions(toml::Table);
+
+impl EngineOptions {
+    /// Deserialize the options into the shim's own options type.
+    pub fn get<T: DeserializeOwned>(&self) -> Result<T> {
+        T::deserialize(self.0.clone())
+            .map_err(|err| Error::InvalidArgument(format!("invalid engine options: {err}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl Config {
-    fn get_from_options(options: Option<&Any>) -> anyhow::Result<Self> {
+    pub(crate) fn get_from_options(options: Option<&Any>) -> anyhow::Result<Self> {
         let Some(opts) = options else {
             return Ok(Default::default());
         };
@@ -74,11 +187,118 @@ impl Config {
 
         let opts = Options::decode(opts.value.as_slice())?;
 
-        let config = toml::from_str(opts.config_body.as_str())
+        let config: Self = toml::from_str(opts.config_body.as_str())
             .map_err(|err| Error::InvalidArgument(format!("invalid shim options: {err}")))?;
+        config.validate()?;
 
         Ok(config)
     }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(level) = &self.log_level {
+            ensure!(
+                LevelFilter::from_str(level).is_ok(),
+                "invalid log level {level:?}"
+            );
+        }
+        if let Some(root) = &self.root {
+            ensure!(root.is_absolute(), "root {root:?} is not an absolute path");
+        }
+        if let Some(dir) = &self.core_dump_dir {
+            ensure!(
+                dir.is_absolute(),
+                "core dump directory {dir:?} is not an absolute path"
+            );
+        }
+        ensure!(
+            !self.in_process || self.allows(Feature::InProcess),
+            "InProcess is set, but the in-process feature is not allowed"
+        );
+        Ok(())
+    }
+
+    /// Whether the tasks are allowed to use the given feature.
+    pub fn allows(&self, feature: Feature) -> bool {
+        self.allowed_features
+            .as_ref()
+            .is_none_or(|features| features.contains(&feature))
+    }
+
+    /// Fails with [`Error::PermissionDenied`] if the tasks are not allowed to use the given feature.
+    pub fn ensure_allowed(&self, feature: Feature) -> Result<()> {
+        if !self.allows(feature) {
+            return Err(Error::PermissionDenied(format!(
+                "the {feature} feature is not allowed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sets the maximum log level of the current process to [`Config::log_level`],
+    /// unless the `RUST_LOG` environment variable is set.
+    pub fn set_log_level(&self) {
+        let Some(level) = self.log_level.as_deref() else {
+            return;
+        };
+        if std::env::var_os(LOG_ENV).is_some() {
+            return;
+        }
+        if let Ok(level) = LevelFilter::from_str(level) {
+            log::set_max_level(level);
+        }
+    }
+}
+
+/// Decodes the `options` of a `CheckpointTaskRequest`.
+/// Options that only make sense for CRIU are rejected, rather than silently ignored.
+fn checkpoint_options(options: Option<&Any>) -> Result<CheckpointOptions> {
+    let Some(opts) = options else {
+        return Ok(Default::default());
+    };
+
+    if opts.type_url.rsplit('/').next() != Some("containerd.runc.v1.CheckpointOptions") {
+        return Err(Error::InvalidArgument(format!(
+            "invalid checkpoint options type {}",
+            opts.type_url
+        )));
+    }
+
+    let options = CheckpointOptions::parse_from_bytes(&opts.value)
+        .map_err(|err| Error::InvalidArgument(format!("invalid checkpoint options: {err}")))?;
+
+    let unsupported = [
+        ("open_tcp", options.open_tcp),
+        ("external_unix_sockets", options.external_unix_sockets),
+        ("terminal", options.terminal),
+        ("file_locks", options.file_locks),
+        ("empty_namespaces", !options.empty_namespaces.is_empty()),
+        ("cgroups_mode", !options.cgroups_mode.is_empty()),
+        ("work_path", !options.work_path.is_empty()),
+    ];
+    if let Some((name, _)) = unsupported.iter().find(|(_, set)| *set) {
+        return Err(Error::InvalidArgument(format!(
+            "checkpoint option {name} is not supported"
+        )));
+    }
+
+    Ok(options)
+}
+
+/// The event reporting why the task, or one of its exec processes, terminated.
+fn task_terminated(
+    container_id: impl Into<String>,
+    exec_id: impl Into<String>,
+    exit_status: u32,
+    termination: &Termination,
+) -> TaskTerminated {
+    TaskTerminated {
+        container_id: container_id.into(),
+        exec_id: exec_id.into(),
+        exit_status,
+        kind: termination.kind().to_string(),
+        message: termination.to_string(),
+        ..Default::default()
+    }
 }
 
 type LocalInstances<T> = RwLock<HashMap<String, Arc<InstanceData<T>>>>;
@@ -123,6 +343,19 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         instance.ok_or_else(|| Error::NotFound(id.to_string()))
     }
 
+    /// Lets operators know why a task was rejected, as the error alone may not be
+    /// surfaced to them, e.g., when the task is created by CRI.
+    fn rejected(&self, id: &str, err: Error) -> Error {
+        if let Error::PermissionDenied(reason) = &err {
+            self.events.send(TaskRejected {
+                container_id: id.to_string(),
+                reason: reason.clone(),
+                ..Default::default()
+            });
+        }
+        err
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     async fn has_instance(&self, id: &str) -> bool {
         self.instances.read().await.contains_key(id)
@@ -140,15 +373,25 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     async fn task_create(&self, req: CreateTaskRequest) -> Result<CreateTaskResponse> {
         let config = Config::get_from_options(req.options.as_ref())
             .map_err(|err| Error::InvalidArgument(format!("invalid shim options: {err}")))?;
+        config.set_log_level();
 
-        if !req.checkpoint().is_empty() || !req.parent_checkpoint().is_empty() {
-            return Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into());
+        if req.terminal {
+            config
+                .ensure_allowed(Feature::Terminal)
+                .map_err(|err| self.rejected(req.id(), err))?;
+        }
+        if !req.checkpoint().is_empty() {
+            config
+                .ensure_allowed(Feature::Checkpoint)
+                .map_err(|err| self.rejected(req.id(), err))?;
         }
 
-        if req.terminal {
-            return Err(Error::InvalidArgument(
-                "terminal is not supported".to_string(),
-            ));
+        if !req.parent_checkpoint().is_empty() {
+            // Checkpoints are not incremental, so the checkpoint alone is enough to restore the task
+            log::debug!(
+                "ignoring parent checkpoint {:?}, as checkpoints are complete",
+                req.parent_checkpoint()
+            );
         }
 
         if self.has_instance(&req.id).await {
@@ -185,6 +428,10 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             }
         }
 
+        let pty = req.terminal.then(Pty::open).transpose()?;
+
+        let hooks = ContainerHooks::new(req.id(), req.bundle(), &spec);
+
         let cfg = InstanceConfig {
             namespace: self.namespace.clone(),
             containerd_address: self.containerd_address.clone(),
@@ -192,11 +439,30 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             stdout: req.stdout.as_str().into(),
             stderr: req.stderr.as_str().into(),
             stdin: req.stdin.as_str().into(),
+            terminal: pty.as_ref().map(|pty| pty.path().to_path_buf()),
             config,
+            checkpoint: (!req.checkpoint().is_empty()).then(|| req.checkpoint().into()),
         };
 
         // Check if this is a cri container
-        let instance = InstanceData::new(req.id(), cfg).await?;
+        let instance = InstanceData::new(req.id(), cfg, pty, hooks)
+            .await
+            .map_err(|err| self.rejected(req.id(), err))?;
+
+        // Per the spec, the prestart, createRuntime and createContainer hooks must be
+        // called as part of the create operation, and the container must be deleted
+        // if any of them fails.
+        debug!("call hooks before the start");
+        for phase in [
+            HookPhase::Prestart,
+            HookPhase::CreateRuntime,
+            HookPhase::CreateContainer,
+        ] {
+            if let Err(err) = instance.run_hooks(phase).await {
+                let _ = instance.delete().await;
+                return Err(err);
+            }
+        }
 
         self.instances
             .write()
@@ -219,25 +485,63 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
         debug!("create done");
 
-        // Per the spec, the prestart hook must be called as part of the create operation
-        debug!("call prehook before the start");
-        oci::setup_prestart_hooks(spec.hooks())?;
-
         Ok(CreateTaskResponse {
             pid: std::process::id(),
             ..Default::default()
         })
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_exec(&self, req: ExecProcessRequest) -> Result<Empty> {
+        let i = self.get_instance(req.id()).await?;
+        i.config.config.ensure_allowed(Feature::Exec)?;
+        if req.terminal {
+            i.config.config.ensure_allowed(Feature::Terminal)?;
+        }
+
+        let spec = req
+            .spec
+            .as_ref()
+            .ok_or_else(|| Error::InvalidArgument("process spec is not set".to_string()))?;
+        let process: Process = serde_json::from_slice(&spec.value)
+            .map_err(|err| Error::InvalidArgument(format!("invalid process spec: {err}")))?;
+
+        let pty = req.terminal.then(Pty::open).transpose()?;
+
+        let cfg = ExecConfig {
+            stdin: req.stdin.as_str().into(),
+            stdout: req.stdout.as_str().into(),
+            stderr: req.stderr.as_str().into(),
+            terminal: pty.as_ref().map(|pty| pty.path().to_path_buf()),
+            process,
+        };
+
+        i.add_exec(req.exec_id(), cfg, pty).await?;
+
+        self.events.send(TaskExecAdded {
+            container_id: req.id,
+            exec_id: req.exec_id,
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     async fn task_start(&self, req: StartRequest) -> Result<StartResponse> {
-        if req.exec_id().is_empty().not() {
-            return Err(ShimError::Unimplemented("exec is not supported".to_string()).into());
+        if !req.exec_id().is_empty() {
+            return self.exec_start(req).await;
         }
 
         let i = self.get_instance(req.id()).await?;
+        i.run_hooks(HookPhase::StartContainer).await?;
         let pid = i.start().await?;
 
+        // Per the spec, a failing poststart hook must not fail the start operation
+        if let Err(err) = i.run_hooks(HookPhase::Poststart).await {
+            log::warn!("poststart hook failed: {err}");
+        }
+
         self.events.send(TaskStart {
             container_id: req.id().into(),
             pid,
@@ -255,9 +559,12 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
                 exit_status: exit_code,
                 exited_at: Some(timestamp.to_timestamp()).into(),
                 pid,
-                id,
+                id: id.clone(),
                 ..Default::default()
             });
+            if let Some(termination) = i.instance.termination().await {
+                events.send(task_terminated(id, "", exit_code, &termination));
+            }
         }
         .spawn();
 
@@ -269,10 +576,57 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         })
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn exec_start(&self, req: StartRequest) -> Result<StartResponse> {
+        let i = self.get_instance(req.id()).await?;
+        let pid = i.start_exec(req.exec_id()).await?;
+
+        self.events.send(TaskExecStarted {
+            container_id: req.id().into(),
+            exec_id: req.exec_id().into(),
+            pid,
+            ..Default::default()
+        });
+
+        let events = self.events.clone();
+
+        let container_id = req.id().to_string();
+        let id = req.exec_id().to_string();
+
+        async move {
+            let Ok((exit_code, timestamp)) = i.wait_exec(&id).await else {
+                return;
+            };
+            events.send(TaskExit {
+                container_id: container_id.clone(),
+                exit_status: exit_code,
+                exited_at: Some(timestamp.to_timestamp()).into(),
+                pid,
+                id: id.clone(),
+                ..Default::default()
+            });
+            if let Some(termination) = i.instance.exec_termination(&id).await {
+                events.send(task_terminated(container_id, id, exit_code, &termination));
+            }
+        }
+        .spawn();
+
+        debug!("exec started: {:?}", req);
+
+        Ok(StartResponse {
+            pid,
+            ..Default::default()
+        })
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     async fn task_kill(&self, req: KillRequest) -> Result<Empty> {
         if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+            self.get_instance(req.id())
+                .await?
+                .kill_exec(req.exec_id(), req.signal())
+                .await?;
+            return Ok(Empty::new());
         }
         self.get_instance(req.id())
             .await?
@@ -282,15 +636,122 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
-    async fn task_delete(&self, req: DeleteRequest) -> Result<DeleteResponse> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+    async fn task_resize_pty(&self, req: ResizePtyRequest) -> Result<Empty> {
+        self.get_instance(req.id())
+            .await?
+            .resize_pty(req.exec_id(), req.width, req.height)
+            .await?;
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_checkpoint(&self, req: CheckpointTaskRequest) -> Result<Empty> {
+        let options = checkpoint_options(req.options.as_ref())?;
+        let path = match options.image_path.as_str() {
+            "" => req.path(),
+            path => path,
+        };
+        if path.is_empty() {
+            return Err(Error::InvalidArgument(
+                "checkpoint path is not set".to_string(),
+            ));
+        }
+
+        let i = self.get_instance(req.id()).await?;
+        i.config.config.ensure_allowed(Feature::Checkpoint)?;
+
+        create_dir_all(path)?;
+        i.checkpoint(Path::new(path)).await?;
+
+        if options.exit {
+            i.kill(SIGKILL).await?;
         }
 
+        self.events.send(TaskCheckpointed {
+            container_id: req.id.clone(),
+            checkpoint: path.to_string(),
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_update(&self, req: UpdateTaskRequest) -> Result<Empty> {
+        let i = self.get_instance(req.id()).await?;
+        i.config.config.ensure_allowed(Feature::Update)?;
+
+        let resources = req
+            .resources
+            .as_ref()
+            .ok_or_else(|| Error::InvalidArgument("resources are not set".to_string()))?;
+        let resources: LinuxResources = serde_json::from_slice(&resources.value)
+            .map_err(|err| Error::InvalidArgument(format!("invalid resources: {err}")))?;
+
+        i.update(&resources).await?;
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_pause(&self, req: PauseRequest) -> Result<Empty> {
+        let i = self.get_instance(req.id()).await?;
+        i.config.config.ensure_allowed(Feature::Pause)?;
+        i.pause().await?;
+
+        self.events.send(TaskPaused {
+            container_id: req.id,
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_resume(&self, req: ResumeRequest) -> Result<Empty> {
         let i = self.get_instance(req.id()).await?;
+        i.config.config.ensure_allowed(Feature::Pause)?;
+        i.resume().await?;
+
+        self.events.send(TaskResumed {
+            container_id: req.id,
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn task_delete(&self, req: DeleteRequest) -> Result<DeleteResponse> {
+        let i = self.get_instance(req.id()).await?;
+
+        if !req.exec_id().is_empty() {
+            let pid = i.get_exec(req.exec_id()).await?.pid().unwrap_or_default();
+            let (exit_code, timestamp) = i
+                .wait_exec(req.exec_id())
+                .now_or_never()
+                .and_then(Result::ok)
+                .unzip();
+            let timestamp = timestamp.map(ToTimestamp::to_timestamp);
+
+            i.delete_exec(req.exec_id()).await?;
+
+            // containerd does not expect a TaskDelete event for exec processes
+            return Ok(DeleteResponse {
+                pid,
+                exit_status: exit_code.unwrap_or_default(),
+                exited_at: timestamp.into(),
+                ..Default::default()
+            });
+        }
 
         i.delete().await?;
 
+        // Per the spec, a failing poststop hook must not fail the delete operation
+        if let Err(err) = i.run_hooks(HookPhase::Poststop).await {
+            log::warn!("poststop hook failed: {err}");
+        }
+
         let pid = i.pid().unwrap_or_default();
         let (exit_code, timestamp) = i.wait().now_or_never().unzip();
         let timestamp = timestamp.map(ToTimestamp::to_timestamp);
@@ -315,12 +776,12 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     async fn task_wait(&self, req: WaitRequest) -> Result<WaitResponse> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
-        }
-
         let i = self.get_instance(req.id()).await?;
-        let (exit_code, timestamp) = i.wait().await;
+        let (exit_code, timestamp) = if req.exec_id().is_empty() {
+            i.wait().await
+        } else {
+            i.wait_exec(req.exec_id()).await?
+        };
 
         debug!("wait finishes");
         Ok(WaitResponse {
@@ -333,7 +794,7 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     async fn task_state(&self, req: StateRequest) -> Result<StateResponse> {
         if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+            return self.exec_state(req).await;
         }
 
         let i = self.get_instance(req.id()).await?;
@@ -343,10 +804,12 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
         let status = if pid.is_none() {
             Status::CREATED
-        } else if exit_code.is_none() {
-            Status::RUNNING
-        } else {
+        } else if exit_code.is_some() {
             Status::STOPPED
+        } else if i.is_paused().await {
+            Status::PAUSED
+        } else {
+            Status::RUNNING
         };
 
         Ok(StateResponse {
@@ -354,6 +817,42 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             stdin: i.config.stdin.to_string_lossy().to_string(),
             stdout: i.config.stdout.to_string_lossy().to_string(),
             stderr: i.config.stderr.to_string_lossy().to_string(),
+            terminal: i.config.terminal.is_some(),
+            pid: pid.unwrap_or_default(),
+            exit_status: exit_code.unwrap_or_default(),
+            exited_at: timestamp.into(),
+            status: status.into(),
+            ..Default::default()
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    async fn exec_state(&self, req: StateRequest) -> Result<StateResponse> {
+        let i = self.get_instance(req.id()).await?;
+        let exec = i.get_exec(req.exec_id()).await?;
+        let pid = exec.pid();
+        let (exit_code, timestamp) = i
+            .wait_exec(req.exec_id())
+            .now_or_never()
+            .and_then(Result::ok)
+            .unzip();
+        let timestamp = timestamp.map(ToTimestamp::to_timestamp);
+
+        let status = if pid.is_none() {
+            Status::CREATED
+        } else if exit_code.is_none() {
+            Status::RUNNING
+        } else {
+            Status::STOPPED
+        };
+
+        Ok(StateResponse {
+            id: req.exec_id.clone(),
+            bundle: i.config.bundle.to_string_lossy().to_string(),
+            stdin: exec.config.stdin.to_string_lossy().to_string(),
+            stdout: exec.config.stdout.to_string_lossy().to_string(),
+            stderr: exec.config.stderr.to_string_lossy().to_string(),
+            terminal: exec.config.terminal.is_some(),
             pid: pid.unwrap_or_default(),
             exit_status: exit_code.unwrap_or_default(),
             exited_at: timestamp.into(),
@@ -393,6 +892,16 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         Ok(self.task_create(req).block_on()?)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn exec(&self, _ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        debug!("exec: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_exec(req).block_on()?)
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     fn start(&self, _ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
         debug!("start: {:?}", req);
@@ -413,6 +922,56 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         Ok(self.task_kill(req).block_on()?)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn resize_pty(&self, _ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        debug!("resize_pty: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_resize_pty(req).block_on()?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn checkpoint(&self, _ctx: &TtrpcContext, req: CheckpointTaskRequest) -> TtrpcResult<Empty> {
+        debug!("checkpoint: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_checkpoint(req).block_on()?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn update(&self, _ctx: &TtrpcContext, req: UpdateTaskRequest) -> TtrpcResult<Empty> {
+        debug!("update: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_update(req).block_on()?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn pause(&self, _ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        debug!("pause: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_pause(req).block_on()?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn resume(&self, _ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        debug!("resume: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        Ok(self.task_resume(req).block_on()?)
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     fn delete(&self, _ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
         debug!("delete: {:?}", req);
@@ -1,10 +1,12 @@
 use std::fs::{File, create_dir};
+use std::sync::Mutex;
 use std::time::Duration;
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
 use containerd_shim::api::Status;
 use containerd_shim::event::Event;
+use oci_spec::runtime::{HookBuilder, HooksBuilder, SpecBuilder};
 use protobuf::{MessageDyn, SpecialFields};
 use serde_json as json;
 use tempfile::tempdir;
@@ -14,33 +16,122 @@ use tokio_async_drop::tokio_async_drop;
 use super::*;
 use crate::sandbox::shim::events::EventSender;
 use crate::sandbox::sync::WaitableCell;
+use crate::sandbox::termination::Termination;
+
+type ExitCode = WaitableCell<(u32, DateTime<Utc>)>;
 
 /// This is used for the tests and is a no-op instance implementation.
 pub struct InstanceStub {
     /// Since we are faking the container, we need to keep track of the "exit" code/time
     /// We'll just mark it as exited when kill is called.
-    exit_code: WaitableCell<(u32, DateTime<Utc>)>,
+    exit_code: ExitCode,
+    /// Same as `exit_code`, but for each of the exec'd processes.
+    exec_exit_codes: Mutex<HashMap<String, ExitCode>>,
+    /// The signal the instance was killed with.
+    signal: Mutex<Option<i32>>,
+    /// Same as `signal`, but for each of the exec'd processes.
+    exec_signals: Mutex<HashMap<String, i32>>,
+}
+
+impl InstanceStub {
+    fn exec_exit_code(&self, exec_id: &str) -> ExitCode {
+        let mut exec_exit_codes = self.exec_exit_codes.lock().unwrap();
+        exec_exit_codes
+            .entry(exec_id.to_string())
+            .or_default()
+            .clone()
+    }
 }
 
 impl Instance for InstanceStub {
     async fn new(_id: String, _cfg: &InstanceConfig) -> Result<Self, Error> {
         Ok(InstanceStub {
             exit_code: WaitableCell::new(),
+            exec_exit_codes: Mutex::default(),
+            signal: Mutex::default(),
+            exec_signals: Mutex::default(),
         })
     }
+    fn pid(&self) -> Option<u32> {
+        Some(std::process::id())
+    }
     async fn start(&self) -> Result<u32, Error> {
         Ok(std::process::id())
     }
-    async fn kill(&self, _signal: u32) -> Result<(), Error> {
+    async fn kill(&self, signal: u32) -> Result<(), Error> {
+        self.signal.lock().unwrap().get_or_insert(signal as i32);
         let _ = self.exit_code.set((1, Utc::now()));
         Ok(())
     }
+    async fn checkpoint(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path.join("checkpoint"), "")?;
+        Ok(())
+    }
+    async fn update(&self, _pid: Option<u32>, _resources: &LinuxResources) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn pause(&self, _pid: u32) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn resume(&self, _pid: u32) -> Result<(), Error> {
+        Ok(())
+    }
     async fn delete(&self) -> Result<(), Error> {
         Ok(())
     }
     async fn wait(&self) -> (u32, DateTime<Utc>) {
         *self.exit_code.wait().await
     }
+    async fn termination(&self) -> Option<Termination> {
+        let signal = (*self.signal.lock().unwrap())?;
+        Some(Termination::Signaled { signal })
+    }
+    async fn exec(&self, exec_id: &str, _cfg: &ExecConfig) -> Result<u32, Error> {
+        let _ = self.exec_exit_code(exec_id);
+        Ok(std::process::id())
+    }
+    async fn kill_exec(&self, exec_id: &str, signal: u32) -> Result<(), Error> {
+        let mut exec_signals = self.exec_signals.lock().unwrap();
+        exec_signals
+            .entry(exec_id.to_string())
+            .or_insert(signal as i32);
+        let _ = self.exec_exit_code(exec_id).set((1, Utc::now()));
+        Ok(())
+    }
+    async fn delete_exec(&self, exec_id: &str) -> Result<(), Error> {
+        self.exec_exit_codes.lock().unwrap().remove(exec_id);
+        Ok(())
+    }
+    async fn wait_exec(&self, exec_id: &str) -> (u32, DateTime<Utc>) {
+        *self.exec_exit_code(exec_id).wait().await
+    }
+    async fn exec_termination(&self, exec_id: &str) -> Option<Termination> {
+        let signal = *self.exec_signals.lock().unwrap().get(exec_id)?;
+        Some(Termination::Signaled { signal })
+    }
+}
+
+/// An instance that the shim policy always rejects.
+pub struct RejectedInstanceStub;
+
+impl Instance for RejectedInstanceStub {
+    async fn new(_id: String, _cfg: &InstanceConfig) -> Result<Self, Error> {
+        Err(Error::PermissionDenied(
+            "native binaries are forbidden".to_string(),
+        ))
+    }
+    async fn start(&self) -> Result<u32, Error> {
+        unreachable!()
+    }
+    async fn kill(&self, _signal: u32) -> Result<(), Error> {
+        unreachable!()
+    }
+    async fn delete(&self) -> Result<(), Error> {
+        unreachable!()
+    }
+    async fn wait(&self) -> (u32, DateTime<Utc>) {
+        unreachable!()
+    }
 }
 
 struct LocalWithDestructor<T: Instance + Send + Sync, E: EventSender> {
@@ -95,6 +186,65 @@ fn create_bundle(dir: &std::path::Path, spec: Option<Spec>) -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_task_terminated() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_kill(KillRequest {
+            id: "test".to_string(),
+            signal: 9,
+            ..Default::default()
+        })
+        .await?;
+
+    loop {
+        let Some(Some((topic, event))) = erx.recv().with_timeout(Duration::from_secs(5)).await
+        else {
+            panic!("no TaskTerminated event");
+        };
+        if topic != "/runwasi/tasks/terminated" {
+            continue;
+        }
+        let event = event.downcast_box::<TaskTerminated>().unwrap();
+        assert_eq!(event.container_id, "test");
+        assert_eq!(event.exit_status, 1);
+        assert_eq!(event.kind, "signaled");
+        assert_eq!(event.message, "killed by signal 9");
+        break;
+    }
+
+    Ok(())
+}
+
 // Use a multi threaded runtime because LocalWithDestructor needs
 // it to run its async drop.
 #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
@@ -494,37 +644,989 @@ async fn test_task_lifecycle() -> Result<()> {
     Ok(())
 }
 
-#[test]
-fn test_default_runtime_options() -> Result<()> {
-    let options: Option<&Any> = None;
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_exec_lifecycle() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
 
-    let config = Config::get_from_options(options).unwrap();
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
 
-    assert_eq!(config.systemd_cgroup, false);
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
 
-    Ok(())
-}
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
 
-#[test]
-fn test_custom_runtime_options() -> Result<()> {
-    let options = Options {
-        type_url: "runtimeoptions.v1.Options".to_string(),
-        config_path: "".to_string(),
-        config_body: "SystemdCgroup = true\n".to_string(),
-    };
-    let req = CreateTaskRequest {
-        options: Some(Any {
-            type_url: options.type_url.clone(),
-            value: options.encode_to_vec(),
-            special_fields: SpecialFields::default(),
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let process = json::to_vec(&oci_spec::runtime::Process::default()).unwrap();
+    let exec_req = ExecProcessRequest {
+        id: "test".to_string(),
+        exec_id: "exec".to_string(),
+        spec: Some(Any {
+            type_url: "types.containerd.io/opencontainers/runtime-spec/1/Process".to_string(),
+            value: process,
+            ..Default::default()
         })
         .into(),
         ..Default::default()
     };
 
-    let config = Config::get_from_options(req.options.as_ref()).unwrap();
+    local.task_exec(exec_req.clone()).await?;
 
-    assert_eq!(config.systemd_cgroup, true);
+    match local.task_exec(exec_req).await.unwrap_err() {
+        Error::AlreadyExists(_) => {}
+        e => return Err(e),
+    }
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::CREATED);
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::RUNNING);
+
+    local
+        .task_kill(KillRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            signal: 9,
+            ..Default::default()
+        })
+        .await?;
+
+    let res = local
+        .task_wait(WaitRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .with_timeout(Duration::from_secs(5))
+        .await
+        .unwrap()?;
+    assert_eq!(res.exit_status, 1);
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::STOPPED);
+
+    // containerd only deletes the process once it got its exit event
+    let mut topics = vec![];
+    let mut termination = None;
+    while topics.len() < 6 {
+        let Some(Some((topic, event))) = erx.recv().with_timeout(Duration::from_secs(5)).await
+        else {
+            break;
+        };
+        if topic == "/runwasi/tasks/terminated" {
+            termination = event.downcast_box::<TaskTerminated>().ok();
+        }
+        topics.push(topic);
+    }
+    assert_eq!(
+        topics,
+        [
+            "/tasks/create",
+            "/tasks/start",
+            "/tasks/exec-added",
+            "/tasks/exec-started",
+            "/tasks/exit",
+            "/runwasi/tasks/terminated",
+        ]
+    );
+    let termination = termination.unwrap();
+    assert_eq!(termination.container_id, "test");
+    assert_eq!(termination.exec_id, "exec");
+    assert_eq!(termination.kind, "signaled");
+
+    local
+        .task_delete(DeleteRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    match local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::NotFound(_) => {}
+        e => return Err(e),
+    }
+
+    // the init process is not affected by the exec'd process
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::RUNNING);
 
     Ok(())
 }
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_pause_resume() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    // a task that has not started can't be paused
+    match local
+        .task_pause(PauseRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_pause(PauseRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::PAUSED);
+
+    local
+        .task_resume(ResumeRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert_eq!(state.status(), Status::RUNNING);
+
+    // a running task can't be resumed
+    match local
+        .task_resume(ResumeRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    let mut topics = vec![];
+    while let Ok((topic, _)) = erx.try_recv() {
+        topics.push(topic);
+    }
+    assert_eq!(
+        topics,
+        [
+            "/tasks/create",
+            "/tasks/start",
+            "/tasks/paused",
+            "/tasks/resumed",
+        ]
+    );
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_update() -> Result<()> {
+    let (etx, _erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let resources = json::json!({ "memory": { "limit": 134217728 } });
+    local
+        .task_update(UpdateTaskRequest {
+            id: "test".to_string(),
+            resources: Some(Any {
+                type_url: "types.containerd.io/opencontainers/runtime-spec/1/LinuxResources"
+                    .to_string(),
+                value: json::to_vec(&resources).unwrap(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        })
+        .await?;
+
+    match local
+        .task_update(UpdateTaskRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::InvalidArgument(_) => {}
+        e => return Err(e),
+    }
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_checkpoint() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path().join("bundle");
+    create_dir(&dir)?;
+    create_bundle(&dir, None)?;
+    let checkpoint = temp.path().join("checkpoint");
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    // a task that has not started can't be checkpointed
+    match local
+        .task_checkpoint(CheckpointTaskRequest {
+            id: "test".to_string(),
+            path: checkpoint.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_checkpoint(CheckpointTaskRequest {
+            id: "test".to_string(),
+            path: checkpoint.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert!(checkpoint.join("checkpoint").exists());
+
+    let mut topics = vec![];
+    while let Ok((topic, _)) = erx.try_recv() {
+        topics.push(topic);
+    }
+    assert_eq!(
+        topics,
+        ["/tasks/create", "/tasks/start", "/tasks/checkpointed"]
+    );
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_checkpoint_options() -> Result<()> {
+    let (etx, _erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path().join("bundle");
+    create_dir(&dir)?;
+    create_bundle(&dir, None)?;
+    let image = temp.path().join("image");
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let options = |options: CheckpointOptions| Any {
+        type_url: "containerd.runc.v1.CheckpointOptions".to_string(),
+        value: options.write_to_bytes().unwrap(),
+        ..Default::default()
+    };
+
+    // options that only make sense for CRIU are rejected
+    match local
+        .task_checkpoint(CheckpointTaskRequest {
+            id: "test".to_string(),
+            path: temp.path().join("checkpoint").to_str().unwrap().to_string(),
+            options: Some(options(CheckpointOptions {
+                open_tcp: true,
+                ..Default::default()
+            }))
+            .into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::InvalidArgument(_) => {}
+        e => return Err(e),
+    }
+
+    // the image path overrides the path of the request, and the task exits after the checkpoint
+    local
+        .task_checkpoint(CheckpointTaskRequest {
+            id: "test".to_string(),
+            path: temp.path().join("checkpoint").to_str().unwrap().to_string(),
+            options: Some(options(CheckpointOptions {
+                exit: true,
+                image_path: image.to_str().unwrap().to_string(),
+                ..Default::default()
+            }))
+            .into(),
+            ..Default::default()
+        })
+        .await?;
+    assert!(image.join("checkpoint").exists());
+
+    let instance = local.get_instance("test").await?;
+    assert_eq!(*instance.instance.signal.lock().unwrap(), Some(9));
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_terminal() -> Result<()> {
+    let (etx, _erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            terminal: true,
+            ..Default::default()
+        })
+        .await?;
+
+    let instance = local.get_instance("test").await?;
+    let terminal = instance.config.terminal.as_ref().unwrap();
+    assert!(terminal.starts_with("/dev/pts"));
+
+    local
+        .task_resize_pty(ResizePtyRequest {
+            id: "test".to_string(),
+            width: 80,
+            height: 24,
+            ..Default::default()
+        })
+        .await?;
+
+    let state = local
+        .task_state(StateRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+    assert!(state.terminal);
+
+    local
+        .task_exec(ExecProcessRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            spec: Some(Any {
+                type_url: "types.containerd.io/opencontainers/runtime-spec/1/Process".to_string(),
+                value: json::to_vec(&Process::default()).unwrap(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        })
+        .await?;
+
+    // the exec'd process doesn't have a terminal
+    match local
+        .task_resize_pty(ResizePtyRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            width: 80,
+            height: 24,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_hooks() -> Result<()> {
+    let (etx, _erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    let log = dir.join("hooks.log");
+
+    // each hook logs its phase and the pid in the state it gets
+    let hook = |phase: &str| {
+        let script = format!(
+            "echo {phase} $(grep -o '\"pid\":[0-9]*') >> {}",
+            log.display()
+        );
+        HookBuilder::default()
+            .path("/bin/sh")
+            .args(vec!["sh".to_string(), "-c".to_string(), script])
+            .build()
+            .unwrap()
+    };
+    let hooks = HooksBuilder::default()
+        .prestart(vec![hook("prestart")])
+        .create_runtime(vec![hook("createRuntime")])
+        .create_container(vec![hook("createContainer")])
+        .start_container(vec![hook("startContainer")])
+        .poststart(vec![hook("poststart")])
+        .poststop(vec![hook("poststop")])
+        .build()?;
+    create_bundle(dir, Some(SpecBuilder::default().hooks(hooks).build()?))?;
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_kill(KillRequest {
+            id: "test".to_string(),
+            signal: 9,
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_wait(WaitRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_delete(DeleteRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    let phases = std::fs::read_to_string(log)?;
+    let pid = std::process::id();
+    assert_eq!(
+        phases.lines().collect::<Vec<_>>(),
+        [
+            "prestart",
+            "createRuntime",
+            "createContainer",
+            "startContainer",
+            "poststart",
+            "poststop"
+        ]
+        .map(|phase| format!("{phase} \"pid\":{pid}"))
+    );
+
+    Ok(())
+}
+
+// Use a multi threaded runtime because LocalWithDestructor needs
+// it to run its async drop.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_failing_create_hook() -> Result<()> {
+    let (etx, _erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+
+    let hooks = HooksBuilder::default()
+        .create_runtime(vec![HookBuilder::default().path("/bin/false").build()?])
+        .build()?;
+    create_bundle(dir, Some(SpecBuilder::default().hooks(hooks).build()?))?;
+
+    match local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+    assert!(!local.has_instance("test").await);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rejected_create() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<RejectedInstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    match local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::PermissionDenied(_) => {}
+        e => return Err(e),
+    }
+    assert!(!local.has_instance("test").await);
+
+    let (topic, event) = erx.try_recv().unwrap();
+    assert_eq!(topic, "/runwasi/tasks/rejected");
+    let event = event.downcast_box::<TaskRejected>().unwrap();
+    assert_eq!(event.container_id, "test");
+    assert_eq!(event.reason, "native binaries are forbidden");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_allowed_features() -> Result<()> {
+    let (etx, mut erx) = channel();
+    let exit_signal = WaitableCell::new();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    let dir = temp.path();
+    create_bundle(dir, None)?;
+
+    let options = Options {
+        type_url: "runtimeoptions.v1.Options".to_string(),
+        config_path: "".to_string(),
+        config_body: "AllowedFeatures = [\"pause\"]\n".to_string(),
+    };
+    let options = Any {
+        type_url: options.type_url.clone(),
+        value: options.encode_to_vec(),
+        special_fields: SpecialFields::default(),
+    };
+
+    // terminals are not allowed, so the task is rejected
+    match local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            terminal: true,
+            options: Some(options.clone()).into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::PermissionDenied(_) => {}
+        e => return Err(e),
+    }
+    assert!(!local.has_instance("test").await);
+
+    let (topic, event) = erx.try_recv().unwrap();
+    assert_eq!(topic, "/runwasi/tasks/rejected");
+    let event = event.downcast_box::<TaskRejected>().unwrap();
+    assert_eq!(event.reason, "the terminal feature is not allowed");
+
+    local
+        .task_create(CreateTaskRequest {
+            id: "test".to_string(),
+            bundle: dir.to_str().unwrap().to_string(),
+            options: Some(options).into(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_start(StartRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    local
+        .task_pause(PauseRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await?;
+
+    match local
+        .task_update(UpdateTaskRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::PermissionDenied(_) => {}
+        e => return Err(e),
+    }
+
+    match local
+        .task_exec(ExecProcessRequest {
+            id: "test".to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err()
+    {
+        Error::PermissionDenied(_) => {}
+        e => return Err(e),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_default_runtime_options() -> Result<()> {
+    let options: Option<&Any> = None;
+
+    let config = Config::get_from_options(options).unwrap();
+
+    assert_eq!(config.systemd_cgroup, false);
+
+    Ok(())
+}
+
+#[test]
+fn test_custom_runtime_options() -> Result<()> {
+    let options = Options {
+        type_url: "runtimeoptions.v1.Options".to_string(),
+        config_path: "".to_string(),
+        config_body: "SystemdCgroup = true\n".to_string(),
+    };
+    let req = CreateTaskRequest {
+        options: Some(Any {
+            type_url: options.type_url.clone(),
+            value: options.encode_to_vec(),
+            special_fields: SpecialFields::default(),
+        })
+        .into(),
+        ..Default::default()
+    };
+
+    let config = Config::get_from_options(req.options.as_ref()).unwrap();
+
+    assert_eq!(config.systemd_cgroup, true);
+
+    Ok(())
+}
+
+#[test]
+fn test_in_process_runtime_options() -> Result<()> {
+    let options = Options {
+        type_url: "runtimeoptions.v1.Options".to_string(),
+        config_path: "".to_string(),
+        config_body: "InProcess = true\nShimCgroup = \"/kubepods/pod1\"\n".to_string(),
+    };
+    let options = Any {
+        type_url: options.type_url.clone(),
+        value: options.encode_to_vec(),
+        special_fields: SpecialFields::default(),
+    };
+
+    let config = Config::get_from_options(Some(&options)).unwrap();
+
+    assert!(config.in_process);
+    assert_eq!(config.shim_cgroup, "/kubepods/pod1");
+    assert!(!config.systemd_cgroup);
+
+    Ok(())
+}
+
+#[test]
+fn test_shim_runtime_options() -> Result<()> {
+    let options = Options {
+        type_url: "runtimeoptions.v1.Options".to_string(),
+        config_path: "".to_string(),
+        config_body: r#"
+            LogLevel = "debug"
+            DisablePrecompilation = true
+            Root = "/run/runwasi"
+            AllowedFeatures = ["exec", "in-process"]
+            CoreDumpOnTrap = true
+            CoreDumpDir = "/var/lib/runwasi/coredumps"
+
+            [Engine]
+            cache = true
+            threads = 4
+        "#
+        .to_string(),
+    };
+    let options = Any {
+        type_url: options.type_url.clone(),
+        value: options.encode_to_vec(),
+        special_fields: SpecialFields::default(),
+    };
+
+    let config = Config::get_from_options(Some(&options)).unwrap();
+
+    assert_eq!(config.log_level.as_deref(), Some("debug"));
+    assert!(config.disable_precompilation);
+    assert_eq!(config.root, Some(PathBuf::from("/run/runwasi")));
+    assert!(config.allows(Feature::Exec));
+    assert!(config.allows(Feature::InProcess));
+    assert!(!config.allows(Feature::Pause));
+    assert!(config.core_dump_on_trap);
+    assert_eq!(
+        config.core_dump_dir,
+        Some(PathBuf::from("/var/lib/runwasi/coredumps"))
+    );
+
+    #[derive(Deserialize)]
+    struct EngineConfig {
+        cache: bool,
+        threads: u32,
+    }
+    let engine: EngineConfig = config.engine.get()?;
+    assert!(engine.cache);
+    assert_eq!(engine.threads, 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_runtime_options() {
+    for config_body in [
+        "LogLevel = \"loud\"\n",
+        "Root = \"relative/path\"\n",
+        "CoreDumpDir = \"coredumps\"\n",
+        "AllowedFeatures = [\"teleport\"]\n",
+        "InProcess = true\nAllowedFeatures = [\"exec\"]\n",
+    ] {
+        let options = Options {
+            type_url: "runtimeoptions.v1.Options".to_string(),
+            config_path: "".to_string(),
+            config_body: config_body.to_string(),
+        };
+        let options = Any {
+            type_url: options.type_url.clone(),
+            value: options.encode_to_vec(),
+            special_fields: SpecialFields::default(),
+        };
+
+        assert!(
+            Config::get_from_options(Some(&options)).is_err(),
+            "{config_body:?} should be invalid"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 //! The shim exposes the [Config] struct to configure the shim and [OtlpConfig] module to enable tracing if the `opentelemetry` feature is enabled.
 
-pub use local::Config;
+pub use events::{TaskRejected, TaskTerminated};
+pub use local::{Config, EngineOptions, Feature};
 
 mod events;
 mod instance_data;
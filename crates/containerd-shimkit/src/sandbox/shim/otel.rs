@@ -1,7 +1,7 @@
 //! OpenTelemetry Configuration Module
 //!
 //! This module provides a configuration structure and associated methods to initialize
-//! OpenTelemetry tracing with the OTLP exporter. The configuration can be set up via
+//! OpenTelemetry tracing and metrics with the OTLP exporter. The configuration can be set up via
 //! the `Config` struct and its builder pattern.
 //!
 //! # Usage
@@ -30,12 +30,15 @@ use opentelemetry::Context;
 use opentelemetry::global::{self, set_text_map_propagator};
 use opentelemetry::propagation::Extractor;
 use opentelemetry::trace::TraceError;
-pub use opentelemetry_otlp::{
-    OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_PROTOCOL, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
-};
 use opentelemetry_otlp::{
-    OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT, Protocol, SpanExporterBuilder, WithExportConfig,
+    MetricsExporterBuilder, OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT, Protocol, SpanExporterBuilder,
+    WithExportConfig,
+};
+pub use opentelemetry_otlp::{
+    OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_METRICS_ENDPOINT, OTEL_EXPORTER_OTLP_PROTOCOL,
+    OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
 };
+use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::propagation::TraceContextPropagator;
 use opentelemetry_sdk::{runtime, trace as sdktrace};
 use tracing::span::{Attributes, Id};
@@ -48,12 +51,16 @@ const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_JSON: &str = "http/json";
 const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_PROTOBUF: &str = "http/protobuf";
 const OTEL_EXPORTER_OTLP_PROTOCOL_GRPC: &str = "grpc";
 const OTEL_EXPORTER_OTLP_TRACES_PROTOCOL: &str = "OTEL_EXPORTER_OTLP_TRACES_PROTOCOL";
+const OTEL_EXPORTER_OTLP_METRICS_PROTOCOL: &str = "OTEL_EXPORTER_OTLP_METRICS_PROTOCOL";
 const OTEL_SDK_DISABLED: &str = "OTEL_SDK_DISABLED";
 
 /// Configuration struct for OpenTelemetry setup.
 pub struct Config {
     traces_endpoint: String,
     traces_protocol: Protocol,
+    /// Metrics are only exported if an endpoint is set for them.
+    metrics_endpoint: Option<String>,
+    metrics_protocol: Protocol,
 }
 
 /// Returns `true` if traces are enabled, `false` otherwise.
@@ -79,16 +86,26 @@ impl Config {
     pub fn build_from_env() -> anyhow::Result<Self> {
         let traces_endpoint = traces_endpoint_from_env()?;
         let traces_protocol: Protocol = traces_protocol_from_env()?;
+        let metrics_endpoint = metrics_endpoint_from_env();
+        let metrics_protocol = metrics_protocol_from_env()?;
         Ok(Self {
             traces_endpoint,
             traces_protocol,
+            metrics_endpoint,
+            metrics_protocol,
         })
     }
 
     /// Initializes the tracer, sets up the telemetry and subscriber layers, and sets the global subscriber.
+    /// If metrics are enabled, this also sets the global meter provider.
     ///
-    /// Note: this function should be called only once and be called by the binary entry point.
-    pub fn init(&self) -> anyhow::Result<impl Drop> {
+    /// Note: this function should be called only once per process.
+    pub fn init(&self) -> anyhow::Result<impl Drop + use<>> {
+        let meter_provider = self.init_meter_provider()?;
+        if let Some(meter_provider) = &meter_provider {
+            global::set_meter_provider(meter_provider.clone());
+        }
+
         let tracer = self.init_tracer()?;
         let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
         set_text_map_propagator(TraceContextPropagator::new());
@@ -101,7 +118,7 @@ impl Config {
             .with(SpanNamingLayer);
 
         tracing::subscriber::set_global_default(subscriber)?;
-        Ok(ShutdownGuard)
+        Ok(ShutdownGuard { meter_provider })
     }
 
     /// Returns the current trace context as a JSON string.
@@ -137,6 +154,28 @@ impl Config {
             .into()
     }
 
+    fn init_meter_provider(&self) -> anyhow::Result<Option<SdkMeterProvider>> {
+        let Some(endpoint) = &self.metrics_endpoint else {
+            return Ok(None);
+        };
+        let exporter: MetricsExporterBuilder = match self.metrics_protocol {
+            Protocol::HttpBinary | Protocol::HttpJson => opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint)
+                .into(),
+            Protocol::Grpc => opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint)
+                .into(),
+        };
+
+        let meter_provider = opentelemetry_otlp::new_pipeline()
+            .metrics(runtime::Tokio)
+            .with_exporter(exporter)
+            .build()?;
+        Ok(Some(meter_provider))
+    }
+
     fn init_tracer(&self) -> Result<opentelemetry_sdk::trace::Tracer, TraceError> {
         let exporter = match self.traces_protocol {
             Protocol::HttpBinary => self.init_tracer_http(),
@@ -154,12 +193,18 @@ impl Config {
 
 /// Shutdown of the open telemetry services will automatically called when the OtelConfig instance goes out of scope.
 #[must_use]
-struct ShutdownGuard;
+struct ShutdownGuard {
+    meter_provider: Option<SdkMeterProvider>,
+}
 
 impl Drop for ShutdownGuard {
     fn drop(&mut self) {
         // Give tracer provider a chance to flush any pending traces.
         opentelemetry::global::shutdown_tracer_provider();
+        // Export the metrics recorded since the last export.
+        if let Some(meter_provider) = self.meter_provider.take() {
+            let _ = meter_provider.shutdown();
+        }
     }
 }
 
@@ -169,13 +214,32 @@ fn traces_endpoint_from_env() -> anyhow::Result<String> {
         .or_else(|_| env::var(OTEL_EXPORTER_OTLP_ENDPOINT))?)
 }
 
+/// Sets the OTLP metrics endpoint from environment variables, if any.
+fn metrics_endpoint_from_env() -> Option<String> {
+    env::var(OTEL_EXPORTER_OTLP_METRICS_ENDPOINT)
+        .or_else(|_| env::var(OTEL_EXPORTER_OTLP_ENDPOINT))
+        .ok()
+        .filter(|endpoint| !endpoint.is_empty())
+}
+
 /// Sets the OTLP protocol from environment variables.
 fn traces_protocol_from_env() -> anyhow::Result<Protocol> {
-    let traces_protocol = env::var(OTEL_EXPORTER_OTLP_TRACES_PROTOCOL).unwrap_or(
+    protocol_from_env(OTEL_EXPORTER_OTLP_TRACES_PROTOCOL)
+}
+
+/// Sets the OTLP metrics protocol from environment variables.
+fn metrics_protocol_from_env() -> anyhow::Result<Protocol> {
+    protocol_from_env(OTEL_EXPORTER_OTLP_METRICS_PROTOCOL)
+}
+
+/// Reads the protocol of a signal from the `signal_protocol` environment variable,
+/// falling back to `OTEL_EXPORTER_OTLP_PROTOCOL`.
+fn protocol_from_env(signal_protocol: &str) -> anyhow::Result<Protocol> {
+    let signal_protocol = env::var(signal_protocol).unwrap_or(
         env::var(OTEL_EXPORTER_OTLP_PROTOCOL)
             .unwrap_or(OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT.to_owned()),
     );
-    let protocol = match traces_protocol.as_str() {
+    let protocol = match signal_protocol.as_str() {
         OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_PROTOBUF => Protocol::HttpBinary,
         OTEL_EXPORTER_OTLP_PROTOCOL_GRPC => Protocol::Grpc,
         OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_JSON => Protocol::HttpJson,
@@ -389,6 +453,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_metrics_endpoint_from_env() {
+        with_vars(
+            [
+                (
+                    OTEL_EXPORTER_OTLP_METRICS_ENDPOINT,
+                    Some("metrics_endpoint"),
+                ),
+                (OTEL_EXPORTER_OTLP_ENDPOINT, Some("general_endpoint")),
+            ],
+            || {
+                assert_eq!(
+                    metrics_endpoint_from_env().as_deref(),
+                    Some("metrics_endpoint")
+                );
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_METRICS_ENDPOINT, None),
+                (OTEL_EXPORTER_OTLP_ENDPOINT, Some("general_endpoint")),
+            ],
+            || {
+                assert_eq!(
+                    metrics_endpoint_from_env().as_deref(),
+                    Some("general_endpoint")
+                );
+            },
+        );
+
+        // metrics are not exported to the endpoint of the traces
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_TRACES_ENDPOINT, Some("trace_endpoint")),
+                (OTEL_EXPORTER_OTLP_METRICS_ENDPOINT, None),
+                (OTEL_EXPORTER_OTLP_ENDPOINT, None),
+            ],
+            || {
+                assert_eq!(metrics_endpoint_from_env(), None);
+            },
+        );
+    }
+
+    #[test]
+    fn test_otel_metrics_protocol_from_env() {
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_METRICS_PROTOCOL, Some("grpc")),
+                (OTEL_EXPORTER_OTLP_PROTOCOL, Some("http/json")),
+            ],
+            || {
+                assert_eq!(metrics_protocol_from_env().unwrap(), Protocol::Grpc);
+                assert_eq!(traces_protocol_from_env().unwrap(), Protocol::HttpJson);
+            },
+        );
+    }
+
     #[test]
     fn test_otel_protocol_from_env_default() {
         with_vars::<String, &str, _, _>([], || {
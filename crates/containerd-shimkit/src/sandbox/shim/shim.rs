@@ -71,7 +71,12 @@ where
             .and_then(|a| a.get("io.kubernetes.cri.sandbox-id"))
             .unwrap_or(&id);
 
-        let (_child, address) = shim::spawn(opts, grouping, vec![])?;
+        let (child, address) = shim::spawn(opts, grouping, vec![])?;
+
+        #[cfg(target_os = "linux")]
+        join_shim_cgroup(child)?;
+        #[cfg(not(target_os = "linux"))]
+        let _ = child;
 
         write_address(&address)?;
 
@@ -102,3 +107,39 @@ where
         })
     }
 }
+
+/// Moves a newly spawned shim to the `ShimCgroup` of the runtime options,
+/// which containerd passes on the stdin of the `start` command.
+#[cfg(target_os = "linux")]
+fn join_shim_cgroup(pid: u32) -> shim::Result<()> {
+    use std::io::Read as _;
+
+    use containerd_shim::cgroup::add_task_to_cgroup;
+    use protobuf::Message as _;
+    use protobuf::well_known_types::any::Any;
+
+    use crate::sandbox::shim::local::Config;
+
+    // the shim was already running for this grouping
+    if pid == 0 {
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut data)
+        .map_err(|err| ShimError::Other(format!("failed to read the runtime options: {err}")))?;
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let options = Any::parse_from_bytes(&data)
+        .map_err(|err| ShimError::InvalidArgument(format!("invalid runtime options: {err}")))?;
+    let config = Config::get_from_options(Some(&options))
+        .map_err(|err| ShimError::InvalidArgument(format!("invalid runtime options: {err}")))?;
+    if !config.shim_cgroup.is_empty() {
+        add_task_to_cgroup(&config.shim_cgroup, pid)?;
+    }
+
+    Ok(())
+}
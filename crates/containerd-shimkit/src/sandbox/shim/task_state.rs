@@ -6,6 +6,7 @@ pub(super) enum TaskState {
     Created,
     Starting,
     Started,
+    Paused,
     Exited,
     Deleting,
 }
@@ -24,11 +25,48 @@ impl TaskState {
     pub fn kill(&mut self) -> Result<()> {
         *self = match self {
             Self::Started => Ok(Self::Started),
+            Self::Paused => Ok(Self::Paused),
             _ => state_transition_error(*self, "Killing"),
         }?;
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub fn checkpoint(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Started => Ok(Self::Started),
+            _ => state_transition_error(*self, "Checkpointing"),
+        }?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub fn update(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Created | Self::Started | Self::Paused => Ok(*self),
+            _ => state_transition_error(*self, "Updating"),
+        }?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub fn pause(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Started => Ok(Self::Paused),
+            _ => state_transition_error(*self, Self::Paused),
+        }?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub fn resume(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Paused => Ok(Self::Started),
+            _ => state_transition_error(*self, Self::Started),
+        }?;
+        Ok(())
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
     pub fn delete(&mut self) -> Result<()> {
         *self = match self {
@@ -0,0 +1,631 @@
+//! Telemetry of the container processes, exported by the shim.
+//!
+//! Container processes are forked before the shim sets up OpenTelemetry, and they don't
+//! set up exporters of their own. Instead:
+//!
+//! - The shim passes the trace context of the span that created the process, see [`traceparent`],
+//!   so the process can continue the trace, e.g., in the `TRACEPARENT` env of the guest.
+//! - The process relays the measurements of its metrics to the shim with [`relay_metrics`],
+//!   over a socket bound by the shim with [`MetricsRelay`], and the shim records them in its own
+//!   meter provider, which exports them.
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{File, Permissions};
+use std::io::Write as _;
+use std::mem::{Discriminant, discriminant};
+use std::os::fd::AsRawFd as _;
+use std::os::unix::fs::PermissionsExt as _;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Duration;
+
+use opentelemetry::global;
+use opentelemetry::metrics::noop::NoopRegistration;
+use opentelemetry::metrics::{
+    CallbackRegistration, Counter, Histogram, InstrumentProvider, Meter, MeterProvider, Observer,
+    Result as MetricsResult, SyncCounter, SyncHistogram, SyncUpDownCounter, Unit, UpDownCounter,
+};
+use opentelemetry::{Key, KeyValue, Value as AttributeValue};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt as _, AsyncRead, BufReader};
+use tokio::net::UnixListener;
+use tokio::task::JoinHandle;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+use super::shim::otel_traces_enabled;
+
+/// How many measurements are buffered in the container process before they're dropped.
+const RELAY_CAPACITY: usize = 4096;
+
+/// How long the container process waits for the buffered measurements to be sent on exit.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returns `true` if the shim exports telemetry.
+pub fn enabled() -> bool {
+    otel_traces_enabled()
+}
+
+/// Returns the W3C `traceparent` of the current span, if it's being traced.
+pub fn traceparent() -> Option<String> {
+    let mut injector = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut injector)
+    });
+    injector.remove("traceparent")
+}
+
+/// A measurement of an instrument, as sent by the container process.
+#[derive(Serialize, Deserialize)]
+struct Measurement<I> {
+    instrument: I,
+    value: MeasurementValue,
+    attributes: Vec<(String, Attribute)>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
+struct Instrument {
+    meter: String,
+    name: String,
+    description: Option<String>,
+    unit: Option<String>,
+}
+
+/// The value of a measurement, with the kind of instrument it was recorded with.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+enum MeasurementValue {
+    U64Counter(u64),
+    F64Counter(f64),
+    I64UpDownCounter(i64),
+    F64UpDownCounter(f64),
+    U64Histogram(u64),
+    F64Histogram(f64),
+}
+
+#[derive(Serialize, Deserialize)]
+enum Attribute {
+    Bool(bool),
+    I64(i64),
+    F64(f64),
+    String(String),
+}
+
+impl From<&AttributeValue> for Attribute {
+    fn from(value: &AttributeValue) -> Self {
+        match value {
+            AttributeValue::Bool(v) => Self::Bool(*v),
+            AttributeValue::I64(v) => Self::I64(*v),
+            AttributeValue::F64(v) => Self::F64(*v),
+            // arrays are flattened to their string representation
+            v => Self::String(v.as_str().into_owned()),
+        }
+    }
+}
+
+impl From<Attribute> for AttributeValue {
+    fn from(value: Attribute) -> Self {
+        match value {
+            Attribute::Bool(v) => v.into(),
+            Attribute::I64(v) => v.into(),
+            Attribute::F64(v) => v.into(),
+            Attribute::String(v) => v.into(),
+        }
+    }
+}
+
+enum Message {
+    Measurement(Measurement<Arc<Instrument>>),
+    /// Acknowledged once the preceding measurements were sent.
+    Flush(mpsc::Sender<()>),
+}
+
+/// Relays the metrics recorded in the container process to the shim over `stream`,
+/// by setting the global meter provider.
+///
+/// Measurements are sent in the background, and dropped if the shim doesn't keep up.
+/// The returned guard sends the pending measurements when dropped, as `exit` doesn't
+/// run destructors.
+pub fn relay_metrics(stream: UnixStream) -> impl Drop {
+    let (sender, receiver) = mpsc::sync_channel(RELAY_CAPACITY);
+    std::thread::spawn(move || send_measurements(stream, receiver));
+    global::set_meter_provider(RelayMeterProvider(sender.clone()));
+    FlushGuard(sender)
+}
+
+fn send_measurements(mut stream: UnixStream, receiver: Receiver<Message>) {
+    let mut line = Vec::new();
+    for message in receiver {
+        match message {
+            Message::Measurement(Measurement {
+                instrument,
+                value,
+                attributes,
+            }) => {
+                line.clear();
+                let measurement = Measurement {
+                    instrument: &*instrument,
+                    value,
+                    attributes,
+                };
+                if serde_json::to_writer(&mut line, &measurement).is_err() {
+                    continue;
+                }
+                line.push(b'\n');
+                if let Err(err) = stream.write_all(&line) {
+                    log::warn!("failed to relay metrics to the shim: {err}");
+                    return;
+                }
+            }
+            Message::Flush(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+#[must_use]
+struct FlushGuard(SyncSender<Message>);
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        let (ack, flushed) = mpsc::channel();
+        if self.0.send(Message::Flush(ack)).is_ok() {
+            let _ = flushed.recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+struct RelayMeterProvider(SyncSender<Message>);
+
+impl MeterProvider for RelayMeterProvider {
+    fn versioned_meter(
+        &self,
+        name: impl Into<Cow<'static, str>>,
+        _version: Option<impl Into<Cow<'static, str>>>,
+        _schema_url: Option<impl Into<Cow<'static, str>>>,
+        _attributes: Option<Vec<KeyValue>>,
+    ) -> Meter {
+        Meter::new(Arc::new(RelayInstrumentProvider {
+            meter: name.into().into_owned(),
+            sender: self.0.clone(),
+        }))
+    }
+}
+
+/// Creates the instruments of a meter, which send their measurements to the shim.
+/// Observable instruments and gauges aren't relayed.
+struct RelayInstrumentProvider {
+    meter: String,
+    sender: SyncSender<Message>,
+}
+
+impl RelayInstrumentProvider {
+    fn instrument(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> Arc<RelayInstrument> {
+        Arc::new(RelayInstrument {
+            instrument: Arc::new(Instrument {
+                meter: self.meter.clone(),
+                name: name.into_owned(),
+                description: description.map(Cow::into_owned),
+                unit: unit.map(|unit| unit.as_str().to_owned()),
+            }),
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+impl InstrumentProvider for RelayInstrumentProvider {
+    fn u64_counter(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<Counter<u64>> {
+        Ok(Counter::new(self.instrument(name, description, unit)))
+    }
+
+    fn f64_counter(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<Counter<f64>> {
+        Ok(Counter::new(self.instrument(name, description, unit)))
+    }
+
+    fn i64_up_down_counter(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<UpDownCounter<i64>> {
+        Ok(UpDownCounter::new(self.instrument(name, description, unit)))
+    }
+
+    fn f64_up_down_counter(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<UpDownCounter<f64>> {
+        Ok(UpDownCounter::new(self.instrument(name, description, unit)))
+    }
+
+    fn u64_histogram(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<Histogram<u64>> {
+        Ok(Histogram::new(self.instrument(name, description, unit)))
+    }
+
+    fn f64_histogram(
+        &self,
+        name: Cow<'static, str>,
+        description: Option<Cow<'static, str>>,
+        unit: Option<Unit>,
+    ) -> MetricsResult<Histogram<f64>> {
+        Ok(Histogram::new(self.instrument(name, description, unit)))
+    }
+
+    fn register_callback(
+        &self,
+        _instruments: &[Arc<dyn Any>],
+        _callback: Box<dyn Fn(&dyn Observer) + Send + Sync>,
+    ) -> MetricsResult<Box<dyn CallbackRegistration>> {
+        Ok(Box::new(NoopRegistration::new()))
+    }
+}
+
+struct RelayInstrument {
+    instrument: Arc<Instrument>,
+    sender: SyncSender<Message>,
+}
+
+impl RelayInstrument {
+    fn send(&self, value: MeasurementValue, attributes: &[KeyValue]) {
+        let measurement = Measurement {
+            instrument: self.instrument.clone(),
+            value,
+            attributes: attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), (&kv.value).into()))
+                .collect(),
+        };
+        match self.sender.try_send(Message::Measurement(measurement)) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => log::debug!("dropping a measurement, the relay is full"),
+        }
+    }
+}
+
+impl SyncCounter<u64> for RelayInstrument {
+    fn add(&self, value: u64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::U64Counter(value), attributes)
+    }
+}
+
+impl SyncCounter<f64> for RelayInstrument {
+    fn add(&self, value: f64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::F64Counter(value), attributes)
+    }
+}
+
+impl SyncUpDownCounter<i64> for RelayInstrument {
+    fn add(&self, value: i64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::I64UpDownCounter(value), attributes)
+    }
+}
+
+impl SyncUpDownCounter<f64> for RelayInstrument {
+    fn add(&self, value: f64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::F64UpDownCounter(value), attributes)
+    }
+}
+
+impl SyncHistogram<u64> for RelayInstrument {
+    fn record(&self, value: u64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::U64Histogram(value), attributes)
+    }
+}
+
+impl SyncHistogram<f64> for RelayInstrument {
+    fn record(&self, value: f64, attributes: &[KeyValue]) {
+        self.send(MeasurementValue::F64Histogram(value), attributes)
+    }
+}
+
+/// A socket where container processes relay their metrics to the shim, see [`relay_metrics`].
+///
+/// The measurements are recorded with the global meter provider of the shim.
+/// The socket is removed when this is dropped, while the processes already connected
+/// keep relaying their metrics until they exit.
+pub struct MetricsRelay {
+    path: String,
+    _dir: File,
+    accept: JoinHandle<()>,
+}
+
+impl MetricsRelay {
+    /// Binds the socket `name` in `dir`, which must be called within a tokio runtime.
+    pub fn bind(dir: impl AsRef<Path>, name: &str) -> std::io::Result<Self> {
+        let dir = File::open(dir)?;
+        let path = socket_path(&dir, name);
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+        let accept = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(record_measurements(stream, global::meter_provider()));
+                    }
+                    Err(err) => {
+                        log::warn!("failed to accept a metrics connection: {err}");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            path,
+            _dir: dir,
+            accept,
+        })
+    }
+
+    /// Connects to the socket `name` in `dir`, from the container process.
+    pub fn connect(dir: impl AsRef<Path>, name: &str) -> std::io::Result<UnixStream> {
+        let dir = File::open(dir)?;
+        UnixStream::connect(socket_path(&dir, name))
+    }
+}
+
+/// The path of the socket `name` in `dir`, through the file descriptor of `dir`.
+/// The path to the bundle of a container can easily exceed the maximum length of a unix socket path.
+fn socket_path(dir: &File, name: &str) -> String {
+    format!("/proc/self/fd/{}/{name}", dir.as_raw_fd())
+}
+
+impl Drop for MetricsRelay {
+    fn drop(&mut self) {
+        self.accept.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// An instrument of the shim, recording the measurements relayed for an instrument
+/// of the container process.
+enum Recorder {
+    U64Counter(Counter<u64>),
+    F64Counter(Counter<f64>),
+    I64UpDownCounter(UpDownCounter<i64>),
+    F64UpDownCounter(UpDownCounter<f64>),
+    U64Histogram(Histogram<u64>),
+    F64Histogram(Histogram<f64>),
+}
+
+impl Recorder {
+    fn new(meter: &Meter, instrument: Instrument, value: MeasurementValue) -> Self {
+        let Instrument {
+            name,
+            description,
+            unit,
+            ..
+        } = instrument;
+        macro_rules! build {
+            ($variant:ident, $builder:ident) => {{
+                let mut builder = meter.$builder(name);
+                if let Some(description) = description {
+                    builder = builder.with_description(description);
+                }
+                if let Some(unit) = unit {
+                    builder = builder.with_unit(Unit::new(unit));
+                }
+                Self::$variant(builder.init())
+            }};
+        }
+        match value {
+            MeasurementValue::U64Counter(_) => build!(U64Counter, u64_counter),
+            MeasurementValue::F64Counter(_) => build!(F64Counter, f64_counter),
+            MeasurementValue::I64UpDownCounter(_) => build!(I64UpDownCounter, i64_up_down_counter),
+            MeasurementValue::F64UpDownCounter(_) => build!(F64UpDownCounter, f64_up_down_counter),
+            MeasurementValue::U64Histogram(_) => build!(U64Histogram, u64_histogram),
+            MeasurementValue::F64Histogram(_) => build!(F64Histogram, f64_histogram),
+        }
+    }
+
+    fn record(&self, value: MeasurementValue, attributes: &[KeyValue]) {
+        use MeasurementValue as V;
+        match (self, value) {
+            (Self::U64Counter(i), V::U64Counter(v)) => i.add(v, attributes),
+            (Self::F64Counter(i), V::F64Counter(v)) => i.add(v, attributes),
+            (Self::I64UpDownCounter(i), V::I64UpDownCounter(v)) => i.add(v, attributes),
+            (Self::F64UpDownCounter(i), V::F64UpDownCounter(v)) => i.add(v, attributes),
+            (Self::U64Histogram(i), V::U64Histogram(v)) => i.record(v, attributes),
+            (Self::F64Histogram(i), V::F64Histogram(v)) => i.record(v, attributes),
+            _ => unreachable!("recorders are keyed by the kind of their measurements"),
+        }
+    }
+}
+
+/// Records the measurements relayed over `stream` with the meters of `provider`,
+/// until the container process closes it.
+async fn record_measurements(stream: impl AsyncRead + Unpin, provider: impl MeterProvider) {
+    let mut meters = HashMap::<String, Meter>::new();
+    let mut recorders =
+        HashMap::<(String, String, Discriminant<MeasurementValue>), Recorder>::new();
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(err) => {
+                log::warn!("failed to read relayed metrics: {err}");
+                return;
+            }
+        };
+        let measurement: Measurement<Instrument> = match serde_json::from_str(&line) {
+            Ok(measurement) => measurement,
+            Err(err) => {
+                log::warn!("ignoring an invalid relayed measurement: {err}");
+                continue;
+            }
+        };
+        let Measurement {
+            instrument,
+            value,
+            attributes,
+        } = measurement;
+        let key = (
+            instrument.meter.clone(),
+            instrument.name.clone(),
+            discriminant(&value),
+        );
+        let recorder = recorders.entry(key).or_insert_with(|| {
+            let meter = meters
+                .entry(instrument.meter.clone())
+                .or_insert_with(|| provider.meter(instrument.meter.clone()));
+            Recorder::new(meter, instrument, value)
+        });
+        let attributes: Vec<_> = attributes
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(Key::new(key), AttributeValue::from(value)))
+            .collect();
+        recorder.record(value, &attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Weak;
+
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::metrics::data::{
+        Histogram as HistogramData, ResourceMetrics, Sum, Temporality,
+    };
+    use opentelemetry_sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry_sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+    };
+
+    use super::*;
+
+    /// A [`ManualReader`] that can be collected after it's registered with a provider.
+    #[derive(Clone, Debug)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl AggregationSelector for SharedReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl TemporalitySelector for SharedReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_metrics() {
+        let (container, shim) = UnixStream::pair().unwrap();
+
+        // container side
+        let (sender, receiver) = mpsc::sync_channel(RELAY_CAPACITY);
+        let sending = std::thread::spawn(move || send_measurements(container, receiver));
+        let meter = RelayMeterProvider(sender.clone()).meter("test");
+        let duration = meter
+            .f64_histogram("request.duration")
+            .with_description("duration of the requests")
+            .with_unit(Unit::new("s"))
+            .init();
+        let active = meter.i64_up_down_counter("active_requests").init();
+        let attributes = [KeyValue::new("method", "GET"), KeyValue::new("status", 200)];
+        duration.record(0.5, &attributes);
+        duration.record(1.5, &attributes);
+        active.add(1, &[]);
+        active.add(1, &[]);
+        active.add(-1, &[]);
+        drop(FlushGuard(sender));
+        drop((meter, duration, active));
+        sending.join().unwrap();
+
+        // shim side
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        shim.set_nonblocking(true).unwrap();
+        let shim = tokio::net::UnixStream::from_std(shim).unwrap();
+        record_measurements(shim, provider.clone()).await;
+
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: vec![],
+        };
+        reader.0.collect(&mut metrics).unwrap();
+        let scope = &metrics.scope_metrics[0];
+        assert_eq!(scope.scope.name, "test");
+
+        let duration = scope
+            .metrics
+            .iter()
+            .find(|m| m.name == "request.duration")
+            .unwrap();
+        assert_eq!(duration.description, "duration of the requests");
+        assert_eq!(duration.unit.as_str(), "s");
+        let data = duration
+            .data
+            .as_any()
+            .downcast_ref::<HistogramData<f64>>()
+            .unwrap();
+        let point = &data.data_points[0];
+        assert_eq!(point.count, 2);
+        assert_eq!(point.sum, 2.0);
+        let mut recorded: Vec<_> = point
+            .attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        recorded.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(recorded, attributes);
+
+        let active = scope
+            .metrics
+            .iter()
+            .find(|m| m.name == "active_requests")
+            .unwrap();
+        let data = active.data.as_any().downcast_ref::<Sum<i64>>().unwrap();
+        assert_eq!(data.data_points[0].value, 1);
+    }
+}
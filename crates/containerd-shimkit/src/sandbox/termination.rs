@@ -0,0 +1,123 @@
+//! Structured reasons for the termination of an instance.
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// Why the process of an instance terminated.
+///
+/// The exit status alone can't tell a guest trap from the process being killed,
+/// so instances can report this alongside it (see [`Instance::termination`](super::Instance::termination)).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Termination {
+    /// The entrypoint returned with the given exit code.
+    Exited { code: i32 },
+    /// The guest exited by calling `proc_exit` with the given exit code.
+    ProcExit { code: i32 },
+    /// The guest trapped, e.g., by executing an `unreachable` instruction.
+    Trap {
+        trap: String,
+        backtrace: Option<String>,
+    },
+    /// The guest exceeded one of its limits, e.g., a resource limit enforced by the engine.
+    LimitExceeded { limit: String },
+    /// The guest didn't finish before its execution deadline.
+    DeadlineExceeded,
+    /// The engine failed to run the guest.
+    EngineError { message: String },
+    /// The process was killed by the given signal.
+    Signaled { signal: i32 },
+}
+
+impl Termination {
+    /// The exit status of the process terminating for this reason.
+    ///
+    /// Traps exit with the status of a process aborted with `SIGABRT`, like the
+    /// `wasmtime` CLI does, so that they can't be mistaken for a `SIGKILL`.
+    /// Exceeding the execution deadline exits with `124`, like `timeout(1)`.
+    pub fn exit_code(&self) -> u32 {
+        match self {
+            Termination::Exited { code } | Termination::ProcExit { code } => (code & 0xff) as u32,
+            Termination::Trap { .. } => 128 + 6,
+            Termination::DeadlineExceeded => 124,
+            Termination::LimitExceeded { .. } | Termination::EngineError { .. } => 137,
+            Termination::Signaled { signal } => 128 + *signal as u32,
+        }
+    }
+
+    /// The kind of termination, e.g., `trap`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Termination::Exited { .. } => "exited",
+            Termination::ProcExit { .. } => "proc_exit",
+            Termination::Trap { .. } => "trap",
+            Termination::LimitExceeded { .. } => "limit_exceeded",
+            Termination::DeadlineExceeded => "deadline_exceeded",
+            Termination::EngineError { .. } => "engine_error",
+            Termination::Signaled { .. } => "signaled",
+        }
+    }
+
+    /// Whether the guest terminated on its own with a zero exit code.
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self,
+            Termination::Exited { code: 0 } | Termination::ProcExit { code: 0 }
+        )
+    }
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Termination::Exited { code } => write!(f, "exited with code {code}"),
+            Termination::ProcExit { code } => write!(f, "called proc_exit with code {code}"),
+            Termination::Trap { trap, backtrace } => {
+                write!(f, "wasm trap: {trap}")?;
+                if let Some(backtrace) = backtrace {
+                    write!(f, "\n{backtrace}")?;
+                }
+                Ok(())
+            }
+            Termination::LimitExceeded { limit } => write!(f, "{limit} limit exceeded"),
+            Termination::DeadlineExceeded => write!(f, "execution deadline exceeded"),
+            Termination::EngineError { message } => write!(f, "engine error: {message}"),
+            Termination::Signaled { signal } => write!(f, "killed by signal {signal}"),
+        }
+    }
+}
+
+impl std::error::Error for Termination {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(Termination::Exited { code: 0 }.exit_code(), 0);
+        assert_eq!(Termination::ProcExit { code: 257 }.exit_code(), 1);
+        let trap = Termination::Trap {
+            trap: "wasm `unreachable` instruction executed".to_string(),
+            backtrace: None,
+        };
+        assert_eq!(trap.exit_code(), 134);
+        assert_eq!(Termination::DeadlineExceeded.exit_code(), 124);
+        assert_eq!(Termination::Signaled { signal: 9 }.exit_code(), 137);
+    }
+
+    #[test]
+    fn test_serialization() -> anyhow::Result<()> {
+        let termination = Termination::LimitExceeded {
+            limit: "fuel".to_string(),
+        };
+        let json = serde_json::to_string(&termination)?;
+        assert_eq!(json, r#"{"kind":"limit_exceeded","limit":"fuel"}"#);
+        assert_eq!(serde_json::from_str::<Termination>(&json)?, termination);
+
+        let json = serde_json::to_string(&Termination::DeadlineExceeded)?;
+        assert_eq!(json, r#"{"kind":"deadline_exceeded"}"#);
+        Ok(())
+    }
+}
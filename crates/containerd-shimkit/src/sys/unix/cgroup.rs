@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use libcgroups::common::{
+    AnyCgroupManager, CgroupConfig, CgroupManager as _, CgroupSetup, ControllerOpt, FreezerState,
+    create_cgroup_manager, get_cgroup_setup,
+};
+use oci_spec::runtime::LinuxResources;
+
+use crate::sandbox::Error;
+
+/// Freeze or thaw the cgroup of a process, with the cgroup freezer.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+pub fn freeze(pid: u32, frozen: bool) -> Result<(), Error> {
+    let manager = cgroup_manager(pid)?;
+    let state = match frozen {
+        true => FreezerState::Frozen,
+        false => FreezerState::Thawed,
+    };
+    manager.freeze(state).context("failed to freeze cgroup")?;
+    Ok(())
+}
+
+/// Apply the resource limits to the cgroup of a process.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+pub fn update(pid: u32, resources: &LinuxResources) -> Result<(), Error> {
+    let manager = cgroup_manager(pid)?;
+    manager
+        .apply(&ControllerOpt {
+            resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        })
+        .context("failed to update cgroup")?;
+    Ok(())
+}
+
+// The cgroup of the container is the one of its freezer, which is in the same place
+// as its other controllers on cgroup v1.
+fn cgroup_manager(pid: u32) -> Result<AnyCgroupManager, Error> {
+    let setup = get_cgroup_setup().context("failed to detect the cgroup setup")?;
+    let unified = matches!(setup, CgroupSetup::Unified);
+    let cgroup_path = freezer_cgroup_path(pid, unified)?;
+
+    // Changing the cgroup of the shim would change the shim too, e.g., freezing it so that it could never thaw it
+    if cgroup_path == freezer_cgroup_path(std::process::id(), unified)? {
+        return Err(Error::FailedPrecondition(format!(
+            "process {pid} is in the cgroup of the shim"
+        )));
+    }
+
+    let manager = create_cgroup_manager(CgroupConfig {
+        cgroup_path,
+        systemd_cgroup: false,
+        container_name: String::new(),
+    })
+    .context("failed to create cgroup manager")?;
+    Ok(manager)
+}
+
+fn freezer_cgroup_path(pid: u32, unified: bool) -> Result<PathBuf> {
+    let content = std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+        .with_context(|| format!("failed to read the cgroup of process {pid}"))?;
+    parse_freezer_cgroup_path(&content, unified)
+        .with_context(|| format!("failed to find the freezer cgroup of process {pid}"))
+}
+
+// Each line of `/proc/<pid>/cgroup` is `hierarchy-id:controllers:path`.
+// The controllers are empty for the cgroup v2 hierarchy.
+fn parse_freezer_cgroup_path(content: &str, unified: bool) -> Option<PathBuf> {
+    content.lines().find_map(|line| {
+        let mut parts = line.splitn(3, ':');
+        let (_id, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+        let is_freezer = match unified {
+            true => controllers.is_empty(),
+            false => controllers.split(',').any(|c| c == "freezer"),
+        };
+        is_freezer.then(|| PathBuf::from(path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_freezer_cgroup_path() {
+        let v2 = "0::/kubepods/pod1234/container\n";
+        assert_eq!(
+            parse_freezer_cgroup_path(v2, true),
+            Some(PathBuf::from("/kubepods/pod1234/container"))
+        );
+
+        let v1 = "12:pids:/default/container\n7:freezer:/default/container\n1:name=systemd:/user.slice\n0::/user.slice\n";
+        assert_eq!(
+            parse_freezer_cgroup_path(v1, false),
+            Some(PathBuf::from("/default/container"))
+        );
+        assert_eq!(
+            parse_freezer_cgroup_path(v1, true),
+            Some(PathBuf::from("/user.slice"))
+        );
+
+        assert_eq!(parse_freezer_cgroup_path("12:pids:/default\n", false), None);
+    }
+
+    #[test]
+    fn test_update_cgroup_of_shim() {
+        let res = update(std::process::id(), &LinuxResources::default());
+        assert!(
+            matches!(res, Err(Error::FailedPrecondition(_))),
+            "unexpected result: {res:?}"
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 use std::sync::LazyLock;
 
+pub mod cgroup;
 pub mod metrics;
+pub mod pty;
 pub mod stdio;
 
 pub static DEFAULT_CONTAINER_ROOT_DIR: LazyLock<PathBuf> =
@@ -0,0 +1,186 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Result, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use nix::fcntl::OFlag;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::pty::{PtyMaster, Winsize, grantpt, posix_openpt, ptsname_r, unlockpt};
+use nix::unistd::pipe2;
+
+use super::stdio::open;
+
+nix::ioctl_write_ptr_bad!(set_window_size, nix::libc::TIOCSWINSZ, Winsize);
+
+/// A pseudo-terminal used as the stdio of a container process.
+///
+/// The process opens the terminal at [`Pty::path`], while the shim keeps the
+/// other end and forwards it to the named pipes from containerd.
+#[derive(Debug)]
+pub struct Pty {
+    master: Arc<PtyMaster>,
+    path: PathBuf,
+    // The shim keeps the terminal open until the process has opened it,
+    // as reading from a terminal that no process has open fails.
+    terminal: Mutex<Option<File>>,
+    // Dropping the write end of this pipe stops the forwarding threads.
+    stop: Mutex<Option<OwnedFd>>,
+    stopped: Arc<OwnedFd>,
+}
+
+impl Pty {
+    pub fn open() -> Result<Self> {
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC)?;
+        grantpt(&master)?;
+        unlockpt(&master)?;
+        let path: PathBuf = ptsname_r(&master)?.into();
+        let terminal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NOCTTY | nix::libc::O_CLOEXEC)
+            .open(&path)?;
+        let (stopped, stop) = pipe2(OFlag::O_CLOEXEC)?;
+        Ok(Self {
+            master: Arc::new(master),
+            path,
+            terminal: Mutex::new(Some(terminal)),
+            stop: Mutex::new(Some(stop)),
+            stopped: Arc::new(stopped),
+        })
+    }
+
+    /// Path to the terminal device to use as the process stdio.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn resize(&self, width: u32, height: u32) -> Result<()> {
+        let size = Winsize {
+            ws_row: height.try_into().unwrap_or(u16::MAX),
+            ws_col: width.try_into().unwrap_or(u16::MAX),
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe { set_window_size(self.master.as_raw_fd(), &size) }?;
+        Ok(())
+    }
+
+    /// Forward `stdin` to the terminal, and the terminal to `stdout`.
+    ///
+    /// This can be called before the process opens the terminal, so that the
+    /// process is only reported as started once its stdio is forwarded.
+    /// The forwarding stops with [`Pty::stop`].
+    pub fn forward(&self, stdin: &Path, stdout: &Path) -> Result<()> {
+        if !stdin.as_os_str().is_empty() {
+            let stdin = open(stdin)?;
+            let master = self.master.clone();
+            let stopped = self.stopped.clone();
+            std::thread::spawn(move || {
+                let _ = forward(&stdin, &mut master.as_ref(), &stopped);
+            });
+        }
+
+        if !stdout.as_os_str().is_empty() {
+            let mut stdout = open(stdout)?;
+            let master = self.master.clone();
+            let stopped = self.stopped.clone();
+            std::thread::spawn(move || forward_output(&master, &mut stdout, &stopped));
+        }
+
+        Ok(())
+    }
+
+    /// Close the shim's own handle to the terminal, once the process has opened it.
+    pub fn release_terminal(&self) {
+        self.terminal.lock().unwrap().take();
+    }
+
+    /// Stop forwarding, once the process has exited.
+    /// The output the process wrote to the terminal is still forwarded.
+    pub fn stop(&self) {
+        self.release_terminal();
+        self.stop.lock().unwrap().take();
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Copy `src` to `dst` until `src` is closed, or `stopped` is ready and `src` has no more data.
+fn forward(src: &impl AsFd, dst: &mut impl Write, stopped: &OwnedFd) -> Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        let mut fds = [
+            PollFd::new(src.as_fd(), PollFlags::POLLIN),
+            PollFd::new(stopped.as_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, PollTimeout::NONE) {
+            Err(nix::errno::Errno::EINTR) => continue,
+            res => res?,
+        };
+        let readable = fds[0].any().unwrap_or(false);
+        let stopped = fds[1].any().unwrap_or(false);
+        if !readable {
+            if stopped {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let n = nix::unistd::read(src.as_fd().as_raw_fd(), &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        dst.write_all(&buf[..n])?;
+    }
+}
+
+fn forward_output(master: &PtyMaster, stdout: &mut File, stopped: &OwnedFd) {
+    match forward(master, stdout, stopped) {
+        // reading from the terminal fails with EIO once the process closes it
+        Err(err) if err.raw_os_error() == Some(nix::libc::EIO) => {}
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+        Err(err) => log::warn!("failed to forward terminal output: {err}"),
+        Ok(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_forward_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stdout = dir.path().join("stdout");
+        File::create(&stdout)?;
+
+        // the output is forwarded even if the process opens the terminal afterwards
+        let pty = Pty::open()?;
+        pty.forward(Path::new(""), &stdout)?;
+
+        let mut terminal = OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::libc::O_NOCTTY)
+            .open(pty.path())?;
+        pty.release_terminal();
+        terminal.write_all(b"hello\n")?;
+        drop(terminal);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !std::fs::read_to_string(&stdout)?.contains("hello") {
+            assert!(Instant::now() < deadline, "output was not forwarded");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        pty.stop();
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+use containerd_shim::Error as ShimError;
+use oci_spec::runtime::LinuxResources;
+
+use crate::sandbox::Error;
+
+/// Windows has no cgroup freezer, so pausing tasks is not supported.
+pub fn freeze(_pid: u32, _frozen: bool) -> Result<(), Error> {
+    Err(ShimError::Unimplemented("pause is not supported".to_string()).into())
+}
+
+/// Windows has no cgroups, so updating the resources of tasks is not supported.
+pub fn update(_pid: u32, _resources: &LinuxResources) -> Result<(), Error> {
+    Err(ShimError::Unimplemented("update is not supported".to_string()).into())
+}
@@ -0,0 +1,33 @@
+use std::convert::Infallible;
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+
+/// Pseudo-terminals are not supported on Windows.
+#[derive(Debug)]
+pub struct Pty(Infallible);
+
+impl Pty {
+    pub fn open() -> Result<Self> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    pub fn path(&self) -> &Path {
+        match self.0 {}
+    }
+
+    pub fn resize(&self, _width: u32, _height: u32) -> Result<()> {
+        match self.0 {}
+    }
+
+    pub fn forward(&self, _stdin: &Path, _stdout: &Path) -> Result<()> {
+        match self.0 {}
+    }
+
+    pub fn release_terminal(&self) {
+        match self.0 {}
+    }
+
+    pub fn stop(&self) {
+        match self.0 {}
+    }
+}